@@ -0,0 +1,555 @@
+use std::rc::Rc;
+
+use crate::common::{
+    bigint::BigInt,
+    interner::{Interner, Str},
+    object::{Capture, Enum, Function, Object, Signature, Variant},
+};
+
+use super::{
+    chunk::{Chunk, PositionRun},
+    instruction::Constant,
+};
+
+/// Every compiled file starts with these bytes.
+const MAGIC: &[u8; 4] = b"INDC";
+/// Changes whenever the instructions or the way they are stored change, since files built
+/// by another version can not be run.
+const FORMAT_VERSION: u16 = 3;
+const HEADER_SIZE: usize = MAGIC.len() + 2 + 8;
+
+/// Serializes a compiled script. After the header, which holds the format version and a
+/// checksum of the rest, integers are LEB128 encoded and strings are prefixed by their
+/// length. An enum is written where it is first referred to and by index after that, so
+/// everything that refers to it still shares it once loaded.
+pub(crate) fn write_chunk(chunk: &Chunk) -> Vec<u8> {
+    let mut writer = Writer {
+        bytes: Vec::new(),
+        enums: Vec::new(),
+    };
+    writer.chunk(chunk);
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + writer.bytes.len());
+    bytes.extend(MAGIC);
+    bytes.extend(FORMAT_VERSION.to_le_bytes());
+    bytes.extend(checksum(&writer.bytes).to_le_bytes());
+    bytes.extend(writer.bytes);
+    bytes
+}
+
+/// Loads a script written by `write_chunk`, interning its strings with `interner`.
+pub(crate) fn read_chunk(bytes: &[u8], interner: &Interner) -> Result<Chunk, String> {
+    if bytes.len() < HEADER_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+        return Err("Invalid bytecode. The file is not a compiled Indu script.".to_string());
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != FORMAT_VERSION {
+        return Err(format!(
+            "Incompatible bytecode. The file was built with format version {version}, but this Indu runs version {FORMAT_VERSION}. Build it again with 'indu build'."
+        ));
+    }
+    let mut expected = [0; 8];
+    expected.copy_from_slice(&bytes[6..HEADER_SIZE]);
+    let payload = &bytes[HEADER_SIZE..];
+    if checksum(payload) != u64::from_le_bytes(expected) {
+        return Err("Corrupted bytecode. The file does not match its checksum.".to_string());
+    }
+
+    let mut reader = Reader {
+        bytes: payload,
+        offset: 0,
+        interner,
+        enums: Vec::new(),
+    };
+    let chunk = reader.chunk()?;
+    if reader.offset != payload.len() {
+        return Err(reader.corrupted());
+    }
+    Ok(chunk)
+}
+
+/// FNV-1a, the same hash strings use.
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+    enums: Vec<Rc<Enum>>,
+}
+
+impl Writer {
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.bytes(&chunk.code);
+
+        self.number(chunk.constants.len());
+        for constant in &chunk.constants {
+            self.constant(constant);
+        }
+
+        // Each run is stored as the difference from the one before it, which is small.
+        self.number(chunk.positions.len());
+        let (mut address, mut start, mut line) = (0, 0, 0);
+        for run in &chunk.positions {
+            self.number((run.address - address) as usize);
+            self.signed(run.start as i64 - start as i64);
+            self.number((run.end - run.start) as usize);
+            self.signed(run.line as i64 - line as i64);
+            self.number(run.column as usize);
+            (address, start, line) = (run.address, run.start, run.line);
+        }
+    }
+
+    fn constant(&mut self, constant: &Constant) {
+        match constant {
+            Constant::Value(object) => {
+                self.bytes.push(0);
+                self.object(object);
+            }
+            Constant::Name(name) => {
+                self.bytes.push(1);
+                self.string(name);
+            }
+            Constant::Enum(enum_type) => {
+                self.bytes.push(2);
+                self.enum_type(enum_type);
+            }
+            Constant::Function(function) => {
+                self.bytes.push(3);
+                self.function(function);
+            }
+            Constant::Class(name, methods) => {
+                self.bytes.push(4);
+                self.string(name);
+                self.number(methods.len());
+                for method in methods {
+                    self.string(method);
+                }
+            }
+            Constant::Keywords(keywords) => {
+                self.bytes.push(5);
+                self.number(keywords.len());
+                for keyword in keywords {
+                    self.string(keyword);
+                }
+            }
+            Constant::Assertion(source, negated) => {
+                self.bytes.push(6);
+                self.string(source);
+                match negated {
+                    Some(negated) => {
+                        self.bytes.push(1);
+                        self.string(negated);
+                    }
+                    None => self.bytes.push(0),
+                }
+            }
+        }
+    }
+
+    /// Only literals and enums end up in the constant table.
+    fn object(&mut self, object: &Object) {
+        match object {
+            Object::Nil => self.bytes.push(0),
+            Object::Boolean(boolean) => {
+                self.bytes.push(1);
+                self.bytes.push(*boolean as u8);
+            }
+            Object::Number(number)
+                if number.fract() == 0. && (0. ..=u32::MAX as f64).contains(number) =>
+            {
+                self.bytes.push(8);
+                self.number(*number as usize);
+            }
+            Object::Number(number) => {
+                self.bytes.push(2);
+                self.bytes.extend(number.to_le_bytes());
+            }
+            Object::BigInt(integer) => {
+                self.bytes.push(3);
+                self.string(&integer.to_string());
+            }
+            Object::String(string) => {
+                self.bytes.push(4);
+                self.string(string);
+            }
+            Object::Enum(enum_type) => {
+                self.bytes.push(5);
+                self.enum_type(enum_type);
+            }
+            Object::Variant(variant) => {
+                self.bytes.push(6);
+                self.enum_type(&variant.enum_type);
+                self.number(variant.index);
+            }
+            Object::Constructor(enum_type, index) => {
+                self.bytes.push(7);
+                self.enum_type(enum_type);
+                self.number(*index);
+            }
+            _ => unreachable!("'{object}' is never a constant"),
+        }
+    }
+
+    fn enum_type(&mut self, enum_type: &Rc<Enum>) {
+        if let Some(index) = self
+            .enums
+            .iter()
+            .position(|known| Rc::ptr_eq(known, enum_type))
+        {
+            self.number(index);
+            return;
+        }
+        self.number(self.enums.len());
+        self.enums.push(enum_type.clone());
+        self.string(&enum_type.name);
+        self.number(enum_type.variants.len());
+        for (name, field_count) in &enum_type.variants {
+            self.string(name);
+            self.number(*field_count);
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.string(&function.name);
+        let signature = &function.signature;
+        self.number(signature.parameters.len());
+        for parameter in &signature.parameters {
+            self.string(parameter);
+        }
+        self.number(signature.required);
+        for default in &signature.defaults {
+            self.string(default);
+        }
+        match &signature.rest {
+            Some(rest) => {
+                self.bytes.push(1);
+                self.string(rest);
+            }
+            None => self.bytes.push(0),
+        }
+        self.chunk(&function.chunk);
+        self.number(function.captures.len());
+        for capture in &function.captures {
+            match capture {
+                Capture::Local(slot) => {
+                    self.bytes.push(0);
+                    self.number(*slot);
+                }
+                Capture::Upvalue(index) => {
+                    self.bytes.push(1);
+                    self.number(*index);
+                }
+            }
+        }
+        self.bytes.push(function.generator as u8);
+    }
+
+    fn number(&mut self, mut number: usize) {
+        while number >= 0x80 {
+            self.bytes.push(number as u8 | 0x80);
+            number >>= 7;
+        }
+        self.bytes.push(number as u8);
+    }
+
+    /// Zigzag encodes a number that may be negative, so small ones of either sign stay short.
+    fn signed(&mut self, number: i64) {
+        self.number(((number << 1) ^ (number >> 63)) as usize);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.number(bytes.len());
+        self.bytes.extend(bytes);
+    }
+
+    fn string(&mut self, string: &str) {
+        self.bytes(string.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    interner: &'a Interner,
+    enums: Vec<Rc<Enum>>,
+}
+
+impl Reader<'_> {
+    fn chunk(&mut self) -> Result<Chunk, String> {
+        let code = self.bytes()?.to_vec();
+
+        let mut constants = Vec::new();
+        for _ in 0..self.number()? {
+            constants.push(self.constant()?);
+        }
+
+        let mut positions = Vec::new();
+        let (mut address, mut start, mut line) = (0u32, 0u32, 0u32);
+        for _ in 0..self.number()? {
+            address = address.wrapping_add(self.number()? as u32);
+            start = (start as i64).wrapping_add(self.signed()?) as u32;
+            let end = start.wrapping_add(self.number()? as u32);
+            line = (line as i64).wrapping_add(self.signed()?) as u32;
+            let column = self.number()? as u32;
+            positions.push(PositionRun {
+                address,
+                start,
+                end,
+                line,
+                column,
+            });
+        }
+
+        Ok(Chunk {
+            code,
+            constants,
+            positions,
+        })
+    }
+
+    fn constant(&mut self) -> Result<Constant, String> {
+        Ok(match self.byte()? {
+            0 => Constant::Value(self.object()?),
+            1 => Constant::Name(self.name()?),
+            2 => Constant::Enum(self.enum_type()?),
+            3 => Constant::Function(Rc::new(self.function()?)),
+            4 => {
+                let name = self.string()?;
+                let mut methods = Vec::new();
+                for _ in 0..self.number()? {
+                    methods.push(self.name()?);
+                }
+                Constant::Class(name, methods)
+            }
+            5 => {
+                let mut keywords = Vec::new();
+                for _ in 0..self.number()? {
+                    keywords.push(self.string()?);
+                }
+                Constant::Keywords(keywords)
+            }
+            6 => {
+                let source = self.string()?;
+                let negated = match self.byte()? {
+                    0 => None,
+                    _ => Some(self.string()?),
+                };
+                Constant::Assertion(source, negated)
+            }
+            _ => return Err(self.corrupted()),
+        })
+    }
+
+    fn object(&mut self) -> Result<Object, String> {
+        let tag = self.byte()?;
+        Ok(match tag {
+            0 => Object::Nil,
+            1 => Object::Boolean(self.byte()? != 0),
+            2 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                Object::Number(f64::from_le_bytes(bytes))
+            }
+            3 => {
+                let integer = BigInt::parse(&self.string()?).ok_or_else(|| self.corrupted())?;
+                Object::BigInt(Rc::new(integer))
+            }
+            4 => Object::String(self.name()?),
+            8 => Object::Number(self.number()? as f64),
+            5 => Object::Enum(self.enum_type()?),
+            6 | 7 => {
+                let enum_type = self.enum_type()?;
+                let index = self.number()?;
+                let Some((_, field_count)) = enum_type.variants.get(index) else {
+                    return Err(self.corrupted());
+                };
+                if tag == 6 {
+                    // Only variants without fields are constants, the rest are built at runtime.
+                    if *field_count != 0 {
+                        return Err(self.corrupted());
+                    }
+                    Object::Variant(Rc::new(Variant::new(enum_type, index, Vec::new())))
+                } else {
+                    Object::Constructor(enum_type, index)
+                }
+            }
+            _ => return Err(self.corrupted()),
+        })
+    }
+
+    fn enum_type(&mut self) -> Result<Rc<Enum>, String> {
+        let index = self.number()?;
+        if let Some(enum_type) = self.enums.get(index) {
+            return Ok(enum_type.clone());
+        }
+        if index != self.enums.len() {
+            return Err(self.corrupted());
+        }
+        let name = self.string()?;
+        let mut variants = Vec::new();
+        for _ in 0..self.number()? {
+            variants.push((self.string()?, self.number()?));
+        }
+        let enum_type = Rc::new(Enum::new(name, variants));
+        self.enums.push(enum_type.clone());
+        Ok(enum_type)
+    }
+
+    fn function(&mut self) -> Result<Function, String> {
+        let name = self.string()?;
+        let mut parameters = Vec::new();
+        for _ in 0..self.number()? {
+            parameters.push(self.string()?);
+        }
+        let required = self.number()?;
+        if required > parameters.len() {
+            return Err(self.corrupted());
+        }
+        let mut defaults = Vec::new();
+        for _ in required..parameters.len() {
+            defaults.push(self.string()?);
+        }
+        let rest = match self.byte()? {
+            0 => None,
+            _ => Some(self.string()?),
+        };
+        let chunk = self.chunk()?;
+        let mut captures = Vec::new();
+        for _ in 0..self.number()? {
+            captures.push(match self.byte()? {
+                0 => Capture::Local(self.number()?),
+                1 => Capture::Upvalue(self.number()?),
+                _ => return Err(self.corrupted()),
+            });
+        }
+        let generator = self.byte()? != 0;
+        Ok(Function::new(
+            name,
+            Signature::new(parameters, required, defaults, rest),
+            chunk,
+            captures,
+            generator,
+        ))
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn number(&mut self) -> Result<usize, String> {
+        let mut number = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= usize::BITS {
+                return Err(self.corrupted());
+            }
+            number |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(number);
+            }
+            shift += 7;
+        }
+    }
+
+    fn signed(&mut self) -> Result<i64, String> {
+        let number = self.number()? as i64;
+        Ok((number >> 1) ^ -(number & 1))
+    }
+
+    fn take(&mut self, length: usize) -> Result<&[u8], String> {
+        if length > self.bytes.len() - self.offset {
+            return Err(self.corrupted());
+        }
+        self.offset += length;
+        Ok(&self.bytes[self.offset - length..self.offset])
+    }
+
+    fn bytes(&mut self) -> Result<&[u8], String> {
+        let length = self.number()?;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let bytes = self.bytes()?.to_vec();
+        String::from_utf8(bytes).map_err(|_| self.corrupted())
+    }
+
+    fn name(&mut self) -> Result<Str, String> {
+        let string = self.string()?;
+        Ok(self.interner.intern(&string))
+    }
+
+    fn corrupted(&self) -> String {
+        format!(
+            "Corrupted bytecode. The file can not be read past byte {}.",
+            HEADER_SIZE + self.offset
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        backend::{chunk::Chunk, instruction::Constant},
+        common::{
+            interner::Interner,
+            object::{Object, Variant},
+        },
+        compile_source,
+    };
+
+    use super::{read_chunk, write_chunk};
+
+    const SOURCE: &str = "enum E { A, B(x) }\nvar v = E.A\nif v is E.B(x) { print x }\n";
+
+    /// Points the constant for `E.A` at `E.B`, as a file with a changed variant index and a
+    /// recomputed checksum would.
+    fn with_variant_index(chunk: &mut Chunk, index: usize) {
+        let constant = chunk
+            .constants
+            .iter_mut()
+            .find(|constant| matches!(constant, Constant::Value(Object::Variant(_))))
+            .unwrap();
+        let Constant::Value(Object::Variant(variant)) = constant else {
+            unreachable!()
+        };
+        let variant = Variant::new(variant.enum_type.clone(), index, Vec::new());
+        *constant = Constant::Value(Object::Variant(Rc::new(variant)));
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let chunk = compile_source(SOURCE).unwrap();
+        let bytes = write_chunk(&chunk);
+        let read = read_chunk(&bytes, &Interner::default()).unwrap();
+        assert_eq!(write_chunk(&read), bytes);
+    }
+
+    #[test]
+    fn rejects_a_constant_variant_with_fields() {
+        let mut chunk = compile_source(SOURCE).unwrap();
+        with_variant_index(&mut chunk, 1);
+        let error = read_chunk(&write_chunk(&chunk), &Interner::default()).err();
+        assert!(error.unwrap().starts_with("Corrupted bytecode."));
+    }
+
+    #[test]
+    fn rejects_a_changed_byte() {
+        let mut bytes = write_chunk(&compile_source(SOURCE).unwrap());
+        *bytes.last_mut().unwrap() ^= 1;
+        let error = read_chunk(&bytes, &Interner::default()).err();
+        assert!(error.unwrap().contains("does not match its checksum"));
+    }
+
+    #[test]
+    fn rejects_a_truncated_file() {
+        let bytes = write_chunk(&compile_source(SOURCE).unwrap());
+        assert!(read_chunk(&bytes[..10], &Interner::default()).is_err());
+    }
+}
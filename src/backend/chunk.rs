@@ -1,110 +1,530 @@
-use std::fmt::Display;
+use crate::common::{interner::Str, position::Position};
 
-use crate::common::position::Position;
-
-use super::instruction::Instruction;
+use super::instruction::{Constant, Instruction, OpCode};
 
+/// Compiled code: a stream of encoded instructions, the constants they refer to and where
+/// in the source each instruction came from.
 #[derive(Clone)]
 pub(crate) struct Chunk {
-    instructions: Vec<Instruction>,
-    positions: Vec<Position>,
+    pub(super) code: Vec<u8>,
+    pub(super) constants: Vec<Constant>,
+    /// Sorted by address. Instructions in a row with the same position share a run.
+    pub(super) positions: Vec<PositionRun>,
+}
+
+/// The position of every instruction from `address` up to the address of the next run.
+#[derive(Clone)]
+pub(super) struct PositionRun {
+    pub(super) address: u32,
+    pub(super) start: u32,
+    pub(super) end: u32,
+    pub(super) line: u32,
+    pub(super) column: u32,
+}
+
+impl PositionRun {
+    fn position(&self) -> Position {
+        Position::new(
+            self.start as usize,
+            self.end as usize,
+            self.line as usize,
+            self.column as usize,
+        )
+    }
+}
+
+enum Operand {
+    Number(usize),
+    Address(usize),
+    Constant(Constant),
 }
 
 impl Chunk {
     pub(crate) fn new() -> Self {
         Self {
-            instructions: Vec::new(),
+            code: Vec::new(),
+            constants: Vec::new(),
             positions: Vec::new(),
         }
     }
 
+    /// Appends an instruction and returns its address.
     pub(crate) fn add_instruction(
         &mut self,
         instruction: Instruction,
         position: Position,
     ) -> usize {
-        self.instructions.push(instruction);
-        self.positions.push(position);
-        self.instructions.len() - 1
+        use Operand::{Address, Number};
+
+        let (opcode, operands) = match instruction {
+            Instruction::Return => (OpCode::Return, vec![]),
+            Instruction::Yield => (OpCode::Yield, vec![]),
+            Instruction::Print => (OpCode::Print, vec![]),
+
+            Instruction::Push(object) => (
+                OpCode::Push,
+                vec![Operand::Constant(Constant::Value(object))],
+            ),
+            Instruction::Pop => (OpCode::Pop, vec![]),
+            Instruction::Duplicate(count) => (OpCode::Duplicate, vec![Number(count)]),
+            Instruction::BuildList(length) => (OpCode::BuildList, vec![Number(length)]),
+            Instruction::BuildRange(inclusive) => {
+                (OpCode::BuildRange, vec![Number(inclusive as usize)])
+            }
+
+            Instruction::DefineGlobal(name) => (
+                OpCode::DefineGlobal,
+                vec![Operand::Constant(Constant::Name(name))],
+            ),
+            Instruction::GetGlobal(name) => (
+                OpCode::GetGlobal,
+                vec![Operand::Constant(Constant::Name(name))],
+            ),
+            Instruction::SetGlobal(name) => (
+                OpCode::SetGlobal,
+                vec![Operand::Constant(Constant::Name(name))],
+            ),
+            Instruction::GetLocal(slot) => (OpCode::GetLocal, vec![Number(slot)]),
+            Instruction::SetLocal(slot) => (OpCode::SetLocal, vec![Number(slot)]),
+            Instruction::BoxLocal(slot) => (OpCode::BoxLocal, vec![Number(slot)]),
+            Instruction::GetCell(slot) => (OpCode::GetCell, vec![Number(slot)]),
+            Instruction::SetCell(slot) => (OpCode::SetCell, vec![Number(slot)]),
+            Instruction::GetUpvalue(index) => (OpCode::GetUpvalue, vec![Number(index)]),
+            Instruction::SetUpvalue(index) => (OpCode::SetUpvalue, vec![Number(index)]),
+            Instruction::GetProperty(name) => (
+                OpCode::GetProperty,
+                vec![Operand::Constant(Constant::Name(name))],
+            ),
+            Instruction::SetProperty(name) => (
+                OpCode::SetProperty,
+                vec![Operand::Constant(Constant::Name(name))],
+            ),
+
+            Instruction::Identify => (OpCode::Identify, vec![]),
+            Instruction::Negate => (OpCode::Negate, vec![]),
+            Instruction::Not => (OpCode::Not, vec![]),
+
+            Instruction::Add => (OpCode::Add, vec![]),
+            Instruction::Subtract => (OpCode::Subtract, vec![]),
+            Instruction::Multiply => (OpCode::Multiply, vec![]),
+            Instruction::Divide => (OpCode::Divide, vec![]),
+            Instruction::Modulo => (OpCode::Modulo, vec![]),
+
+            Instruction::Equal => (OpCode::Equal, vec![]),
+            Instruction::NotEqual => (OpCode::NotEqual, vec![]),
+            Instruction::Greater => (OpCode::Greater, vec![]),
+            Instruction::GreaterEqual => (OpCode::GreaterEqual, vec![]),
+            Instruction::Lesser => (OpCode::Lesser, vec![]),
+            Instruction::LesserEqual => (OpCode::LesserEqual, vec![]),
+
+            Instruction::And => (OpCode::And, vec![]),
+            Instruction::Or => (OpCode::Or, vec![]),
+
+            Instruction::Jump(ip) => (OpCode::Jump, vec![Address(ip)]),
+            Instruction::JumpIfFalse(ip) => (OpCode::JumpIfFalse, vec![Address(ip)]),
+            Instruction::JumpIfNil(ip) => (OpCode::JumpIfNil, vec![Address(ip)]),
+
+            Instruction::TestVariant(enum_type, index) => (
+                OpCode::TestVariant,
+                vec![Operand::Constant(Constant::Enum(enum_type)), Number(index)],
+            ),
+            Instruction::UnpackVariant => (OpCode::UnpackVariant, vec![]),
+
+            Instruction::GetIterator => (OpCode::GetIterator, vec![]),
+            Instruction::IterateNext(ip) => (OpCode::IterateNext, vec![Address(ip)]),
+
+            Instruction::Closure(function) => (
+                OpCode::Closure,
+                vec![Operand::Constant(Constant::Function(function))],
+            ),
+            Instruction::BuildClass(name, methods) => (
+                OpCode::BuildClass,
+                vec![Operand::Constant(Constant::Class(name, methods))],
+            ),
+            Instruction::Call(argument_count) => (OpCode::Call, vec![Number(argument_count)]),
+            Instruction::CallWithKeywords(argument_count, keywords) => (
+                OpCode::CallWithKeywords,
+                vec![
+                    Number(argument_count),
+                    Operand::Constant(Constant::Keywords(keywords)),
+                ],
+            ),
+            Instruction::Spawn(argument_count, keywords) => (
+                OpCode::Spawn,
+                vec![
+                    Number(argument_count),
+                    Operand::Constant(Constant::Keywords(keywords)),
+                ],
+            ),
+
+            Instruction::AssertFailed(source, negated) => (
+                OpCode::AssertFailed,
+                vec![Operand::Constant(Constant::Assertion(source, negated))],
+            ),
+
+            Instruction::Continue => (OpCode::Continue, vec![]),
+        };
+
+        let address = self.code.len();
+        self.code.push(opcode as u8);
+        for operand in operands {
+            match operand {
+                Operand::Number(number) => self.write_operand(number),
+                Operand::Address(address) => self.code.extend((address as u32).to_le_bytes()),
+                Operand::Constant(constant) => {
+                    let index = self.add_constant(constant);
+                    self.write_operand(index);
+                }
+            }
+        }
+        if self.positions.last().map(PositionRun::position).as_ref() != Some(&position) {
+            self.positions.push(PositionRun {
+                address: address as u32,
+                start: position.start as u32,
+                end: position.end as u32,
+                line: position.line as u32,
+                column: position.column as u32,
+            });
+        }
+        address
     }
 
-    pub(crate) fn edit_instruction(&mut self, instruction_index: usize, instruction: Instruction) {
-        self.instructions[instruction_index] = instruction;
+    /// Reuses an equal constant already in the table, so a literal or name used over and
+    /// over is stored once.
+    fn add_constant(&mut self, constant: Constant) -> usize {
+        if let Some(index) = self
+            .constants
+            .iter()
+            .position(|existing| existing.is_same_as(&constant))
+        {
+            return index;
+        }
+        self.constants.push(constant);
+        self.constants.len() - 1
     }
 
-    pub(crate) fn get_instruction(&self, instruction_index: usize) -> Instruction {
-        self.instructions[instruction_index].clone()
+    fn write_operand(&mut self, mut operand: usize) {
+        while operand >= 0x80 {
+            self.code.push(operand as u8 | 0x80);
+            operand >>= 7;
+        }
+        self.code.push(operand as u8);
     }
 
-    pub(crate) fn get_position(&self, position_index: usize) -> Position {
-        self.positions[position_index].clone()
+    /// Points the jump at `address` to `target`, once the target is known.
+    pub(crate) fn patch_jump(&mut self, address: usize, target: usize) {
+        self.code[address + 1..address + 5].copy_from_slice(&(target as u32).to_le_bytes());
     }
 
-    #[allow(unused)]
-    pub(crate) fn debug_instruction(&self, instruction_index: usize) {
-        let instruction = self.get_instruction(instruction_index);
-        match instruction {
-            Instruction::Return => self.debug_simple_instruction("return", instruction_index),
-            Instruction::Print => self.debug_simple_instruction("print", instruction_index),
+    pub(crate) fn code(&self) -> &[u8] {
+        &self.code
+    }
 
-            Instruction::Push(object) => {
-                self.debug_complex_instruction("push", instruction_index, object);
-            }
-            Instruction::Pop => self.debug_simple_instruction("pop", instruction_index),
-
-            Instruction::Identify => self.debug_simple_instruction("identify", instruction_index),
-            Instruction::Negate => self.debug_simple_instruction("negate", instruction_index),
-            Instruction::Not => self.debug_simple_instruction("not", instruction_index),
-
-            Instruction::Add => self.debug_simple_instruction("add", instruction_index),
-            Instruction::Subtract => self.debug_simple_instruction("sub", instruction_index),
-            Instruction::Multiply => self.debug_simple_instruction("multiply", instruction_index),
-            Instruction::Divide => self.debug_simple_instruction("divide", instruction_index),
-
-            Instruction::Equal => self.debug_simple_instruction("equal", instruction_index),
-            Instruction::NotEqual => self.debug_simple_instruction("not_equal", instruction_index),
-            Instruction::Greater => self.debug_simple_instruction("greater", instruction_index),
-            Instruction::GreaterEqual => {
-                self.debug_simple_instruction("greater_equal", instruction_index)
-            }
-            Instruction::Lesser => self.debug_simple_instruction("lesser", instruction_index),
-            Instruction::LesserEqual => {
-                self.debug_simple_instruction("lesser_equal", instruction_index)
+    /// Reads the operand at `address`, returning it and the address after it.
+    pub(crate) fn read_operand(&self, mut address: usize) -> (usize, usize) {
+        let mut operand = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.code[address];
+            address += 1;
+            operand |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return (operand, address);
             }
+            shift += 7;
+        }
+    }
+
+    pub(crate) fn read_address(&self, address: usize) -> usize {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.code[address..address + 4]);
+        u32::from_le_bytes(bytes) as usize
+    }
+
+    pub(crate) fn constant(&self, index: usize) -> &Constant {
+        &self.constants[index]
+    }
+
+    /// Decodes the instruction at `address`, returning it and the address of the next one.
+    /// Unlike the virtual machine, which runs verified code, this checks everything it reads.
+    pub(crate) fn get_instruction(&self, address: usize) -> Result<(Instruction, usize), String> {
+        let byte = self.code[address];
+        let opcode = OpCode::from_byte(byte).ok_or_else(|| {
+            format!("Invalid bytecode. '{byte}' at address {address} is not an opcode.")
+        })?;
+        let mut decoder = Decoder {
+            chunk: self,
+            opcode,
+            address,
+            next: address + 1,
+        };
+        let instruction = match opcode {
+            OpCode::Return => Instruction::Return,
+            OpCode::Yield => Instruction::Yield,
+            OpCode::Print => Instruction::Print,
+
+            OpCode::Push => match decoder.constant()? {
+                Constant::Value(object) => Instruction::Push(object.clone()),
+                _ => return Err(decoder.wrong_constant("a value")),
+            },
+            OpCode::Pop => Instruction::Pop,
+            OpCode::Duplicate => Instruction::Duplicate(decoder.operand()?),
+            OpCode::BuildList => Instruction::BuildList(decoder.operand()?),
+            OpCode::BuildRange => Instruction::BuildRange(decoder.operand()? != 0),
+
+            OpCode::DefineGlobal => Instruction::DefineGlobal(decoder.name()?),
+            OpCode::GetGlobal => Instruction::GetGlobal(decoder.name()?),
+            OpCode::SetGlobal => Instruction::SetGlobal(decoder.name()?),
+            OpCode::GetLocal => Instruction::GetLocal(decoder.operand()?),
+            OpCode::SetLocal => Instruction::SetLocal(decoder.operand()?),
+            OpCode::BoxLocal => Instruction::BoxLocal(decoder.operand()?),
+            OpCode::GetCell => Instruction::GetCell(decoder.operand()?),
+            OpCode::SetCell => Instruction::SetCell(decoder.operand()?),
+            OpCode::GetUpvalue => Instruction::GetUpvalue(decoder.operand()?),
+            OpCode::SetUpvalue => Instruction::SetUpvalue(decoder.operand()?),
+            OpCode::GetProperty => Instruction::GetProperty(decoder.name()?),
+            OpCode::SetProperty => Instruction::SetProperty(decoder.name()?),
 
-            Instruction::And => self.debug_simple_instruction("and", instruction_index),
-            Instruction::Or => self.debug_simple_instruction("or", instruction_index),
+            OpCode::Identify => Instruction::Identify,
+            OpCode::Negate => Instruction::Negate,
+            OpCode::Not => Instruction::Not,
 
-            Instruction::JumpIfFalse(ip) => {
-                self.debug_complex_instruction("jump_if_false", instruction_index, ip)
+            OpCode::Add => Instruction::Add,
+            OpCode::Subtract => Instruction::Subtract,
+            OpCode::Multiply => Instruction::Multiply,
+            OpCode::Divide => Instruction::Divide,
+            OpCode::Modulo => Instruction::Modulo,
+
+            OpCode::Equal => Instruction::Equal,
+            OpCode::NotEqual => Instruction::NotEqual,
+            OpCode::Greater => Instruction::Greater,
+            OpCode::GreaterEqual => Instruction::GreaterEqual,
+            OpCode::Lesser => Instruction::Lesser,
+            OpCode::LesserEqual => Instruction::LesserEqual,
+
+            OpCode::And => Instruction::And,
+            OpCode::Or => Instruction::Or,
+
+            OpCode::Jump => Instruction::Jump(decoder.address()?),
+            OpCode::JumpIfFalse => Instruction::JumpIfFalse(decoder.address()?),
+            OpCode::JumpIfNil => Instruction::JumpIfNil(decoder.address()?),
+
+            OpCode::TestVariant => match decoder.constant()? {
+                Constant::Enum(enum_type) => {
+                    Instruction::TestVariant(enum_type.clone(), decoder.operand()?)
+                }
+                _ => return Err(decoder.wrong_constant("an enum")),
+            },
+            OpCode::UnpackVariant => Instruction::UnpackVariant,
+
+            OpCode::GetIterator => Instruction::GetIterator,
+            OpCode::IterateNext => Instruction::IterateNext(decoder.address()?),
+
+            OpCode::Closure => match decoder.constant()? {
+                Constant::Function(function) => Instruction::Closure(function.clone()),
+                _ => return Err(decoder.wrong_constant("a function")),
+            },
+            OpCode::BuildClass => match decoder.constant()? {
+                Constant::Class(name, methods) => {
+                    Instruction::BuildClass(name.clone(), methods.clone())
+                }
+                _ => return Err(decoder.wrong_constant("a class")),
+            },
+            OpCode::Call => Instruction::Call(decoder.operand()?),
+            OpCode::CallWithKeywords | OpCode::Spawn => {
+                let argument_count = decoder.operand()?;
+                let Constant::Keywords(keywords) = decoder.constant()? else {
+                    return Err(decoder.wrong_constant("a list of keywords"));
+                };
+                if opcode == OpCode::Spawn {
+                    Instruction::Spawn(argument_count, keywords.clone())
+                } else {
+                    Instruction::CallWithKeywords(argument_count, keywords.clone())
+                }
             }
 
-            Instruction::Continue => self.debug_simple_instruction("continue", instruction_index),
+            OpCode::AssertFailed => match decoder.constant()? {
+                Constant::Assertion(source, negated) => {
+                    Instruction::AssertFailed(source.clone(), negated.clone())
+                }
+                _ => return Err(decoder.wrong_constant("an assertion")),
+            },
+
+            OpCode::Continue => Instruction::Continue,
         };
+        Ok((instruction, decoder.next))
+    }
+
+    /// Checks that the position table starts at the first instruction and is in order, so
+    /// every address has a position.
+    pub(crate) fn check_positions(&self) -> Result<(), String> {
+        let ordered = self
+            .positions
+            .windows(2)
+            .all(|runs| runs[0].address < runs[1].address);
+        let covered = matches!(self.positions.first(), Some(first) if first.address == 0);
+        if ordered && covered {
+            Ok(())
+        } else {
+            Err("Invalid bytecode. The position table does not cover the code.".to_string())
+        }
+    }
+
+    pub(crate) fn get_position(&self, address: usize) -> Position {
+        let index = self
+            .positions
+            .partition_point(|run| run.address as usize <= address);
+        self.positions[index - 1].position()
     }
 
-    fn debug_simple_instruction(&self, instruction_name: &str, instruction_index: usize) {
-        println!(
-            "{:014} {} {}",
-            instruction_index, self.positions[instruction_index].line, instruction_name,
-        );
+    pub(crate) fn len(&self) -> usize {
+        self.code.len()
     }
+}
+
+/// Reads the operands of one instruction, failing instead of reading past the end of the
+/// code or the constant table.
+struct Decoder<'a> {
+    chunk: &'a Chunk,
+    opcode: OpCode,
+    address: usize,
+    next: usize,
+}
 
-    fn debug_complex_instruction<T>(
-        &self,
-        instruction_name: &str,
-        instruction_index: usize,
-        operand: T,
-    ) where
-        T: Display,
-    {
-        println!(
-            "{:014} {} {} '{}'",
-            instruction_index, self.positions[instruction_index].line, instruction_name, operand
-        );
+impl<'a> Decoder<'a> {
+    fn operand(&mut self) -> Result<usize, String> {
+        let mut operand = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *self
+                .chunk
+                .code
+                .get(self.next)
+                .ok_or_else(|| self.truncated())?;
+            if shift >= usize::BITS {
+                return Err(self.truncated());
+            }
+            self.next += 1;
+            operand |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(operand);
+            }
+            shift += 7;
+        }
     }
 
-    pub(crate) fn ip_is_valid(&self, ip: usize) -> bool {
-        ip < self.instructions.len()
+    fn address(&mut self) -> Result<usize, String> {
+        if self.next + 4 > self.chunk.code.len() {
+            return Err(self.truncated());
+        }
+        self.next += 4;
+        Ok(self.chunk.read_address(self.next - 4))
+    }
+
+    fn constant(&mut self) -> Result<&'a Constant, String> {
+        let index = self.operand()?;
+        self.chunk.constants.get(index).ok_or_else(|| {
+            format!(
+                "Invalid bytecode. '{:?}' at address {} refers to constant {index}, which does not exist.",
+                self.opcode, self.address
+            )
+        })
+    }
+
+    fn name(&mut self) -> Result<Str, String> {
+        match self.constant()? {
+            Constant::Name(name) => Ok(name.clone()),
+            _ => Err(self.wrong_constant("a name")),
+        }
+    }
+
+    fn truncated(&self) -> String {
+        format!(
+            "Invalid bytecode. The operands of '{:?}' at address {} run past the end of the code.",
+            self.opcode, self.address
+        )
+    }
+
+    fn wrong_constant(&self, expected: &str) -> String {
+        format!(
+            "Invalid bytecode. '{:?}' at address {} expects its constant to be {expected}.",
+            self.opcode, self.address
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        backend::instruction::Instruction,
+        common::{object::Object, position::Position},
+        compile_source,
+    };
+
+    use super::Chunk;
+
+    const SOURCE: &str = concat!(
+        "enum Shape { Dot, Square(side) }\n",
+        "class Box { fun init(size) { this.size = size } }\n",
+        "fun area(shape, scale = 1) {\n",
+        "    if shape is Shape.Square(side) { return side * side * scale }\n",
+        "    return 0\n",
+        "}\n",
+        "var total = 0\n",
+        "for i in 0..=3 { total = total + area(Shape.Square(i), scale: 2) }\n",
+        "assert total > 0, \"no area\"\n",
+        "print Box(total)?.size ?? \"none\"\n",
+    );
+
+    /// Decodes every instruction of `chunk` and encodes it again into a new chunk.
+    fn reencode(chunk: &Chunk) -> Chunk {
+        let mut copy = Chunk::new();
+        let mut address = 0;
+        while address < chunk.len() {
+            let (instruction, next) = chunk.get_instruction(address).unwrap();
+            copy.add_instruction(instruction, chunk.get_position(address));
+            address = next;
+        }
+        copy
+    }
+
+    #[test]
+    fn decodes_what_it_encodes() {
+        let chunk = compile_source(SOURCE).unwrap();
+        let copy = reencode(&chunk);
+        assert_eq!(copy.code, chunk.code);
+        assert_eq!(copy.constants.len(), chunk.constants.len());
+        for address in 0..chunk.len() {
+            assert_eq!(copy.get_position(address), chunk.get_position(address));
+        }
+    }
+
+    #[test]
+    fn stores_equal_constants_once() {
+        let chunk = compile_source("var a = \"a\"\nprint \"a\" + a\nprint a + 1 + 1\n").unwrap();
+        // "a", the name a, 1 and the nil the script returns.
+        assert_eq!(chunk.constants.len(), 4);
+    }
+
+    #[test]
+    fn finds_positions_at_the_edges_of_runs() {
+        let first = Position::new(0, 3, 0, 0);
+        let second = Position::new(4, 9, 1, 0);
+        let mut chunk = Chunk::new();
+        chunk.add_instruction(Instruction::Push(Object::Nil), first.clone());
+        let pop = chunk.add_instruction(Instruction::Pop, first.clone());
+        let push = chunk.add_instruction(Instruction::Push(Object::Nil), second.clone());
+        let ret = chunk.add_instruction(Instruction::Return, second.clone());
+        let print = chunk.add_instruction(Instruction::Print, first.clone());
+
+        // Instructions in a row with the same position share a run.
+        assert_eq!(chunk.positions.len(), 3);
+        assert_eq!(chunk.get_position(0), first);
+        assert_eq!(chunk.get_position(pop), first);
+        assert_eq!(chunk.get_position(push), second);
+        // The operand bytes of an instruction belong to its run too.
+        assert_eq!(chunk.get_position(push + 1), second);
+        assert_eq!(chunk.get_position(ret), second);
+        assert_eq!(chunk.get_position(print), first);
     }
 }
@@ -0,0 +1,306 @@
+use std::fmt::Write;
+
+use crate::common::{
+    interner::Str,
+    object::{Capture, Object},
+};
+
+use super::{
+    chunk::Chunk,
+    instruction::{Instruction, OpCode},
+};
+
+/// Renders a compiled script as a listing, one instruction per line with its address, the
+/// line and column it was compiled from, its opcode and its operands. Jump targets are
+/// labelled, and every function the script builds a closure of gets a section of its own
+/// after the code that builds it.
+pub(crate) fn disassemble(chunk: &Chunk) -> String {
+    let mut listing = String::new();
+    section(chunk, "<script>", None, &mut listing);
+    listing
+}
+
+fn section(chunk: &Chunk, name: &str, parameters: Option<String>, listing: &mut String) {
+    match parameters {
+        Some(parameters) => writeln!(listing, "== fun {name}({parameters}) ==").unwrap(),
+        None => writeln!(listing, "== {name} ==").unwrap(),
+    }
+
+    let labels = Labels::new(chunk);
+    let mut functions = Vec::new();
+    let mut address = 0;
+    while address < chunk.len() {
+        if let Some(label) = labels.at(address) {
+            writeln!(listing, "{label}:").unwrap();
+        }
+        match chunk.get_instruction(address) {
+            Ok((instruction, next)) => {
+                writeln!(listing, "{}", line(chunk, address, &instruction, &labels)).unwrap();
+                if let Instruction::Closure(function) = instruction {
+                    functions.push(function);
+                }
+                address = next;
+            }
+            Err(message) => {
+                writeln!(listing, "{address:04}  {message}").unwrap();
+                break;
+            }
+        }
+    }
+
+    for function in functions {
+        let name = if name == "<script>" {
+            function.name.clone()
+        } else {
+            format!("{name}.{}", function.name)
+        };
+        let signature = &function.signature;
+        let defaults = signature.parameters[signature.required..]
+            .iter()
+            .zip(&signature.defaults)
+            .map(|(parameter, default)| format!("{parameter} = {default}"));
+        let parameters: Vec<String> = signature.parameters[..signature.required]
+            .iter()
+            .cloned()
+            .chain(defaults)
+            .chain(signature.rest.iter().map(|rest| format!("...{rest}")))
+            .collect();
+        writeln!(listing).unwrap();
+        section(&function.chunk, &name, Some(parameters.join(", ")), listing);
+    }
+}
+
+/// Formats the instruction at `address` the way a listing shows it.
+pub(crate) fn instruction(chunk: &Chunk, address: usize) -> String {
+    match chunk.get_instruction(address) {
+        Ok((instruction, _)) => line(chunk, address, &instruction, &Labels::default()),
+        Err(message) => format!("{address:04}  {message}"),
+    }
+}
+
+/// The name of the opcode at `address` and its operands, for output that keeps them apart.
+pub(crate) fn opcode_and_operands(chunk: &Chunk, address: usize) -> (&'static str, String) {
+    match chunk.get_instruction(address) {
+        Ok((instruction, _)) => {
+            let opcode = OpCode::from_byte(chunk.code()[address]).unwrap();
+            (name(opcode), operands(&instruction, &Labels::default()))
+        }
+        Err(message) => ("invalid", message),
+    }
+}
+
+fn line(chunk: &Chunk, address: usize, instruction: &Instruction, labels: &Labels) -> String {
+    let position = chunk.get_position(address);
+    let opcode = OpCode::from_byte(chunk.code()[address]).unwrap();
+    let operands = operands(instruction, labels);
+    format!(
+        "{address:04}  {:>4}:{:<4}  {}{}{operands}",
+        position.line + 1,
+        position.column + 1,
+        name(opcode),
+        if operands.is_empty() { "" } else { " " },
+    )
+}
+
+fn name(opcode: OpCode) -> &'static str {
+    match opcode {
+        OpCode::Return => "return",
+        OpCode::Yield => "yield",
+        OpCode::Print => "print",
+        OpCode::Push => "push",
+        OpCode::Pop => "pop",
+        OpCode::Duplicate => "duplicate",
+        OpCode::BuildList => "build_list",
+        OpCode::BuildRange => "build_range",
+        OpCode::DefineGlobal => "define_global",
+        OpCode::GetGlobal => "get_global",
+        OpCode::SetGlobal => "set_global",
+        OpCode::GetLocal => "get_local",
+        OpCode::SetLocal => "set_local",
+        OpCode::BoxLocal => "box_local",
+        OpCode::GetCell => "get_cell",
+        OpCode::SetCell => "set_cell",
+        OpCode::GetUpvalue => "get_upvalue",
+        OpCode::SetUpvalue => "set_upvalue",
+        OpCode::GetProperty => "get_property",
+        OpCode::SetProperty => "set_property",
+        OpCode::Identify => "identify",
+        OpCode::Negate => "negate",
+        OpCode::Not => "not",
+        OpCode::Add => "add",
+        OpCode::Subtract => "subtract",
+        OpCode::Multiply => "multiply",
+        OpCode::Divide => "divide",
+        OpCode::Modulo => "modulo",
+        OpCode::Equal => "equal",
+        OpCode::NotEqual => "not_equal",
+        OpCode::Greater => "greater",
+        OpCode::GreaterEqual => "greater_equal",
+        OpCode::Lesser => "lesser",
+        OpCode::LesserEqual => "lesser_equal",
+        OpCode::And => "and",
+        OpCode::Or => "or",
+        OpCode::Jump => "jump",
+        OpCode::JumpIfFalse => "jump_if_false",
+        OpCode::JumpIfNil => "jump_if_nil",
+        OpCode::TestVariant => "test_variant",
+        OpCode::UnpackVariant => "unpack_variant",
+        OpCode::GetIterator => "get_iterator",
+        OpCode::IterateNext => "iterate_next",
+        OpCode::Closure => "closure",
+        OpCode::BuildClass => "build_class",
+        OpCode::Call => "call",
+        OpCode::CallWithKeywords => "call_with_keywords",
+        OpCode::Spawn => "spawn",
+        OpCode::AssertFailed => "assert_failed",
+        OpCode::Continue => "continue",
+    }
+}
+
+fn operands(instruction: &Instruction, labels: &Labels) -> String {
+    match instruction {
+        Instruction::Return
+        | Instruction::Yield
+        | Instruction::Print
+        | Instruction::Pop
+        | Instruction::Identify
+        | Instruction::Negate
+        | Instruction::Not
+        | Instruction::Add
+        | Instruction::Subtract
+        | Instruction::Multiply
+        | Instruction::Divide
+        | Instruction::Modulo
+        | Instruction::Equal
+        | Instruction::NotEqual
+        | Instruction::Greater
+        | Instruction::GreaterEqual
+        | Instruction::Lesser
+        | Instruction::LesserEqual
+        | Instruction::And
+        | Instruction::Or
+        | Instruction::UnpackVariant
+        | Instruction::GetIterator
+        | Instruction::Continue => String::new(),
+
+        Instruction::Push(Object::String(string)) => format!("{:?}", string.as_str()),
+        Instruction::Push(object) => object.to_string(),
+        Instruction::Duplicate(count) => count.to_string(),
+        Instruction::BuildList(length) => length.to_string(),
+        Instruction::BuildRange(inclusive) => {
+            if *inclusive { "inclusive" } else { "exclusive" }.to_string()
+        }
+
+        Instruction::DefineGlobal(name)
+        | Instruction::GetGlobal(name)
+        | Instruction::SetGlobal(name)
+        | Instruction::GetProperty(name)
+        | Instruction::SetProperty(name) => name.to_string(),
+        Instruction::GetLocal(slot)
+        | Instruction::SetLocal(slot)
+        | Instruction::BoxLocal(slot)
+        | Instruction::GetCell(slot)
+        | Instruction::SetCell(slot) => format!("slot {slot}"),
+        Instruction::GetUpvalue(index) | Instruction::SetUpvalue(index) => {
+            format!("upvalue {index}")
+        }
+
+        Instruction::Jump(target)
+        | Instruction::JumpIfFalse(target)
+        | Instruction::JumpIfNil(target)
+        | Instruction::IterateNext(target) => labels.target(*target),
+
+        Instruction::TestVariant(enum_type, index) => enum_type.variant_name(*index),
+
+        Instruction::Closure(function) => {
+            let captures: Vec<String> = function
+                .captures
+                .iter()
+                .map(|capture| match capture {
+                    Capture::Local(slot) => format!("slot {slot}"),
+                    Capture::Upvalue(index) => format!("upvalue {index}"),
+                })
+                .collect();
+            if captures.is_empty() {
+                format!("<fun {}>", function.name)
+            } else {
+                format!("<fun {}> captures {}", function.name, captures.join(", "))
+            }
+        }
+        Instruction::BuildClass(name, methods) => format!(
+            "{name} ({})",
+            methods
+                .iter()
+                .map(Str::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Instruction::Call(argument_count) => argument_count.to_string(),
+        Instruction::CallWithKeywords(argument_count, keywords)
+        | Instruction::Spawn(argument_count, keywords) => {
+            if keywords.is_empty() {
+                argument_count.to_string()
+            } else {
+                format!("{argument_count} ({})", keywords.join(", "))
+            }
+        }
+
+        Instruction::AssertFailed(source, _) => format!("{source:?}"),
+    }
+}
+
+/// Names every address a jump lands on, in the order they appear in the code.
+#[derive(Default)]
+struct Labels {
+    targets: Vec<usize>,
+}
+
+impl Labels {
+    fn new(chunk: &Chunk) -> Self {
+        let mut targets = Vec::new();
+        let mut address = 0;
+        while address < chunk.len() {
+            let Ok((instruction, next)) = chunk.get_instruction(address) else {
+                break;
+            };
+            if let Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::JumpIfNil(target)
+            | Instruction::IterateNext(target) = instruction
+            {
+                targets.push(target);
+            }
+            address = next;
+        }
+        targets.sort_unstable();
+        targets.dedup();
+        Self { targets }
+    }
+
+    fn at(&self, address: usize) -> Option<String> {
+        let index = self.targets.binary_search(&address).ok()?;
+        Some(format!("L{}", index + 1))
+    }
+
+    /// Listings without labels, like a single traced instruction, show the address instead.
+    fn target(&self, address: usize) -> String {
+        self.at(address).unwrap_or_else(|| format!("{address:04}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compile_source;
+
+    use super::disassemble;
+
+    #[test]
+    fn listing_shows_signatures_and_columns() {
+        let source = "fun h(a, b = 1 + 2, ...rest) { return rest }\n  print h(1)\n";
+        let listing = disassemble(&compile_source(source).unwrap());
+        assert!(listing.contains("== fun h(a, b = 1 + 2, ...rest) =="));
+        // Columns count from the start of their own line, not from the start of the file.
+        assert!(listing.contains("0009     2:9     get_global h"));
+        assert!(listing.contains("0011     2:11    push 1"));
+    }
+}
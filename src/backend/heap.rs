@@ -0,0 +1,392 @@
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    mem::{size_of, size_of_val},
+    ops::Deref,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+use crate::common::{
+    interner::Str,
+    object::{Closure, Generator, Instance, IteratorState, Object},
+};
+
+/// The first collection happens once this many bytes are allocated.
+const INITIAL_THRESHOLD: usize = 1024 * 1024;
+/// After a collection, the next one happens once the heap has grown by this factor.
+const GROWTH_FACTOR: usize = 2;
+
+/// A handle to a value on the heap. Handles are copied freely and do not keep their value
+/// alive: it lives until a collection finds no handle to it that is reachable from the
+/// virtual machine's roots.
+pub(crate) struct Gc<T: 'static> {
+    allocation: NonNull<Allocation<T>>,
+}
+
+struct Allocation<T: ?Sized> {
+    marked: Cell<bool>,
+    value: T,
+}
+
+impl<T> Gc<T> {
+    pub(crate) fn ptr_eq(a: &Self, b: &Self) -> bool {
+        a.allocation == b.allocation
+    }
+}
+
+impl<T> Clone for Gc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Gc<T> {}
+
+impl<T> Deref for Gc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // SAFETY: Collections only run at safe points, where every handle that is used
+        // afterwards is reachable from the roots, so the allocation has not been freed.
+        unsafe { &self.allocation.as_ref().value }
+    }
+}
+
+/// A value that can live on the heap, which knows the handles it holds.
+pub(crate) trait Trace {
+    fn trace(&self, tracer: &mut Tracer);
+
+    /// Bytes the value owns outside of its allocation, like the buffer of a list.
+    fn owned_size(&self) -> usize {
+        0
+    }
+}
+
+impl Trace for RefCell<Vec<Object>> {
+    fn trace(&self, tracer: &mut Tracer) {
+        for element in self.borrow().iter() {
+            tracer.object(element);
+        }
+    }
+
+    fn owned_size(&self) -> usize {
+        self.borrow().capacity() * size_of::<Object>()
+    }
+}
+
+impl Trace for RefCell<Instance> {
+    fn trace(&self, tracer: &mut Tracer) {
+        let instance = self.borrow();
+        for method in instance.class.methods.values() {
+            tracer.closure(method);
+        }
+        for field in instance.fields.values() {
+            tracer.object(field);
+        }
+    }
+
+    fn owned_size(&self) -> usize {
+        self.borrow().fields.capacity() * (size_of::<Str>() + size_of::<Object>())
+    }
+}
+
+impl Trace for RefCell<Object> {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.object(&self.borrow());
+    }
+}
+
+/// Marks everything reachable from the roots. Values are queued instead of traced right
+/// away, so long chains of lists or enum variants do not recurse.
+pub(crate) struct Tracer {
+    gray: Vec<Gray>,
+    /// Reference counted objects already queued, which can be shared and form cycles too.
+    visited: HashSet<*const ()>,
+}
+
+/// A value that is known to be reachable, whose contents are not traced yet.
+enum Gray {
+    Allocation(NonNull<Allocation<dyn Trace>>),
+    Object(Object),
+    Closure(Rc<Closure>),
+    Generator(Rc<RefCell<Generator>>),
+}
+
+impl Tracer {
+    fn mark<T: Trace>(&mut self, handle: Gc<T>) {
+        // SAFETY: A reachable handle points to a live allocation.
+        let allocation = unsafe { handle.allocation.as_ref() };
+        if !allocation.marked.replace(true) {
+            self.gray.push(Gray::Allocation(handle.allocation));
+        }
+    }
+
+    fn visit<T>(&mut self, object: &Rc<T>) -> bool {
+        self.visited.insert(Rc::as_ptr(object) as *const ())
+    }
+
+    /// Queues a reference counted `object` the first time its value `rc` is seen.
+    fn queue<T>(&mut self, rc: &Rc<T>, object: &Object) {
+        if self.visit(rc) {
+            self.gray.push(Gray::Object(object.clone()));
+        }
+    }
+
+    pub(crate) fn object(&mut self, object: &Object) {
+        match object {
+            Object::List(list) => self.mark(*list),
+            Object::Instance(instance) => self.mark(*instance),
+            Object::Cell(cell) => self.mark(*cell),
+            Object::Function(closure) => self.closure(closure),
+            Object::Generator(generator) => self.generator(generator),
+            Object::Iterator(iterator) => self.queue(iterator, object),
+            Object::Channel(channel) => self.queue(channel, object),
+            Object::Variant(variant) => self.queue(variant, object),
+            Object::Class(class) => self.queue(class, object),
+            Object::BoundMethod(bound) => self.queue(bound, object),
+            Object::Module(module) => self.queue(module, object),
+            Object::Match(matched) => self.queue(matched, object),
+            Object::Nil
+            | Object::Boolean(_)
+            | Object::Number(_)
+            | Object::BigInt(_)
+            | Object::String(_)
+            | Object::Range(_)
+            | Object::Fiber(_)
+            | Object::Enum(_)
+            | Object::Constructor(..)
+            | Object::NativeFunction(_)
+            | Object::Regex(_) => {}
+        }
+    }
+
+    pub(crate) fn closure(&mut self, closure: &Rc<Closure>) {
+        if self.visit(closure) {
+            self.gray.push(Gray::Closure(closure.clone()));
+        }
+    }
+
+    pub(crate) fn generator(&mut self, generator: &Rc<RefCell<Generator>>) {
+        if self.visit(generator) {
+            self.gray.push(Gray::Generator(generator.clone()));
+        }
+    }
+
+    /// Queues everything a gray value refers to.
+    fn trace(&mut self, gray: Gray) {
+        match gray {
+            // SAFETY: Only live allocations are marked.
+            Gray::Allocation(allocation) => unsafe { allocation.as_ref() }.value.trace(self),
+            Gray::Object(object) => self.trace_object(&object),
+            Gray::Closure(closure) => {
+                for upvalue in &closure.upvalues {
+                    self.mark(*upvalue);
+                }
+                for default in &closure.defaults {
+                    self.object(default);
+                }
+            }
+            Gray::Generator(generator) => {
+                let generator = generator.borrow();
+                self.closure(&generator.closure);
+                for slot in &generator.slots {
+                    self.object(slot);
+                }
+            }
+        }
+    }
+
+    fn trace_object(&mut self, object: &Object) {
+        match object {
+            Object::Iterator(iterator) => match &*iterator.borrow() {
+                IteratorState::List(list, _) => self.mark(*list),
+                IteratorState::Callable(callable) => self.object(callable),
+                IteratorState::Generator(generator) => self.generator(generator),
+                IteratorState::Range(_) | IteratorState::String(..) => {}
+            },
+            Object::Channel(channel) => {
+                if let Some(value) = &channel.borrow().value {
+                    self.object(value);
+                }
+            }
+            Object::Variant(variant) => {
+                for value in &variant.values {
+                    self.object(value);
+                }
+            }
+            Object::Class(class) => {
+                for method in class.methods.values() {
+                    self.closure(method);
+                }
+            }
+            Object::BoundMethod(bound) => {
+                self.object(&bound.receiver);
+                self.closure(&bound.method);
+            }
+            Object::Module(module) => {
+                for member in module.members.values() {
+                    self.object(member);
+                }
+            }
+            Object::Match(matched) => {
+                for property in matched.properties.values() {
+                    self.object(property);
+                }
+            }
+            // Only the objects `object` queues are traced here.
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Where lists, instances and captured variables live, which can refer to each other in
+/// cycles. Values are freed by a tracing mark-and-sweep collection once the bytes allocated
+/// since the last one outgrow what survived it. Strings and other values that can not form
+/// cycles are reference counted instead.
+pub(crate) struct Heap {
+    allocations: Vec<NonNull<Allocation<dyn Trace>>>,
+    bytes_allocated: usize,
+    next_collection: usize,
+    /// Collects whenever anything was allocated, to find handles missing from the roots.
+    stress: bool,
+    allocated: bool,
+}
+
+impl Heap {
+    pub(crate) fn new() -> Self {
+        Self {
+            allocations: Vec::new(),
+            bytes_allocated: 0,
+            next_collection: INITIAL_THRESHOLD,
+            stress: false,
+            allocated: false,
+        }
+    }
+
+    pub(crate) fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub(crate) fn allocate<T: Trace>(&mut self, value: T) -> Gc<T> {
+        self.bytes_allocated += size_of::<Allocation<T>>() + value.owned_size();
+        self.allocated = true;
+        let allocation = NonNull::from(Box::leak(Box::new(Allocation {
+            marked: Cell::new(false),
+            value,
+        })));
+        self.allocations.push(allocation);
+        Gc { allocation }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn allocation_count(&self) -> usize {
+        self.allocations.len()
+    }
+
+    /// Whether to collect at the next safe point: when the heap has outgrown its threshold,
+    /// or under stress whenever anything was allocated since the last collection.
+    pub(crate) fn should_collect(&self) -> bool {
+        if self.stress {
+            self.allocated
+        } else {
+            self.bytes_allocated > self.next_collection
+        }
+    }
+
+    /// Frees every allocation `roots` can not reach. Every handle used after this call must
+    /// be reachable from the roots.
+    pub(crate) fn collect(&mut self, roots: impl FnOnce(&mut Tracer)) {
+        let mut tracer = Tracer {
+            gray: Vec::new(),
+            visited: HashSet::new(),
+        };
+        roots(&mut tracer);
+        while let Some(gray) = tracer.gray.pop() {
+            tracer.trace(gray);
+        }
+
+        let mut bytes_live = 0;
+        self.allocations.retain(|allocation| {
+            // SAFETY: Allocations are only freed here, after which they are not retained.
+            let live = unsafe { allocation.as_ref() };
+            if live.marked.replace(false) {
+                bytes_live += size_of_val(live) + live.value.owned_size();
+                true
+            } else {
+                // SAFETY: Nothing reachable points to the allocation anymore.
+                drop(unsafe { Box::from_raw(allocation.as_ptr()) });
+                false
+            }
+        });
+        self.bytes_allocated = bytes_live;
+        self.next_collection = (bytes_live * GROWTH_FACTOR).max(INITIAL_THRESHOLD);
+        self.allocated = false;
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        for allocation in self.allocations.drain(..) {
+            // SAFETY: The heap owns every allocation, and it is going away.
+            drop(unsafe { Box::from_raw(allocation.as_ptr()) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    #[test]
+    fn keeps_everything_reachable_under_stress() {
+        let mut vm = VirtualMachine::new();
+        vm.heap().set_stress(true);
+        let source = concat!(
+            "class Node { fun init(value) { this.value = value this.next = nil } }\n",
+            "class Nodes { fun __iter__() { return [Node(10), Node(20)] } }\n",
+            "fun* numbers() { for i in 0..3 { yield Node(i) } }\n",
+            "fun counter() { var count = 0 return fun() { count = count + 1 return count } }\n",
+            "var total = 0\n",
+            "for i in 0..50 {\n",
+            "    var a = Node(i)\n",
+            "    var b = Node(a)\n",
+            "    a.next = b\n",
+            "    total = total + b.value.value\n",
+            "}\n",
+            "for node in Nodes() { total = total + node.value }\n",
+            "for node in numbers() { total = total + node.value }\n",
+            "var count = counter()\n",
+            "count()\n",
+            "total = total + count()\n",
+            "var c = chan()\n",
+            "fun put() { send(c, [total, Node(nil)]) }\n",
+            "spawn put()\n",
+            "var received = recv(c)\n",
+        );
+        run_script(&mut vm, source).unwrap();
+        // 0 + 1 + ... + 49, then 10 + 20 from `__iter__`, 0 + 1 + 2 from the generator and 2.
+        assert_eq!(
+            vm.global("received").as_deref(),
+            Some("[1260, <Node instance>]")
+        );
+    }
+
+    #[test]
+    fn frees_unreachable_cycles() {
+        let mut vm = VirtualMachine::new();
+        vm.heap().set_stress(true);
+        let source = concat!(
+            "class Node { fun init() { this.next = nil } }\n",
+            "for i in 0..100 {\n",
+            "    var a = Node()\n",
+            "    var b = Node()\n",
+            "    a.next = b\n",
+            "    b.next = a\n",
+            "}\n",
+            "var done = true\n",
+        );
+        run_script(&mut vm, source).unwrap();
+        assert!(vm.heap().allocation_count() < 10);
+    }
+}
@@ -1,12 +1,36 @@
-use crate::common::object::Object;
+use std::rc::Rc;
 
+use crate::common::{
+    interner::Str,
+    object::{Enum, Function, Object},
+};
+
+/// An instruction as the compiler emits it. A chunk stores it encoded, as an opcode byte
+/// followed by its operands, and decodes it back for debugging.
 #[derive(Clone)]
 pub(crate) enum Instruction {
     Return,
+    Yield,
     Print,
 
     Push(Object),
     Pop,
+    Duplicate(usize),
+    BuildList(usize),
+    BuildRange(bool),
+
+    DefineGlobal(Str),
+    GetGlobal(Str),
+    SetGlobal(Str),
+    GetLocal(usize),
+    SetLocal(usize),
+    BoxLocal(usize),
+    GetCell(usize),
+    SetCell(usize),
+    GetUpvalue(usize),
+    SetUpvalue(usize),
+    GetProperty(Str),
+    SetProperty(Str),
 
     Identify,
     Negate,
@@ -16,6 +40,7 @@ pub(crate) enum Instruction {
     Subtract,
     Multiply,
     Divide,
+    Modulo,
 
     Equal,
     NotEqual,
@@ -27,7 +52,182 @@ pub(crate) enum Instruction {
     And,
     Or,
 
+    Jump(usize),
     JumpIfFalse(usize),
+    JumpIfNil(usize),
+
+    TestVariant(Rc<Enum>, usize),
+    UnpackVariant,
+
+    GetIterator,
+    IterateNext(usize),
+
+    Closure(Rc<Function>),
+    BuildClass(String, Vec<Str>),
+    Call(usize),
+    CallWithKeywords(usize, Vec<String>),
+    Spawn(usize, Vec<String>),
+
+    AssertFailed(String, Option<String>),
 
     Continue,
 }
+
+/// The first byte of an encoded instruction. Counts, slots and indices into the constant
+/// table follow it as LEB128 operands, which take one byte below 128. Jump addresses are
+/// four bytes, so a jump can be patched once its target is known.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub(crate) enum OpCode {
+    Return,
+    Yield,
+    Print,
+    Push,
+    Pop,
+    Duplicate,
+    BuildList,
+    BuildRange,
+    DefineGlobal,
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    BoxLocal,
+    GetCell,
+    SetCell,
+    GetUpvalue,
+    SetUpvalue,
+    GetProperty,
+    SetProperty,
+    Identify,
+    Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Lesser,
+    LesserEqual,
+    And,
+    Or,
+    Jump,
+    JumpIfFalse,
+    JumpIfNil,
+    TestVariant,
+    UnpackVariant,
+    GetIterator,
+    IterateNext,
+    Closure,
+    BuildClass,
+    Call,
+    CallWithKeywords,
+    Spawn,
+    AssertFailed,
+    Continue,
+}
+
+const OPCODES: [OpCode; 50] = [
+    OpCode::Return,
+    OpCode::Yield,
+    OpCode::Print,
+    OpCode::Push,
+    OpCode::Pop,
+    OpCode::Duplicate,
+    OpCode::BuildList,
+    OpCode::BuildRange,
+    OpCode::DefineGlobal,
+    OpCode::GetGlobal,
+    OpCode::SetGlobal,
+    OpCode::GetLocal,
+    OpCode::SetLocal,
+    OpCode::BoxLocal,
+    OpCode::GetCell,
+    OpCode::SetCell,
+    OpCode::GetUpvalue,
+    OpCode::SetUpvalue,
+    OpCode::GetProperty,
+    OpCode::SetProperty,
+    OpCode::Identify,
+    OpCode::Negate,
+    OpCode::Not,
+    OpCode::Add,
+    OpCode::Subtract,
+    OpCode::Multiply,
+    OpCode::Divide,
+    OpCode::Modulo,
+    OpCode::Equal,
+    OpCode::NotEqual,
+    OpCode::Greater,
+    OpCode::GreaterEqual,
+    OpCode::Lesser,
+    OpCode::LesserEqual,
+    OpCode::And,
+    OpCode::Or,
+    OpCode::Jump,
+    OpCode::JumpIfFalse,
+    OpCode::JumpIfNil,
+    OpCode::TestVariant,
+    OpCode::UnpackVariant,
+    OpCode::GetIterator,
+    OpCode::IterateNext,
+    OpCode::Closure,
+    OpCode::BuildClass,
+    OpCode::Call,
+    OpCode::CallWithKeywords,
+    OpCode::Spawn,
+    OpCode::AssertFailed,
+    OpCode::Continue,
+];
+
+impl OpCode {
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        OPCODES.get(byte as usize).copied()
+    }
+}
+
+/// An operand that does not fit in a byte stream, kept in the chunk's constant table.
+#[derive(Clone)]
+pub(crate) enum Constant {
+    Value(Object),
+    Name(Str),
+    Enum(Rc<Enum>),
+    Function(Rc<Function>),
+    Class(String, Vec<Str>),
+    Keywords(Vec<String>),
+    Assertion(String, Option<String>),
+}
+
+impl Constant {
+    /// Whether `other` can stand in for this constant. Numbers have to have the same bits,
+    /// so `0` and `-0` are kept apart, and anything else that is not a literal has to be
+    /// the very same one.
+    pub(super) fn is_same_as(&self, other: &Constant) -> bool {
+        match (self, other) {
+            (Constant::Value(a), Constant::Value(b)) => match (a, b) {
+                (Object::Nil, Object::Nil) => true,
+                (Object::Boolean(a), Object::Boolean(b)) => a == b,
+                (Object::Number(a), Object::Number(b)) => a.to_bits() == b.to_bits(),
+                (Object::BigInt(a), Object::BigInt(b)) => a == b,
+                (Object::String(a), Object::String(b)) => a.as_str() == b.as_str(),
+                (Object::Variant(a), Object::Variant(b)) => Rc::ptr_eq(a, b),
+                _ => false,
+            },
+            (Constant::Name(a), Constant::Name(b)) => a == b,
+            (Constant::Enum(a), Constant::Enum(b)) => Rc::ptr_eq(a, b),
+            (Constant::Function(a), Constant::Function(b)) => Rc::ptr_eq(a, b),
+            (Constant::Class(a, a_methods), Constant::Class(b, b_methods)) => {
+                a == b && a_methods == b_methods
+            }
+            (Constant::Keywords(a), Constant::Keywords(b)) => a == b,
+            (Constant::Assertion(a, a_negated), Constant::Assertion(b, b_negated)) => {
+                a == b && a_negated == b_negated
+            }
+            _ => false,
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub(crate) mod bytecode;
 pub(crate) mod chunk;
+pub(crate) mod disassembler;
+pub(crate) mod heap;
 pub(crate) mod instruction;
+pub(crate) mod trace;
+pub(crate) mod verifier;
 pub(crate) mod vm;
@@ -0,0 +1,189 @@
+use std::io::{Result, Write};
+
+use crate::common::object::{Function, Object};
+
+use super::disassembler;
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum TraceFormat {
+    /// The instruction as `indu disasm` lists it, followed by the stack.
+    Text,
+    /// One JSON object per line, which is easy to diff between versions of the interpreter.
+    Json,
+}
+
+/// Records every instruction the virtual machine executes, with the values on the stack of
+/// the running frame before it executes. Instructions can be limited to a function and to a
+/// range of source lines.
+pub(crate) struct Trace {
+    output: Box<dyn Write>,
+    format: TraceFormat,
+    function: Option<String>,
+    lines: Option<(usize, usize)>,
+}
+
+impl Trace {
+    pub(crate) fn new(output: Box<dyn Write>, format: TraceFormat) -> Self {
+        Self {
+            output,
+            format,
+            function: None,
+            lines: None,
+        }
+    }
+
+    /// Only records instructions of functions with this name, like `fib` or `Vector.init`.
+    pub(crate) fn set_function(&mut self, name: String) {
+        self.function = Some(name);
+    }
+
+    /// Only records instructions compiled from lines `first` to `last`, counting from 1.
+    pub(crate) fn set_lines(&mut self, first: usize, last: usize) {
+        self.lines = Some((first, last));
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        function: &Function,
+        address: usize,
+        stack: &[Object],
+    ) -> Result<()> {
+        if matches!(&self.function, Some(name) if *name != function.name) {
+            return Ok(());
+        }
+        let chunk = &function.chunk;
+        let line = chunk.get_position(address).line + 1;
+        if matches!(self.lines, Some((first, last)) if line < first || line > last) {
+            return Ok(());
+        }
+
+        match self.format {
+            TraceFormat::Text => {
+                let stack: Vec<String> = stack.iter().map(Object::to_string).collect();
+                writeln!(
+                    self.output,
+                    "{:<16} {:<48} [{}]",
+                    function.name,
+                    disassembler::instruction(chunk, address),
+                    stack.join(", ")
+                )
+            }
+            TraceFormat::Json => {
+                let (op, operands) = disassembler::opcode_and_operands(chunk, address);
+                let stack: Vec<String> = stack.iter().map(json_value).collect();
+                writeln!(
+                    self.output,
+                    "{{\"ip\":{address},\"op\":{},\"operands\":{},\"function\":{},\"line\":{line},\"stack\":[{}]}}",
+                    json_string(op),
+                    json_string(&operands),
+                    json_string(&function.name),
+                    stack.join(",")
+                )
+            }
+        }
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<()> {
+        self.output.flush()
+    }
+}
+
+/// Values JSON has a type for keep it, and every other value is written as it prints.
+fn json_value(object: &Object) -> String {
+    match object {
+        Object::Nil => "null".to_string(),
+        Object::Boolean(boolean) => boolean.to_string(),
+        Object::Number(number) if number.is_finite() => number.to_string(),
+        Object::String(string) => json_string(string),
+        _ => json_string(&object.to_string()),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len() + 2);
+    escaped.push('"');
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            character if character.is_control() => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            }
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    use super::{Trace, TraceFormat};
+
+    /// Keeps what the trace writes where the test can still read it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(format: TraceFormat, configure: impl FnOnce(&mut Trace)) -> Vec<String> {
+        let output = Output::default();
+        let mut trace = Trace::new(Box::new(output.clone()), format);
+        configure(&mut trace);
+        let mut vm = VirtualMachine::new();
+        vm.set_trace(Some(trace));
+        let source = "fun f(x) { return x + 1 }\nvar s = [nil, true, \"a\"]\nprint f(2)\n";
+        run_script(&mut vm, source).unwrap();
+        let text = String::from_utf8(output.0.take()).unwrap();
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let lines = trace(TraceFormat::Json, |trace| {
+            trace.set_function("f".to_string())
+        });
+        assert_eq!(
+            lines,
+            [
+                r#"{"ip":0,"op":"get_local","operands":"slot 1","function":"f","line":1,"stack":["<fun f>",2]}"#,
+                r#"{"ip":2,"op":"push","operands":"1","function":"f","line":1,"stack":["<fun f>",2,2]}"#,
+                r#"{"ip":4,"op":"add","operands":"","function":"f","line":1,"stack":["<fun f>",2,2,1]}"#,
+                r#"{"ip":5,"op":"return","operands":"","function":"f","line":1,"stack":["<fun f>",2,3]}"#,
+            ]
+        );
+
+        // Values JSON has a type for keep it, and every other value is written as it prints.
+        let lines = trace(TraceFormat::Json, |trace| trace.set_lines(2, 2));
+        assert_eq!(
+            lines[3..],
+            [
+                r#"{"ip":10,"op":"build_list","operands":"3","function":"<script>","line":2,"stack":["<fun <script>>",null,true,"a"]}"#,
+                r#"{"ip":12,"op":"define_global","operands":"s","function":"<script>","line":2,"stack":["<fun <script>>","[nil, true, a]"]}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn writes_text_lines_like_a_listing() {
+        let lines = trace(TraceFormat::Text, |trace| trace.set_lines(3, 3));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("<script>         0014     3:7     get_global f"));
+        assert!(lines[0].ends_with("[<fun <script>>]"));
+    }
+}
@@ -0,0 +1,552 @@
+use crate::common::{
+    error::{Error, ErrorKind},
+    object::{Capture, Function, Object},
+};
+
+use super::{chunk::Chunk, instruction::Instruction};
+
+/// Checks a compiled script before it runs, so that code from a damaged or hand made file
+/// fails with an error instead of crashing the virtual machine. Every instruction has to
+/// decode, every jump has to land on an instruction, and along every path the stack has
+/// to hold what each instruction takes from it, with the same depth wherever paths meet.
+pub(crate) fn verify(chunk: &Chunk) -> Result<(), Error> {
+    // The script itself sits in the first slot of its frame.
+    Verifier::new(chunk, 1, 0, false)?.run()
+}
+
+/// The stack of a frame at an instruction, as far as the verifier can tell.
+#[derive(Clone, Copy, PartialEq)]
+struct State {
+    /// Values on the stack above the frame's base, including its locals.
+    depth: usize,
+    /// How many fields the value on top has, when an enum variant was just tested for.
+    variant_fields: Option<usize>,
+}
+
+struct Verifier<'a> {
+    chunk: &'a Chunk,
+    upvalue_count: usize,
+    generator: bool,
+    /// The address of every instruction, which all decode.
+    addresses: Vec<usize>,
+    /// The state on entry to each instruction, by index, once a path reaches it.
+    states: Vec<Option<State>>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(
+        chunk: &'a Chunk,
+        depth: usize,
+        upvalue_count: usize,
+        generator: bool,
+    ) -> Result<Self, Error> {
+        // Code has to end by returning, so empty code is never valid.
+        if chunk.len() == 0 {
+            return Err(Error::new(
+                ErrorKind::Bytecode,
+                "Invalid bytecode. A function has no code.".to_string(),
+                None,
+            ));
+        }
+        chunk
+            .check_positions()
+            .map_err(|message| Error::new(ErrorKind::Bytecode, message, None))?;
+
+        let mut addresses = Vec::new();
+        let mut address = 0;
+        while address < chunk.len() {
+            addresses.push(address);
+            address = chunk
+                .get_instruction(address)
+                .map_err(|message| Self::error_at(chunk, address, message))?
+                .1;
+        }
+
+        let mut states = vec![None; addresses.len()];
+        states[0] = Some(State {
+            depth,
+            variant_fields: None,
+        });
+        Ok(Self {
+            chunk,
+            upvalue_count,
+            generator,
+            addresses,
+            states,
+        })
+    }
+
+    fn run(mut self) -> Result<(), Error> {
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            let state = self.states[index].unwrap();
+            for (successor, state) in self.step(index, state)? {
+                let target = self.index_of(index, successor)?;
+                match self.states[target] {
+                    None => {
+                        self.states[target] = Some(state);
+                        pending.push(target);
+                    }
+                    Some(known) if known.depth != state.depth => {
+                        return Err(self.error(
+                            target,
+                            format!(
+                                "Invalid bytecode. The stack is {} or {} values deep at address {}, depending on the path taken.",
+                                known.depth, state.depth, self.addresses[target]
+                            ),
+                        ));
+                    }
+                    Some(known) if known != state => {
+                        // Paths only agree on the depth, so a variant can no longer be unpacked.
+                        self.states[target] = Some(State {
+                            depth: state.depth,
+                            variant_fields: None,
+                        });
+                        pending.push(target);
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the instruction at `index` to `state`, returning the addresses execution can
+    /// continue at, each with the state it has there.
+    fn step(&self, index: usize, state: State) -> Result<Vec<(usize, State)>, Error> {
+        let address = self.addresses[index];
+        let Ok((instruction, next)) = self.chunk.get_instruction(address) else {
+            unreachable!("every instruction was decoded before")
+        };
+        let depth = state.depth;
+        let after = |depth| State {
+            depth,
+            variant_fields: None,
+        };
+        let needs = |count: usize| {
+            if depth < count {
+                Err(self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. The instruction at address {address} takes {count} value(s), but the stack holds {depth}."
+                    ),
+                ))
+            } else {
+                Ok(depth - count)
+            }
+        };
+        // Counts are read from the file, so the depths worked out from them are checked.
+        let grow = |depth: usize, count: usize| {
+            depth.checked_add(count).ok_or_else(|| {
+                self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. The instruction at address {address} grows the stack past what it can hold."
+                    ),
+                )
+            })
+        };
+        // A call takes its callee and arguments and leaves its result. The count is checked
+        // against the stack before anything is added to it.
+        let call = |argument_count: usize| {
+            needs(argument_count)?;
+            grow(needs(grow(argument_count, 1)?)?, 1)
+        };
+        let slot = |slot: usize| {
+            if slot < depth {
+                Ok(())
+            } else {
+                Err(self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. The instruction at address {address} uses slot {slot}, but the stack holds {depth} value(s)."
+                    ),
+                ))
+            }
+        };
+        let upvalue = |upvalue: usize| {
+            if upvalue < self.upvalue_count {
+                Ok(())
+            } else {
+                Err(self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. The instruction at address {address} uses upvalue {upvalue}, but the function has {}.",
+                        self.upvalue_count
+                    ),
+                ))
+            }
+        };
+
+        let successors = match &instruction {
+            Instruction::Return => {
+                needs(1)?;
+                vec![]
+            }
+            Instruction::Yield => {
+                if !self.generator {
+                    return Err(self.error(
+                        index,
+                        format!(
+                            "Invalid bytecode. The instruction at address {address} yields outside of a generator."
+                        ),
+                    ));
+                }
+                vec![(next, after(needs(1)?))]
+            }
+            Instruction::Print | Instruction::Pop | Instruction::DefineGlobal(_) => {
+                vec![(next, after(needs(1)?))]
+            }
+
+            Instruction::Push(Object::Variant(variant))
+                if variant.values.len()
+                    != variant
+                        .enum_type
+                        .variants
+                        .get(variant.index)
+                        .map_or(usize::MAX, |(_, fields)| *fields) =>
+            {
+                return Err(self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. The instruction at address {address} pushes a '{}' variant that does not match its declaration.",
+                        variant.enum_type.name
+                    ),
+                ));
+            }
+            Instruction::Push(_) | Instruction::GetGlobal(_) => vec![(next, after(depth + 1))],
+            Instruction::Duplicate(count) => {
+                let depth = grow(needs(*count)?, *count)?;
+                vec![(next, after(grow(depth, *count)?))]
+            }
+            Instruction::BuildList(length) => vec![(next, after(grow(needs(*length)?, 1)?))],
+            Instruction::BuildRange(_) => vec![(next, after(needs(2)? + 1))],
+
+            Instruction::SetGlobal(_) => {
+                needs(1)?;
+                vec![(next, after(depth))]
+            }
+            Instruction::GetLocal(local) | Instruction::GetCell(local) => {
+                slot(*local)?;
+                vec![(next, after(depth + 1))]
+            }
+            Instruction::SetLocal(local) | Instruction::SetCell(local) => {
+                slot(*local)?;
+                needs(1)?;
+                vec![(next, after(depth))]
+            }
+            Instruction::BoxLocal(local) => {
+                slot(*local)?;
+                vec![(next, after(depth))]
+            }
+            Instruction::GetUpvalue(captured) => {
+                upvalue(*captured)?;
+                vec![(next, after(depth + 1))]
+            }
+            Instruction::SetUpvalue(captured) => {
+                upvalue(*captured)?;
+                needs(1)?;
+                vec![(next, after(depth))]
+            }
+            Instruction::GetProperty(_)
+            | Instruction::Identify
+            | Instruction::Negate
+            | Instruction::Not
+            | Instruction::GetIterator => vec![(next, after(needs(1)? + 1))],
+            Instruction::SetProperty(_)
+            | Instruction::Add
+            | Instruction::Subtract
+            | Instruction::Multiply
+            | Instruction::Divide
+            | Instruction::Modulo
+            | Instruction::Equal
+            | Instruction::NotEqual
+            | Instruction::Greater
+            | Instruction::GreaterEqual
+            | Instruction::Lesser
+            | Instruction::LesserEqual
+            | Instruction::And
+            | Instruction::Or => vec![(next, after(needs(2)? + 1))],
+
+            Instruction::Jump(target) => vec![(*target, after(depth))],
+            Instruction::JumpIfFalse(target) => {
+                let depth = needs(1)?;
+                // When a test for a variant passes, the value it tested is on top again.
+                let passed = State {
+                    depth,
+                    variant_fields: state.variant_fields,
+                };
+                vec![(*target, after(depth)), (next, passed)]
+            }
+            Instruction::JumpIfNil(target) => {
+                needs(1)?;
+                vec![(*target, after(depth)), (next, after(depth))]
+            }
+
+            Instruction::TestVariant(enum_type, variant) => {
+                needs(1)?;
+                let Some((_, fields)) = enum_type.variants.get(*variant) else {
+                    return Err(self.error(
+                        index,
+                        format!(
+                            "Invalid bytecode. The instruction at address {address} tests for variant {variant}, but '{}' has {}.",
+                            enum_type.name,
+                            enum_type.variants.len()
+                        ),
+                    ));
+                };
+                vec![(
+                    next,
+                    State {
+                        depth: depth + 1,
+                        variant_fields: Some(*fields),
+                    },
+                )]
+            }
+            Instruction::UnpackVariant => {
+                let Some(fields) = state.variant_fields else {
+                    return Err(self.error(
+                        index,
+                        format!(
+                            "Invalid bytecode. The instruction at address {address} unpacks a value that was not tested to be a variant."
+                        ),
+                    ));
+                };
+                vec![(next, after(grow(needs(1)?, fields)?))]
+            }
+
+            Instruction::IterateNext(target) => {
+                needs(1)?;
+                vec![(*target, after(depth)), (next, after(depth + 1))]
+            }
+
+            Instruction::Closure(function) => {
+                let depth = self.closure(index, function, depth)?;
+                vec![(next, after(depth))]
+            }
+            Instruction::BuildClass(_, methods) => {
+                vec![(next, after(grow(needs(methods.len())?, 1)?))]
+            }
+            Instruction::Call(argument_count) => vec![(next, after(call(*argument_count)?))],
+            Instruction::CallWithKeywords(argument_count, keywords)
+            | Instruction::Spawn(argument_count, keywords) => {
+                if keywords.len() > *argument_count {
+                    return Err(self.error(
+                        index,
+                        format!(
+                            "Invalid bytecode. The call at address {address} has more keywords than arguments."
+                        ),
+                    ));
+                }
+                vec![(next, after(call(*argument_count)?))]
+            }
+
+            Instruction::AssertFailed(_, negated) => {
+                needs(if negated.is_some() { 3 } else { 1 })?;
+                vec![]
+            }
+
+            Instruction::Continue => vec![(next, after(depth))],
+        };
+        Ok(successors)
+    }
+
+    /// Checks a function before the closure that captures it is built, and returns the
+    /// depth after its defaults are replaced by the closure.
+    fn closure(&self, index: usize, function: &Function, depth: usize) -> Result<usize, Error> {
+        let address = self.addresses[index];
+        let signature = &function.signature;
+        if signature.required > signature.arity() {
+            return Err(self.error(
+                index,
+                format!(
+                    "Invalid bytecode. '{}' at address {address} requires more parameters than it has.",
+                    function.name
+                ),
+            ));
+        }
+        for capture in &function.captures {
+            let valid = match capture {
+                Capture::Local(slot) => *slot < depth,
+                Capture::Upvalue(upvalue) => *upvalue < self.upvalue_count,
+            };
+            if !valid {
+                return Err(self.error(
+                    index,
+                    format!(
+                        "Invalid bytecode. '{}' at address {address} captures a variable that does not exist.",
+                        function.name
+                    ),
+                ));
+            }
+        }
+
+        // The callee and its arguments, with the rest parameter in the slot after them.
+        let depth_in_function = 1 + signature.arity() + signature.variadic() as usize;
+        Verifier::new(
+            &function.chunk,
+            depth_in_function,
+            function.captures.len(),
+            function.generator,
+        )?
+        .run()?;
+
+        let defaults = signature.arity() - signature.required;
+        if depth < defaults {
+            return Err(self.error(
+                index,
+                format!(
+                    "Invalid bytecode. The instruction at address {address} takes {defaults} default(s), but the stack holds {depth}."
+                ),
+            ));
+        }
+        Ok(depth - defaults + 1)
+    }
+
+    /// Finds the instruction at `address`, which execution reaches from the one at `from`.
+    fn index_of(&self, from: usize, address: usize) -> Result<usize, Error> {
+        if address == self.chunk.len() {
+            return Err(self.error(
+                from,
+                "Invalid bytecode. Execution can run past the end of the code.".to_string(),
+            ));
+        }
+        self.addresses
+            .binary_search(&address)
+            .map_err(|_| {
+                self.error(
+                    from,
+                    format!(
+                        "Invalid bytecode. The instruction at address {} jumps to {address}, which is not the start of an instruction.",
+                        self.addresses[from]
+                    ),
+                )
+            })
+    }
+
+    fn error(&self, index: usize, message: String) -> Error {
+        Self::error_at(self.chunk, self.addresses[index], message)
+    }
+
+    fn error_at(chunk: &Chunk, address: usize, message: String) -> Error {
+        Error::new(
+            ErrorKind::Bytecode,
+            message,
+            Some(chunk.get_position(address)),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use crate::{
+        backend::{
+            chunk::Chunk,
+            instruction::{Constant, Instruction},
+        },
+        common::{
+            object::{Object, Variant},
+            position::Position,
+        },
+        compile_source,
+    };
+
+    use super::verify;
+
+    fn chunk(instructions: Vec<Instruction>) -> Chunk {
+        let mut chunk = Chunk::new();
+        for instruction in instructions {
+            chunk.add_instruction(instruction, Position::new(0, 1, 0, 0));
+        }
+        chunk
+    }
+
+    fn message(chunk: &Chunk) -> String {
+        verify(chunk).err().unwrap().message
+    }
+
+    #[test]
+    fn accepts_compiled_code() {
+        let source =
+            "enum E { A, B(x) }\nvar v = E.B(1)\nif v is E.B(x) { print x } else { print 0 }\n";
+        assert!(verify(&compile_source(source).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_code() {
+        assert!(message(&Chunk::new()).contains("has no code"));
+    }
+
+    #[test]
+    fn rejects_a_jump_into_an_instruction() {
+        let chunk = chunk(vec![
+            Instruction::Jump(1),
+            Instruction::Push(Object::Nil),
+            Instruction::Return,
+        ]);
+        assert!(message(&chunk).contains("which is not the start of an instruction"));
+    }
+
+    #[test]
+    fn rejects_paths_with_different_depths() {
+        let mut chunk = chunk(vec![
+            Instruction::Push(Object::Boolean(true)),
+            Instruction::JumpIfFalse(0),
+            Instruction::Push(Object::Nil),
+        ]);
+        let target = chunk.add_instruction(Instruction::Return, Position::new(0, 1, 0, 0));
+        chunk.patch_jump(2, target);
+        assert!(message(&chunk).contains("depending on the path taken"));
+    }
+
+    #[test]
+    fn rejects_code_that_runs_past_its_end() {
+        let chunk = chunk(vec![Instruction::Push(Object::Nil)]);
+        assert!(message(&chunk).contains("run past the end"));
+    }
+
+    #[test]
+    fn rejects_a_variant_that_does_not_match_its_enum() {
+        let source = "enum E { A, B(x) }\nvar v = E.A\nif v is E.B(x) { print x }\n";
+        let mut chunk = compile_source(source).unwrap();
+        for constant in &mut chunk.constants {
+            if let Constant::Value(Object::Variant(variant)) = constant {
+                let variant = Variant::new(variant.enum_type.clone(), 1, Vec::new());
+                *constant = Constant::Value(Object::Variant(Rc::new(variant)));
+            }
+        }
+        assert!(message(&chunk).contains("does not match its declaration"));
+    }
+
+    /// A count the stack can not hold, which overflows if anything is added to it.
+    fn rejects_overflowing(instruction: Instruction) {
+        let chunk = chunk(vec![
+            Instruction::Push(Object::Nil),
+            instruction,
+            Instruction::Return,
+        ]);
+        assert!(message(&chunk).contains("but the stack holds 2"));
+    }
+
+    #[test]
+    fn rejects_a_call_with_too_many_arguments() {
+        rejects_overflowing(Instruction::Call(usize::MAX));
+    }
+
+    #[test]
+    fn rejects_a_keyword_call_with_too_many_arguments() {
+        rejects_overflowing(Instruction::CallWithKeywords(usize::MAX, Vec::new()));
+    }
+
+    #[test]
+    fn rejects_a_spawn_with_too_many_arguments() {
+        rejects_overflowing(Instruction::Spawn(usize::MAX, Vec::new()));
+    }
+
+    #[test]
+    fn rejects_duplicating_more_than_the_stack_holds() {
+        rejects_overflowing(Instruction::Duplicate(usize::MAX));
+    }
+}
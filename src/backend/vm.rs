@@ -1,279 +1,1650 @@
-use crate::common::{
-    error::{Error, ErrorKind},
-    object::Object,
+use std::{cell::RefCell, cmp::Ordering, collections::VecDeque, rc::Rc};
+
+use crate::{
+    common::{
+        bigint::{BigInt, MAX_SAFE_INTEGER},
+        error::{Error, ErrorKind, StackFrame, StackTrace},
+        interner::{Interner, Str, StrMap},
+        object::{
+            BoundMethod, Capture, Class, Closure, Function, Generator, GeneratorState, Instance,
+            IteratorState, Object, Range, Signature, Variant,
+        },
+        position::Position,
+    },
+    stdlib::{
+        self,
+        random::Random,
+        time::{Clock, SystemClock},
+    },
 };
 
-use super::{chunk::Chunk, instruction::Instruction};
+use super::{
+    chunk::Chunk,
+    heap::{Gc, Heap},
+    instruction::{Constant, OpCode},
+    trace::Trace,
+    verifier,
+};
 
-pub(crate) struct VirtualMachine {
+/// How deeply calls can nest by default.
+const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+/// How many values the stack of a fiber can hold by default.
+const DEFAULT_MAX_STACK_SIZE: usize = 1_000_000;
+/// How deeply calls from native code can nest. Each one runs the virtual machine again on
+/// the native stack, which is far smaller than the limits above.
+const MAX_NATIVE_CALL_DEPTH: usize = 200;
+
+/// Why `run` stopped executing instructions.
+#[derive(PartialEq)]
+enum Halt {
+    /// The frame count dropped back to the depth `run` was called with.
+    Returned,
+    /// The running fiber blocked, and its frame is set to retry the call.
+    Blocked,
+}
+
+struct CallFrame {
+    closure: Rc<Closure>,
     ip: usize,
+    base: usize,
+    /// The generator this frame runs, which it is saved back into when it yields.
+    generator: Option<Rc<RefCell<Generator>>>,
+}
+
+impl CallFrame {
+    fn new(closure: Rc<Closure>, base: usize) -> Self {
+        Self {
+            closure,
+            ip: 0,
+            base,
+            generator: None,
+        }
+    }
+}
+
+/// A fiber that is not running. The running fiber's frames and stack live in the virtual
+/// machine itself. A blocked fiber retries the call it blocked on when it is resumed.
+struct Fiber {
+    id: usize,
+    frames: Vec<CallFrame>,
     stack: Vec<Object>,
 }
 
+pub(crate) struct VirtualMachine {
+    frames: Vec<CallFrame>,
+    stack: Vec<Object>,
+    globals: StrMap<Object>,
+    interner: Interner,
+    clock: Box<dyn Clock>,
+    random: Random,
+    fibers: VecDeque<Fiber>,
+    fiber_id: usize,
+    fiber_count: usize,
+    blocked: bool,
+    channel_operations: usize,
+    main_finished: bool,
+    heap: Heap,
+    trace: Option<Trace>,
+    max_call_depth: usize,
+    max_stack_size: usize,
+    /// The file the script was read from, for stack traces.
+    file: String,
+    /// How many calls from native code into the virtual machine are running.
+    native_calls: usize,
+    /// Fibers blocked inside a call from native code while the other fibers run, which are
+    /// roots like the fibers waiting in `fibers`.
+    waiting_fibers: Vec<Fiber>,
+}
+
 impl VirtualMachine {
     pub(crate) fn new() -> Self {
+        let interner = Interner::default();
         Self {
-            ip: 0,
+            frames: Vec::new(),
             stack: Vec::new(),
+            globals: stdlib::globals()
+                .into_iter()
+                .map(|(name, object)| (interner.intern(&name), object))
+                .collect(),
+            interner,
+            clock: Box::new(SystemClock::new()),
+            random: Random::from_entropy(),
+            fibers: VecDeque::new(),
+            fiber_id: 0,
+            fiber_count: 0,
+            blocked: false,
+            channel_operations: 0,
+            main_finished: false,
+            heap: Heap::new(),
+            trace: None,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack_size: DEFAULT_MAX_STACK_SIZE,
+            file: "<input>".to_string(),
+            native_calls: 0,
+            waiting_fibers: Vec::new(),
         }
     }
 
+    pub(crate) fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    pub(crate) fn clock(&mut self) -> &mut dyn Clock {
+        self.clock.as_mut()
+    }
+
+    pub(crate) fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    /// The interner the compiler should intern names and literals with, so they are shared
+    /// with the names this virtual machine already knows.
+    pub(crate) fn interner(&self) -> Interner {
+        self.interner.clone()
+    }
+
+    /// How the global `name` prints, for tests to compare against.
+    #[cfg(test)]
+    pub(crate) fn global(&self, name: &str) -> Option<String> {
+        let global = self.globals.get(&self.interner.intern(name));
+        global.map(Object::to_string)
+    }
+
+    pub(crate) fn heap(&mut self) -> &mut Heap {
+        &mut self.heap
+    }
+
+    /// Limits how deeply calls can nest before a script fails with a stack overflow.
+    pub(crate) fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// Limits how many values the stack of a fiber can hold before a script fails with a
+    /// stack overflow.
+    pub(crate) fn set_max_stack_size(&mut self, max_stack_size: usize) {
+        self.max_stack_size = max_stack_size;
+    }
+
+    pub(crate) fn set_file(&mut self, file: String) {
+        self.file = file;
+    }
+
+    /// Records every instruction executed from now on, or stops recording them.
+    pub(crate) fn set_trace(&mut self, trace: Option<Trace>) {
+        self.trace = trace;
+    }
+
+    /// Called by a native function that can not finish yet. The running fiber is suspended
+    /// once the native function returns, and the call is retried when the fiber resumes.
+    pub(crate) fn block_fiber(&mut self) {
+        self.blocked = true;
+    }
+
+    /// Called by a native function that may have unblocked other fibers.
+    pub(crate) fn wake_fibers(&mut self) {
+        self.channel_operations += 1;
+    }
+
     pub(crate) fn interpret(&mut self, chunk: Chunk) -> Result<(), Error> {
-        self.ip = 0;
-        self.stack = Vec::new();
-        self.run(&chunk)
+        verifier::verify(&chunk)?;
+        let script = Rc::new(Closure::new(
+            Rc::new(Function::new(
+                "<script>".to_string(),
+                Signature::default(),
+                chunk,
+                Vec::new(),
+                false,
+            )),
+            Vec::new(),
+            Vec::new(),
+        ));
+        self.stack = vec![Object::Function(script.clone())];
+        self.frames = vec![CallFrame::new(script, 0)];
+        self.fibers.clear();
+        self.waiting_fibers.clear();
+        self.fiber_id = 0;
+        self.main_finished = false;
+        let result = self.schedule();
+        if let Some(trace) = &mut self.trace {
+            trace.flush().or(Err(Error::new(
+                ErrorKind::System,
+                "Could not write the trace.".to_string(),
+                None,
+            )))?;
+        }
+        result
     }
 
-    fn run(&mut self, chunk: &Chunk) -> Result<(), Error> {
-        while chunk.ip_is_valid(self.ip) {
-            #[cfg(feature = "debug_trace_execution")]
-            {
-                print!("stack [");
-                for object in &self.stack {
-                    print!(" {object},");
+    /// Runs fibers round-robin, switching whenever the running fiber blocks, until every
+    /// fiber finishes, including the ones still running when the main fiber finishes.
+    fn schedule(&mut self) -> Result<(), Error> {
+        let mut idle_fibers = 0;
+        loop {
+            let channel_operations = self.channel_operations;
+            let blocked = self.run(0)? == Halt::Blocked;
+            if !blocked && self.fiber_id == 0 {
+                self.main_finished = true;
+            }
+            if !blocked && self.fibers.is_empty() {
+                return Ok(());
+            }
+
+            if blocked && self.channel_operations == channel_operations {
+                idle_fibers += 1;
+                if idle_fibers > self.fibers.len() {
+                    return Err(self.deadlock_error());
                 }
-                println!(" ]");
-                chunk.debug_instruction(self.ip);
+            } else {
+                idle_fibers = 0;
+            }
+
+            if blocked {
+                let fiber = self.take_fiber();
+                self.fibers.push_back(fiber);
             }
+            let fiber = self.fibers.pop_front().unwrap();
+            self.load_fiber(fiber);
+        }
+    }
+
+    /// Gives every other fiber a turn while the running fiber is blocked inside a call from
+    /// native code, where it can not be suspended.
+    fn run_other_fibers(&mut self) -> Result<(), Error> {
+        let channel_operations = self.channel_operations;
+        let current = self.take_fiber();
+        self.waiting_fibers.push(current);
+        for _ in 0..self.fibers.len() {
+            let fiber = self.fibers.pop_front().unwrap();
+            self.load_fiber(fiber);
+            if self.run(0)? == Halt::Blocked {
+                let fiber = self.take_fiber();
+                self.fibers.push_back(fiber);
+            } else if self.fiber_id == 0 {
+                self.main_finished = true;
+            }
+        }
+        let current = self.waiting_fibers.pop().unwrap();
+        self.load_fiber(current);
+        if self.channel_operations == channel_operations {
+            Err(self.deadlock_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn take_fiber(&mut self) -> Fiber {
+        Fiber {
+            id: self.fiber_id,
+            frames: std::mem::take(&mut self.frames),
+            stack: std::mem::take(&mut self.stack),
+        }
+    }
+
+    fn load_fiber(&mut self, fiber: Fiber) {
+        self.fiber_id = fiber.id;
+        self.frames = fiber.frames;
+        self.stack = fiber.stack;
+    }
+
+    /// Frees the heap values the program can no longer reach. The roots are the stacks and
+    /// frames of every fiber and the globals; everything else is reached through them.
+    fn collect_garbage(&mut self) {
+        let Self {
+            heap,
+            frames,
+            stack,
+            globals,
+            fibers,
+            waiting_fibers,
+            ..
+        } = self;
+        heap.collect(|tracer| {
+            let fibers = fibers
+                .iter()
+                .chain(waiting_fibers.iter())
+                .map(|fiber| (&fiber.frames, &fiber.stack));
+            for (frames, stack) in fibers.chain([(&*frames, &*stack)]) {
+                for frame in frames {
+                    tracer.closure(&frame.closure);
+                    if let Some(generator) = &frame.generator {
+                        tracer.generator(generator);
+                    }
+                }
+                for object in stack {
+                    tracer.object(object);
+                }
+            }
+            for global in globals.values() {
+                tracer.object(global);
+            }
+        });
+    }
+
+    /// The running fiber is blocked, so its frame is back at the start of the call it retries.
+    fn deadlock_error(&self) -> Error {
+        let frame = self.frame();
+        let position = frame.closure.function.chunk.get_position(frame.ip);
+        let message = if self.main_finished {
+            format!(
+                "Deadlock. The script finished while {} fiber(s) are blocked.",
+                self.fibers.len() + 1
+            )
+        } else {
+            "Deadlock. All fibers are blocked.".to_string()
+        };
+        Error::new(ErrorKind::Runtime, message, Some(position.clone()))
+            .with_stack_trace(self.stack_trace(&position))
+    }
+
+    /// Calls `callee` from native code and runs it to completion.
+    pub(crate) fn call_value(
+        &mut self,
+        callee: Object,
+        arguments: Vec<Object>,
+    ) -> Result<Object, Error> {
+        if self.native_calls >= MAX_NATIVE_CALL_DEPTH {
+            return Err(self.generate_error(format!(
+                "Stack overflow. Calls from operators and native functions nest deeper than {MAX_NATIVE_CALL_DEPTH}."
+            )));
+        }
+        self.native_calls += 1;
+        let result = self.run_call(callee, arguments);
+        self.native_calls -= 1;
+        result
+    }
+
+    fn run_call(&mut self, callee: Object, arguments: Vec<Object>) -> Result<Object, Error> {
+        let depth = self.frames.len();
+        let argument_count = arguments.len();
+        self.stack.push(callee.clone());
+        self.stack.extend(arguments);
+        self.call_object(callee.clone(), argument_count, &[])?;
+        while self.blocked {
+            self.blocked = false;
+            self.run_other_fibers()?;
+            self.call_object(callee.clone(), argument_count, &[])?;
+        }
+        if self.frames.len() > depth {
+            self.run(depth)?;
+        }
+        Ok(self.stack.pop().unwrap())
+    }
+
+    /// Executes instructions until the frame count drops back to `depth`, or until the fiber
+    /// blocks when `depth` is 0.
+    fn run(&mut self, depth: usize) -> Result<Halt, Error> {
+        loop {
+            let Some((address, opcode)) = self.read_opcode() else {
+                // Verified code always returns before its end.
+                let name = &self.frame().closure.function.name;
+                return Err(Error::new(
+                    ErrorKind::Bytecode,
+                    format!("Invalid bytecode. Execution ran past the end of '{name}'."),
+                    None,
+                ));
+            };
+            // Every value pushed so far is checked before the next instruction runs, which
+            // pushes at most a few more.
+            if self.stack.len() > self.max_stack_size {
+                return Err(self.generate_error(format!(
+                    "Stack overflow. The stack holds more than {} values.",
+                    self.max_stack_size
+                )));
+            }
+            // Between instructions, every live value is on a stack, in a frame or a global.
+            // Native code that calls back into the virtual machine keeps the values it still
+            // needs on the stack too, as the callee and its arguments.
+            if self.heap.should_collect() {
+                self.collect_garbage();
+            }
+
+            if let Some(trace) = &mut self.trace {
+                let frame = self.frames.last().unwrap();
+                trace
+                    .record(&frame.closure.function, address, &self.stack[frame.base..])
+                    .or(Err(Error::new(
+                        ErrorKind::System,
+                        "Could not write the trace.".to_string(),
+                        None,
+                    )))?;
+            }
+
+            match opcode {
+                OpCode::Return => {
+                    let result = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    if let Some(generator) = frame.generator {
+                        generator.borrow_mut().state = GeneratorState::Finished;
+                    }
+                    self.stack.truncate(frame.base);
+                    self.stack.push(result);
+                    if self.frames.len() == depth {
+                        return Ok(Halt::Returned);
+                    }
+                }
 
-            match self.get_instruction(chunk) {
-                Instruction::Return => break,
+                OpCode::Yield => {
+                    let result = self.stack.pop().unwrap();
+                    let frame = self.frames.pop().unwrap();
+                    let generator = frame.generator.unwrap();
+                    let mut generator = generator.borrow_mut();
+                    generator.ip = frame.ip;
+                    generator.slots = self.stack.split_off(frame.base);
+                    generator.state = GeneratorState::Suspended;
+                    self.stack.push(result);
+                    if self.frames.len() == depth {
+                        return Ok(Halt::Returned);
+                    }
+                }
 
-                Instruction::Print => println!("{}", self.stack.pop().unwrap()),
+                OpCode::Print => {
+                    let object = self.stack.pop().unwrap();
+                    println!("{}", self.stringify(&object)?);
+                }
 
-                Instruction::Push(object) => self.stack.push(object),
+                OpCode::Push => {
+                    let index = self.read_operand();
+                    let Constant::Value(object) = self.chunk().constant(index) else {
+                        unreachable!()
+                    };
+                    self.stack.push(object.clone());
+                }
 
-                Instruction::Pop => {
+                OpCode::Pop => {
                     self.stack.pop().unwrap();
                 }
 
-                Instruction::Identify => {
+                OpCode::Duplicate => {
+                    let count = self.read_operand();
+                    let start = self.stack.len() - count;
+                    self.stack.extend_from_within(start..);
+                }
+
+                OpCode::BuildList => {
+                    let length = self.read_operand();
+                    let elements = self.stack.split_off(self.stack.len() - length);
+                    self.stack
+                        .push(Object::List(self.heap.allocate(RefCell::new(elements))));
+                }
+
+                OpCode::BuildRange => {
+                    let inclusive = self.read_operand() != 0;
+                    let end = self.stack.pop().unwrap();
+                    let start = self.stack.pop().unwrap();
+                    match (&start, &end) {
+                        (Object::Number(start), Object::Number(end)) => self
+                            .stack
+                            .push(Object::Range(Range::new(*start, *end, inclusive))),
+                        _ => {
+                            return Err(self.generate_error(format!(
+                                "Invalid range. Range bounds must be numbers, found '{start}' and '{end}'."
+                            )))
+                        }
+                    }
+                }
+
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
                     let object = self.stack.pop().unwrap();
-                    if let Object::Number(num) = object {
-                        self.stack.push(Object::Number(num));
+                    self.globals.insert(name, object);
+                }
+
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    if let Some(object) = self.globals.get(&name) {
+                        self.stack.push(object.clone());
+                    } else {
+                        return Err(self.generate_error(format!(
+                            "Undefined identifier. '{name}' is not defined."
+                        )));
+                    }
+                }
+
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let object = self.stack.last().unwrap().clone();
+                    if let Some(global) = self.globals.get_mut(&name) {
+                        *global = object;
+                    } else {
+                        return Err(self.generate_error(format!(
+                            "Undefined identifier. '{name}' is not defined."
+                        )));
+                    }
+                }
+
+                OpCode::GetLocal => {
+                    let slot = self.read_operand();
+                    let base = self.frame().base;
+                    self.stack.push(self.stack[base + slot].clone());
+                }
+
+                OpCode::SetLocal => {
+                    let slot = self.read_operand();
+                    let base = self.frame().base;
+                    self.stack[base + slot] = self.stack.last().unwrap().clone();
+                }
+
+                OpCode::BoxLocal => {
+                    let slot = self.read_operand();
+                    let base = self.frame().base;
+                    let object = self.stack[base + slot].clone();
+                    self.stack[base + slot] =
+                        Object::Cell(self.heap.allocate(RefCell::new(object)));
+                }
+
+                OpCode::GetCell => {
+                    let slot = self.read_operand();
+                    let cell = self.get_cell(slot);
+                    let object = cell.borrow().clone();
+                    self.stack.push(object);
+                }
+
+                OpCode::SetCell => {
+                    let slot = self.read_operand();
+                    let cell = self.get_cell(slot);
+                    *cell.borrow_mut() = self.stack.last().unwrap().clone();
+                }
+
+                OpCode::GetUpvalue => {
+                    let index = self.read_operand();
+                    let object = self.frame().closure.upvalues[index].borrow().clone();
+                    self.stack.push(object);
+                }
+
+                OpCode::SetUpvalue => {
+                    let index = self.read_operand();
+                    let object = self.stack.last().unwrap().clone();
+                    *self.frame().closure.upvalues[index].borrow_mut() = object;
+                }
+
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.stack.pop().unwrap();
+                    let property = match &object {
+                        Object::Module(module) => module.members.get(name.as_str()).cloned(),
+                        Object::Match(matched) => matched.properties.get(name.as_str()).cloned(),
+                        Object::Enum(enum_type) => enum_type
+                            .find_variant(&name)
+                            .map(|index| enum_type.member(index)),
+                        Object::Instance(instance) => {
+                            let instance = instance.borrow();
+                            instance.fields.get(&name).cloned().or_else(|| {
+                                instance.class.methods.get(&name).map(|method| {
+                                    Object::BoundMethod(Rc::new(BoundMethod::new(
+                                        object.clone(),
+                                        method.clone(),
+                                    )))
+                                })
+                            })
+                        }
+                        _ => None,
+                    };
+                    if let Some(property) = property {
+                        self.stack.push(property);
                     } else {
-                        return Err(Error::new(
-                            ErrorKind::Runtime,
-                            format!("Invalid operator. Unary operator '-' is not defined for '{object}'."),
-                            Some(chunk.get_position(self.ip - 1)),
-                        ));
+                        return Err(self.generate_error(format!(
+                            "Undefined property. '{object}' has no property '{name}'."
+                        )));
                     }
                 }
 
-                Instruction::Negate => {
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.stack.pop().unwrap();
+                    let object = self.stack.pop().unwrap();
+                    if let Object::Instance(instance) = &object {
+                        instance.borrow_mut().fields.insert(name, value.clone());
+                        self.stack.push(value);
+                    } else {
+                        return Err(self.generate_error(format!(
+                            "Invalid assignment. Can not set property '{name}' on '{object}'."
+                        )));
+                    }
+                }
+
+                OpCode::Identify => {
+                    let object = self.stack.pop().unwrap();
+                    if let Object::Number(_) | Object::BigInt(_) = object {
+                        self.stack.push(object);
+                    } else {
+                        return Err(self.generate_error(format!(
+                            "Invalid operator. Unary operator '+' is not defined for '{object}'."
+                        )));
+                    }
+                }
+
+                OpCode::Negate => {
                     let object = self.stack.pop().unwrap();
                     if let Object::Number(num) = object {
                         self.stack.push(Object::Number(-num));
+                    } else if let Object::BigInt(integer) = &object {
+                        self.stack.push(Object::BigInt(Rc::new(integer.negate())));
+                    } else if let Some(result) = self.call_method(&object, "__neg__", Vec::new())? {
+                        self.stack.push(result);
                     } else {
-                        return Err(Error::new(
-                            ErrorKind::Runtime,
-                            format!("Invalid operator. Unary operator '-' is not defined for '{object}'."),
-                            Some(chunk.get_position(self.ip - 1)),
-                        ));
+                        return Err(self.generate_error(format!(
+                            "Invalid operator. Unary operator '-' is not defined for '{object}'."
+                        )));
                     }
                 }
 
-                Instruction::Not => {
+                OpCode::Not => {
                     let object = self.stack.pop().unwrap();
                     if let Object::Boolean(bool) = object {
                         self.stack.push(Object::Boolean(!bool));
                     } else {
-                        return Err(Error::new(
-                            ErrorKind::Runtime,
-                            format!("Invalid operator. Unary operator '!' is not defined for '{object}'."),
-                            Some(chunk.get_position(self.ip - 1)),
-                        ));
+                        return Err(self.generate_error(format!(
+                            "Invalid operator. Unary operator '!' is not defined for '{object}'."
+                        )));
                     }
                 }
 
-                Instruction::Add => {
+                OpCode::Add => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
-                        (Object::Number(a), Object::Number(b)) => {
-                            self.stack.push(Object::Number(a + b))
-                        }
                         (Object::String(a), Object::String(b)) => {
-                            self.stack.push(Object::String(format!("{a}{b}")))
-                        }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '+' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                            self.stack.push(Object::String(format!("{a}{b}").into()))
                         }
+                        _ => match Self::arithmetic(&a, &b, |a, b| a + b, BigInt::add) {
+                            Some(result) => self.stack.push(result),
+                            None => self.call_operator(&a, &b, "+")?,
+                        },
                     };
                 }
 
-                Instruction::Subtract => {
+                OpCode::Subtract => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    match (&a, &b) {
-                        (Object::Number(a), Object::Number(b)) => {
-                            self.stack.push(Object::Number(a - b))
-                        }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '-' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
-                        }
+                    match Self::arithmetic(&a, &b, |a, b| a - b, BigInt::subtract) {
+                        Some(result) => self.stack.push(result),
+                        None => self.call_operator(&a, &b, "-")?,
                     };
                 }
 
-                Instruction::Multiply => {
+                OpCode::Multiply => {
+                    let b = self.stack.pop().unwrap();
+                    let a = self.stack.pop().unwrap();
+                    match Self::arithmetic(&a, &b, |a, b| a * b, BigInt::multiply) {
+                        Some(result) => self.stack.push(result),
+                        None => self.call_operator(&a, &b, "*")?,
+                    };
+                }
+
+                OpCode::Divide => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
-                            self.stack.push(Object::Number(a * b))
-                        }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '*' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                            if b == &0. {
+                                return Err(self.generate_error(format!(
+                                    "Division by zero. Can not divide '{a}' by '0'."
+                                )));
+                            }
+                            self.stack.push(Object::Number(a / b))
                         }
+                        _ => self.divide(&a, &b, false)?,
                     };
                 }
 
-                Instruction::Divide => {
+                OpCode::Modulo => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
                             if b == &0. {
-                                return Err(Error::new(
-                                    ErrorKind::Runtime,
-                                    format!("Division by zero. Can not divide '{a}' by '0'."),
-                                    Some(chunk.get_position(self.ip - 1)),
-                                ));
+                                return Err(self.generate_error(format!(
+                                    "Division by zero. Can not divide '{a}' by '0'."
+                                )));
                             }
-                            self.stack.push(Object::Number(a / b))
-                        }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '/' is not defined for '{a}' and '{b}'"),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                            self.stack.push(Object::Number(a % b))
                         }
+                        _ => self.divide(&a, &b, true)?,
                     };
                 }
 
-                Instruction::Equal => {
+                OpCode::Equal => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(Object::Boolean(a == b));
+                    let equal = self.equals(&a, &b)?;
+                    self.stack.push(Object::Boolean(equal));
                 }
 
-                Instruction::NotEqual => {
+                OpCode::NotEqual => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
-                    self.stack.push(Object::Boolean(a != b));
+                    let equal = self.equals(&a, &b)?;
+                    self.stack.push(Object::Boolean(!equal));
                 }
 
-                Instruction::Greater => {
+                OpCode::Greater => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
                             self.stack.push(Object::Boolean(a > b))
                         }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '>' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                        _ if a.as_number().is_some() && b.as_number().is_some() => {
+                            let ordering = Object::compare_numbers(&a, &b);
+                            self.stack
+                                .push(Object::Boolean(matches!(ordering, Some(Ordering::Greater))))
                         }
+                        _ => self.call_operator(&a, &b, ">")?,
                     };
                 }
 
-                Instruction::GreaterEqual => {
+                OpCode::GreaterEqual => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
                             self.stack.push(Object::Boolean(a >= b))
                         }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '>=' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                        _ if a.as_number().is_some() && b.as_number().is_some() => {
+                            let ordering = Object::compare_numbers(&a, &b);
+                            self.stack.push(Object::Boolean(matches!(
+                                ordering,
+                                Some(Ordering::Greater | Ordering::Equal)
+                            )))
                         }
+                        _ => self.call_operator(&a, &b, ">=")?,
                     };
                 }
 
-                Instruction::Lesser => {
+                OpCode::Lesser => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
                             self.stack.push(Object::Boolean(a < b));
                         }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '<' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                        _ if a.as_number().is_some() && b.as_number().is_some() => {
+                            let ordering = Object::compare_numbers(&a, &b);
+                            self.stack
+                                .push(Object::Boolean(matches!(ordering, Some(Ordering::Less))))
                         }
+                        _ => self.call_operator(&a, &b, "<")?,
                     };
                 }
 
-                Instruction::LesserEqual => {
+                OpCode::LesserEqual => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     match (&a, &b) {
                         (Object::Number(a), Object::Number(b)) => {
                             self.stack.push(Object::Boolean(a <= b))
                         }
-                        _ => {
-                            return Err(Error::new(
-                                ErrorKind::Runtime,
-                                format!("Invalid operator. Binary operator '<=' is not defined for '{a}' and '{b}'."),
-                                Some(chunk.get_position(self.ip - 1)),
-                            ))
+                        _ if a.as_number().is_some() && b.as_number().is_some() => {
+                            let ordering = Object::compare_numbers(&a, &b);
+                            self.stack.push(Object::Boolean(matches!(
+                                ordering,
+                                Some(Ordering::Less | Ordering::Equal)
+                            )))
                         }
+                        _ => self.call_operator(&a, &b, "<=")?,
                     };
                 }
 
-                Instruction::And => {
+                OpCode::And => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     self.stack
                         .push(Object::Boolean(a.is_truthy() && b.is_truthy()))
                 }
 
-                Instruction::Or => {
+                OpCode::Or => {
                     let b = self.stack.pop().unwrap();
                     let a = self.stack.pop().unwrap();
                     self.stack
                         .push(Object::Boolean(a.is_truthy() || b.is_truthy()))
                 }
 
-                Instruction::JumpIfFalse(ip) => {
+                OpCode::Jump => {
+                    let ip = self.read_address();
+                    self.frame_mut().ip = ip;
+                }
+
+                OpCode::JumpIfFalse => {
+                    let ip = self.read_address();
                     let a = self.stack.pop().unwrap();
                     if !a.is_truthy() {
-                        self.ip = ip;
+                        self.frame_mut().ip = ip;
                     }
                 }
 
-                Instruction::Continue => {}
+                OpCode::TestVariant => {
+                    let Constant::Enum(enum_type) = self.read_constant() else {
+                        unreachable!()
+                    };
+                    let enum_type = enum_type.clone();
+                    let index = self.read_operand();
+                    let matches = matches!(
+                        self.stack.last(),
+                        Some(Object::Variant(variant))
+                            if Rc::ptr_eq(&variant.enum_type, &enum_type) && variant.index == index
+                    );
+                    self.stack.push(Object::Boolean(matches));
+                }
+
+                OpCode::UnpackVariant => {
+                    if let Some(Object::Variant(variant)) = self.stack.pop() {
+                        self.stack.extend(variant.values.iter().cloned());
+                    }
+                }
+
+                OpCode::JumpIfNil => {
+                    let ip = self.read_address();
+                    if let Some(Object::Nil) = self.stack.last() {
+                        self.frame_mut().ip = ip;
+                    }
+                }
+
+                OpCode::GetIterator => {
+                    // The iterable stays on the stack while `__iter__` runs, so it is not
+                    // collected.
+                    let iterable = self.stack.last().unwrap().clone();
+                    let iterator = match self.call_method(&iterable, "__iter__", Vec::new())? {
+                        Some(iterator) => self.iterator(iterator)?,
+                        None => self.iterator(iterable)?,
+                    };
+                    self.stack.pop();
+                    self.stack
+                        .push(Object::Iterator(Rc::new(RefCell::new(iterator))));
+                }
+
+                OpCode::IterateNext => {
+                    let ip = self.read_address();
+                    let iterator = self.stack.last().unwrap().clone();
+                    if let Some(element) = self.next_element(iterator)? {
+                        self.stack.push(element);
+                    } else {
+                        self.frame_mut().ip = ip;
+                    }
+                }
+
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        unreachable!()
+                    };
+                    let function = function.clone();
+                    let upvalues = function
+                        .captures
+                        .iter()
+                        .map(|capture| match capture {
+                            Capture::Local(slot) => self.get_cell(*slot),
+                            Capture::Upvalue(index) => self.frame().closure.upvalues[*index],
+                        })
+                        .collect();
+                    let signature = &function.signature;
+                    let defaults = self
+                        .stack
+                        .split_off(self.stack.len() - (signature.arity() - signature.required));
+                    self.stack.push(Object::Function(Rc::new(Closure::new(
+                        function, upvalues, defaults,
+                    ))));
+                }
+
+                OpCode::BuildClass => {
+                    let Constant::Class(name, method_names) = self.read_constant() else {
+                        unreachable!()
+                    };
+                    let (name, method_names) = (name.clone(), method_names.clone());
+                    let closures = self.stack.split_off(self.stack.len() - method_names.len());
+                    let methods = method_names
+                        .into_iter()
+                        .zip(closures)
+                        .filter_map(|(name, closure)| match closure {
+                            Object::Function(closure) => Some((name, closure)),
+                            _ => None,
+                        })
+                        .collect();
+                    self.stack
+                        .push(Object::Class(Rc::new(Class::new(name, methods))));
+                }
+
+                OpCode::Call => {
+                    let argument_count = self.read_operand();
+                    let callee = self.stack[self.stack.len() - argument_count - 1].clone();
+                    self.call_object(callee, argument_count, &[])?;
+                }
+
+                OpCode::CallWithKeywords => {
+                    let argument_count = self.read_operand();
+                    let (closure, index) = self.read_shared_constant();
+                    let Constant::Keywords(keywords) = closure.function.chunk.constant(index)
+                    else {
+                        unreachable!()
+                    };
+                    let callee = self.stack[self.stack.len() - argument_count - 1].clone();
+                    self.call_object(callee, argument_count, keywords)?;
+                }
+
+                OpCode::Spawn => {
+                    let argument_count = self.read_operand();
+                    let (closure, index) = self.read_shared_constant();
+                    let Constant::Keywords(keywords) = closure.function.chunk.constant(index)
+                    else {
+                        unreachable!()
+                    };
+                    let callee = self.stack[self.stack.len() - argument_count - 1].clone();
+                    if !matches!(&callee, Object::Function(closure) if !closure.function.generator)
+                    {
+                        return Err(self.generate_error(format!(
+                            "Invalid spawn. Only functions can be spawned, found '{callee}'."
+                        )));
+                    }
+                    self.call_object(callee, argument_count, keywords)?;
+                    let mut frame = self.frames.pop().unwrap();
+                    let stack = self.stack.split_off(frame.base);
+                    frame.base = 0;
+                    self.fiber_count += 1;
+                    self.fibers.push_back(Fiber {
+                        id: self.fiber_count,
+                        frames: vec![frame],
+                        stack,
+                    });
+                    self.stack.push(Object::Fiber(self.fiber_count));
+                }
+
+                OpCode::AssertFailed => {
+                    let Constant::Assertion(source, negated) = self.read_constant() else {
+                        unreachable!()
+                    };
+                    let (source, negated) = (source.clone(), negated.clone());
+                    let message = self.stack.pop().unwrap();
+                    let mut error = format!("Assertion failed. {source}");
+                    if let Some(negated) = negated {
+                        let b = self.stack.pop().unwrap();
+                        let a = self.stack.pop().unwrap();
+                        error.push_str(&format!(" ({a} {negated} {b})"));
+                    }
+                    error.push('.');
+                    if message != Object::Nil {
+                        error.push_str(&format!(" {message}"));
+                    }
+                    return Err(self.generate_error(error));
+                }
+
+                OpCode::Continue => {}
+            }
+
+            if self.blocked {
+                self.blocked = false;
+                // The call is retried once the fiber runs again.
+                self.frame_mut().ip = address;
+                if depth == 0 {
+                    return Ok(Halt::Blocked);
+                }
+                self.run_other_fibers()?;
             }
         }
+    }
 
+    /// Calls the callee sitting below `argument_count` arguments on the stack. The last
+    /// `keywords.len()` arguments are passed by the matching keyword.
+    fn call_object(
+        &mut self,
+        callee: Object,
+        argument_count: usize,
+        keywords: &[String],
+    ) -> Result<(), Error> {
+        match callee {
+            Object::Function(closure) => {
+                let base = self.stack.len() - argument_count - 1;
+                if closure.function.signature.is_simple() && keywords.is_empty() {
+                    self.check_arity(
+                        &closure.function.name,
+                        closure.function.signature.arity(),
+                        argument_count,
+                    )?;
+                } else {
+                    self.bind_arguments(&closure, argument_count, keywords)?;
+                }
+                if closure.function.generator {
+                    let slots = self.stack.split_off(base);
+                    let generator = Generator::new(closure, slots);
+                    self.stack
+                        .push(Object::Generator(Rc::new(RefCell::new(generator))));
+                } else {
+                    self.push_frame(CallFrame::new(closure, base))?;
+                }
+                Ok(())
+            }
+            Object::Generator(generator) => {
+                let name = format!("<generator {}>", generator.borrow().closure.function.name);
+                self.check_arity(&name, 0, argument_count)?;
+                self.resume_generator(generator, self.stack.len() - 1)
+            }
+            Object::NativeFunction(native) => {
+                if !keywords.is_empty() {
+                    return Err(self.generate_error(format!(
+                        "Invalid arguments. '{}' does not accept keyword arguments.",
+                        native.name
+                    )));
+                }
+                self.check_arity(&native.name, native.arity, argument_count)?;
+                // The callee and arguments stay on the stack in case the call blocks and has to
+                // be retried.
+                let arguments = self.stack[self.stack.len() - argument_count..].to_vec();
+                let result = (native.function)(self, arguments)
+                    .map_err(|message| self.generate_error(message))?;
+                if !self.blocked {
+                    self.stack.truncate(self.stack.len() - argument_count - 1);
+                    self.stack.push(result);
+                }
+                Ok(())
+            }
+            Object::Class(class) => {
+                let base = self.stack.len() - argument_count - 1;
+                let instance = Object::Instance(
+                    self.heap
+                        .allocate(RefCell::new(Instance::new(class.clone()))),
+                );
+                self.stack[base] = instance;
+                match class.methods.get(&self.interner.intern("init")) {
+                    Some(initializer) => self.call_object(
+                        Object::Function(initializer.clone()),
+                        argument_count,
+                        keywords,
+                    ),
+                    None => self.check_arity(&class.name, 0, argument_count),
+                }
+            }
+            Object::BoundMethod(bound) => {
+                let base = self.stack.len() - argument_count - 1;
+                self.stack[base] = bound.receiver.clone();
+                self.call_object(
+                    Object::Function(bound.method.clone()),
+                    argument_count,
+                    keywords,
+                )
+            }
+            Object::Constructor(enum_type, index) => {
+                let name = enum_type.variant_name(index);
+                if !keywords.is_empty() {
+                    return Err(self.generate_error(format!(
+                        "Invalid arguments. '{name}' does not accept keyword arguments."
+                    )));
+                }
+                self.check_arity(&name, enum_type.variants[index].1, argument_count)?;
+                let values = self.stack.split_off(self.stack.len() - argument_count);
+                self.stack.pop();
+                self.stack.push(Object::Variant(Rc::new(Variant::new(
+                    enum_type, index, values,
+                ))));
+                Ok(())
+            }
+            _ => Err(self.generate_error(format!("Invalid call. '{callee}' is not callable."))),
+        }
+    }
+
+    /// Replaces the arguments on top of the stack with one value per parameter slot, filling
+    /// in keyword arguments, defaults and the rest parameter.
+    fn bind_arguments(
+        &mut self,
+        closure: &Closure,
+        argument_count: usize,
+        keywords: &[String],
+    ) -> Result<(), Error> {
+        let name = &closure.function.name;
+        let signature = &closure.function.signature;
+        let keyword_values = self.stack.split_off(self.stack.len() - keywords.len());
+        let positional_count = argument_count - keywords.len();
+        let positional_values = self.stack.split_off(self.stack.len() - positional_count);
+
+        let mut slots = vec![None; signature.arity()];
+        let mut rest = Vec::new();
+        for (i, value) in positional_values.into_iter().enumerate() {
+            if i < signature.arity() {
+                slots[i] = Some(value);
+            } else if signature.variadic() {
+                rest.push(value);
+            } else {
+                return Err(self.generate_error(format!(
+                    "Invalid arguments. '{name}' expects at most {} argument(s), found {positional_count}.",
+                    signature.arity()
+                )));
+            }
+        }
+        for (keyword, value) in keywords.iter().zip(keyword_values) {
+            match signature
+                .parameters
+                .iter()
+                .position(|parameter| parameter == keyword)
+            {
+                Some(i) if slots[i].is_some() => {
+                    return Err(self.generate_error(format!(
+                    "Invalid arguments. Parameter '{keyword}' of '{name}' is given more than once."
+                )))
+                }
+                Some(i) => slots[i] = Some(value),
+                None => {
+                    return Err(self.generate_error(format!(
+                        "Unknown parameter. '{name}' has no parameter named '{keyword}'."
+                    )))
+                }
+            }
+        }
+
+        let missing: Vec<String> = slots
+            .iter()
+            .zip(&signature.parameters)
+            .take(signature.required)
+            .filter(|(slot, _)| slot.is_none())
+            .map(|(_, parameter)| format!("'{parameter}'"))
+            .collect();
+        if !missing.is_empty() {
+            return Err(self.generate_error(format!(
+                "Missing argument. '{name}' is missing required parameter(s) {}.",
+                missing.join(", ")
+            )));
+        }
+
+        for (i, slot) in slots.into_iter().enumerate() {
+            let value = slot.unwrap_or_else(|| closure.defaults[i - signature.required].clone());
+            self.stack.push(value);
+        }
+        if signature.variadic() {
+            let rest = self.heap.allocate(RefCell::new(rest));
+            self.stack.push(Object::List(rest));
+        }
         Ok(())
     }
 
-    fn get_instruction(&mut self, chunk: &Chunk) -> Instruction {
-        self.ip += 1;
-        chunk.get_instruction(self.ip - 1)
+    fn get_cell(&mut self, slot: usize) -> Gc<RefCell<Object>> {
+        match &self.stack[self.frame().base + slot] {
+            Object::Cell(cell) => *cell,
+            object => {
+                let object = object.clone();
+                self.heap.allocate(RefCell::new(object))
+            }
+        }
+    }
+
+    /// Continues `generator` from its last `yield`, with its slots placed at `base`, where
+    /// the generator itself was called.
+    fn resume_generator(
+        &mut self,
+        generator: Rc<RefCell<Generator>>,
+        base: usize,
+    ) -> Result<(), Error> {
+        let mut state = generator.borrow_mut();
+        match state.state {
+            GeneratorState::Suspended => {}
+            GeneratorState::Running => {
+                return Err(self.generate_error(format!(
+                    "Running generator. '<generator {}>' can not resume itself.",
+                    state.closure.function.name
+                )))
+            }
+            GeneratorState::Finished => {
+                return Err(self.generate_error(format!(
+                "Finished generator. '<generator {}>' can not be resumed after it has returned.",
+                state.closure.function.name
+            )))
+            }
+        }
+        state.state = GeneratorState::Running;
+        self.stack.truncate(base);
+        self.stack.append(&mut state.slots);
+        let frame = CallFrame {
+            closure: state.closure.clone(),
+            ip: state.ip,
+            base,
+            generator: Some(generator.clone()),
+        };
+        drop(state);
+        self.push_frame(frame)
+    }
+
+    /// How a `for` loop steps through `iterable`. Instances are stepped through with
+    /// `__next__`, which ends the loop by returning nil.
+    fn iterator(&self, iterable: Object) -> Result<IteratorState, Error> {
+        Ok(match &iterable {
+            Object::Range(range) => IteratorState::Range(range.clone()),
+            Object::String(string) => IteratorState::String(string.chars().collect(), 0),
+            Object::List(list) => IteratorState::List(*list, 0),
+            Object::Function(_) | Object::NativeFunction(_) => IteratorState::Callable(iterable),
+            Object::Generator(generator) => IteratorState::Generator(generator.clone()),
+            Object::Instance(instance) if self.has_method(&iterable, "__next__") => {
+                let name = self.interner.intern("__next__");
+                let method = instance.borrow().class.methods[&name].clone();
+                let bound = BoundMethod::new(iterable.clone(), method);
+                IteratorState::Callable(Object::BoundMethod(Rc::new(bound)))
+            }
+            _ => {
+                return Err(self.generate_error(format!(
+                    "Invalid iterable. '{iterable}' can not be iterated over."
+                )))
+            }
+        })
+    }
+
+    fn next_element(&mut self, iterator: Object) -> Result<Option<Object>, Error> {
+        let Object::Iterator(iterator) = iterator else {
+            return Err(self.generate_error(format!(
+                "Invalid iterator. '{iterator}' is not an iterator."
+            )));
+        };
+        let callable = match &mut *iterator.borrow_mut() {
+            IteratorState::Callable(callable) => callable.clone(),
+            IteratorState::Generator(generator) => {
+                if generator.borrow().state == GeneratorState::Finished {
+                    return Ok(None);
+                }
+                Object::Generator(generator.clone())
+            }
+            state => return Ok(state.next_element()),
+        };
+        let element = self.call_value(callable.clone(), Vec::new())?;
+        if let Object::Generator(generator) = callable {
+            let finished = generator.borrow().state == GeneratorState::Finished;
+            return Ok(if finished { None } else { Some(element) });
+        }
+        if element == Object::Nil {
+            Ok(None)
+        } else {
+            Ok(Some(element))
+        }
+    }
+
+    /// Arithmetic on numbers and big integers. Integers whose result would not be exact as a
+    /// number are computed as big integers, and any other operands as numbers.
+    fn arithmetic(
+        a: &Object,
+        b: &Object,
+        number_operation: fn(f64, f64) -> f64,
+        integer_operation: fn(&BigInt, &BigInt) -> BigInt,
+    ) -> Option<Object> {
+        if let (Object::Number(a), Object::Number(b)) = (a, b) {
+            let result = number_operation(*a, *b);
+            // A rounded result can land on the limit itself, so only smaller ones are exact.
+            if result.abs() < MAX_SAFE_INTEGER
+                || !Object::is_safe_integer(*a)
+                || !Object::is_safe_integer(*b)
+            {
+                return Some(Object::Number(result));
+            }
+        }
+        match (a.as_integer(), b.as_integer()) {
+            (Some(a), Some(b)) => Some(Object::integer(integer_operation(&a, &b))),
+            _ => Some(Object::Number(number_operation(
+                a.as_number()?,
+                b.as_number()?,
+            ))),
+        }
+    }
+
+    /// Divides when a big integer is involved. Like numbers, `/` gives the exact quotient
+    /// when there is one and a number otherwise, while `%` takes the remainder of the
+    /// truncated quotient, which `div` gives.
+    fn divide(&mut self, a: &Object, b: &Object, remainder: bool) -> Result<(), Error> {
+        let operator = if remainder { "%" } else { "/" };
+        let (Some(x), Some(y)) = (a.as_number(), b.as_number()) else {
+            return self.call_operator(a, b, operator);
+        };
+        if y == 0. {
+            return Err(
+                self.generate_error(format!("Division by zero. Can not divide '{a}' by '0'."))
+            );
+        }
+        let result = match (a.as_integer(), b.as_integer()) {
+            (Some(a), Some(b)) => match a.divide(&b).unwrap() {
+                (_, rest) if remainder => Object::integer(rest),
+                (quotient, rest) if rest.is_zero() => Object::integer(quotient),
+                _ => Object::Number(x / y),
+            },
+            _ if remainder => Object::Number(x % y),
+            _ => Object::Number(x / y),
+        };
+        self.stack.push(result);
+        Ok(())
+    }
+
+    /// Falls back on a method of `a`, or of `b` for the comparisons that swap their
+    /// operands, when a binary operator is not defined for the operands. Every comparison is
+    /// derived from `__lt__`.
+    fn call_operator(&mut self, a: &Object, b: &Object, operator: &str) -> Result<(), Error> {
+        let result = match operator {
+            "+" => self.call_method(a, "__add__", vec![b.clone()])?,
+            "-" => self.call_method(a, "__sub__", vec![b.clone()])?,
+            "*" => self.call_method(a, "__mul__", vec![b.clone()])?,
+            "/" => self.call_method(a, "__div__", vec![b.clone()])?,
+            "%" => self.call_method(a, "__mod__", vec![b.clone()])?,
+            _ => self.compare(a, b, operator)?.map(Object::Boolean),
+        };
+        match result {
+            Some(result) => {
+                self.stack.push(result);
+                Ok(())
+            }
+            None => Err(self.generate_error(format!(
+                "Invalid operator. Binary operator '{operator}' is not defined for '{a}' and '{b}'."
+            ))),
+        }
+    }
+
+    /// Derives a comparison from `__lt__`, preferring the operand that `operator` can be
+    /// written with directly, and otherwise flipping it around the other one with `__eq__`.
+    fn compare(&mut self, a: &Object, b: &Object, operator: &str) -> Result<Option<bool>, Error> {
+        // The operands are swapped for `>` and `<=`, which read as `b < a`.
+        let (first, second) = match operator {
+            "<" | ">=" => (a, b),
+            _ => (b, a),
+        };
+        let negated = matches!(operator, ">=" | "<=");
+        if let Some(less) = self.less(first, second)? {
+            return Ok(Some(less != negated));
+        }
+        match self.less(second, first)? {
+            // `first < second` is false when `second < first` or they are equal.
+            Some(less) => {
+                let less = !less && !self.equals(second, first)?;
+                Ok(Some(less != negated))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn less(&mut self, a: &Object, b: &Object) -> Result<Option<bool>, Error> {
+        Ok(self
+            .call_method(a, "__lt__", vec![b.clone()])?
+            .map(|result| result.is_truthy()))
+    }
+
+    /// Compares with `__eq__` of the left operand, or of the right one when only it defines it.
+    fn equals(&mut self, a: &Object, b: &Object) -> Result<bool, Error> {
+        if let Some(result) = self.call_method(a, "__eq__", vec![b.clone()])? {
+            return Ok(result.is_truthy());
+        }
+        match self.call_method(b, "__eq__", vec![a.clone()])? {
+            Some(result) => Ok(result.is_truthy()),
+            None => Ok(a == b),
+        }
+    }
+
+    /// Converts `object` to a string the way `print` does, with `__str__` when it defines it.
+    pub(crate) fn stringify(&mut self, object: &Object) -> Result<String, Error> {
+        match self.call_method(object, "__str__", Vec::new())? {
+            Some(Object::String(string)) => Ok(string.to_string()),
+            Some(result) => Err(self.generate_error(format!(
+                "Invalid conversion. '__str__' of '{object}' must return a string, found '{result}'."
+            ))),
+            None => Ok(object.to_string()),
+        }
+    }
+
+    fn has_method(&self, receiver: &Object, name: &str) -> bool {
+        let Object::Instance(instance) = receiver else {
+            return false;
+        };
+        let name = self.interner.intern(name);
+        instance.borrow().class.methods.contains_key(&name)
+    }
+
+    /// Calls the method `name` of `receiver`, if it is an instance whose class defines it.
+    fn call_method(
+        &mut self,
+        receiver: &Object,
+        name: &str,
+        arguments: Vec<Object>,
+    ) -> Result<Option<Object>, Error> {
+        let Object::Instance(instance) = receiver else {
+            return Ok(None);
+        };
+        let name = self.interner.intern(name);
+        let method = instance.borrow().class.methods.get(&name).cloned();
+        match method {
+            Some(method) => {
+                let bound = BoundMethod::new(receiver.clone(), method);
+                self.call_value(Object::BoundMethod(Rc::new(bound)), arguments)
+                    .map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn check_arity(&self, name: &str, arity: usize, argument_count: usize) -> Result<(), Error> {
+        if arity == argument_count {
+            Ok(())
+        } else {
+            Err(self.generate_error(format!(
+                "Invalid arguments. '{name}' expects {arity} argument(s), found {argument_count}."
+            )))
+        }
+    }
+
+    /// Enters a call, unless it would nest calls deeper than the limit allows.
+    fn push_frame(&mut self, frame: CallFrame) -> Result<(), Error> {
+        if self.frames.len() >= self.max_call_depth {
+            return Err(self.generate_error(format!(
+                "Stack overflow. Calling '{}' nests calls deeper than {}.",
+                frame.closure.function.name, self.max_call_depth
+            )));
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn chunk(&self) -> &Chunk {
+        &self.frame().closure.function.chunk
+    }
+
+    /// Decodes the opcode of the next instruction along with its address, or returns `None`
+    /// at the end of the chunk.
+    fn read_opcode(&mut self) -> Option<(usize, OpCode)> {
+        let frame = self.frame_mut();
+        let byte = frame.closure.function.chunk.code().get(frame.ip)?;
+        frame.ip += 1;
+        Some((frame.ip - 1, OpCode::from_byte(*byte).unwrap()))
+    }
+
+    fn read_operand(&mut self) -> usize {
+        let frame = self.frame_mut();
+        let (operand, next) = frame.closure.function.chunk.read_operand(frame.ip);
+        frame.ip = next;
+        operand
+    }
+
+    fn read_address(&mut self) -> usize {
+        let frame = self.frame_mut();
+        frame.ip += 4;
+        frame.closure.function.chunk.read_address(frame.ip - 4)
+    }
+
+    /// Reads a constant that is needed by value, which costs cloning it.
+    fn read_constant(&mut self) -> &Constant {
+        let index = self.read_operand();
+        self.chunk().constant(index)
+    }
+
+    /// Reads a constant that has to stay borrowed while the virtual machine is used, through
+    /// a handle to the running closure rather than a copy of the constant.
+    fn read_shared_constant(&mut self) -> (Rc<Closure>, usize) {
+        let index = self.read_operand();
+        (self.frame().closure.clone(), index)
+    }
+
+    fn read_name(&mut self) -> Str {
+        let Constant::Name(name) = self.read_constant() else {
+            unreachable!()
+        };
+        name.clone()
+    }
+
+    fn current_position(&self) -> Position {
+        let frame = self.frame();
+        frame.closure.function.chunk.get_position(frame.ip - 1)
+    }
+
+    fn generate_error(&self, message: String) -> Error {
+        let position = self.current_position();
+        Error::new(ErrorKind::Runtime, message, Some(position.clone()))
+            .with_stack_trace(self.stack_trace(&position))
+    }
+
+    /// The running fiber's calls, where the innermost one is at `position` and every other
+    /// one is at the call it made.
+    fn stack_trace(&self, position: &Position) -> StackTrace {
+        StackTrace::new(self.frames.len(), |index| {
+            let frame = &self.frames[self.frames.len() - 1 - index];
+            let line = if index == 0 {
+                position.line
+            } else {
+                frame.closure.function.chunk.get_position(frame.ip - 1).line
+            };
+            StackFrame {
+                function: frame.closure.function.name.clone(),
+                file: self.file.clone(),
+                line: line + 1,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::run_script;
+
+    use super::VirtualMachine;
+
+    fn error(source: &str) -> String {
+        run_script(&mut VirtualMachine::new(), source)
+            .unwrap_err()
+            .message
+    }
+
+    #[test]
+    fn equality_falls_back_to_the_right_operand() {
+        let mut vm = VirtualMachine::new();
+        let source = "class Any { fun __eq__(other) { return true } }\n\
+                      var left = 1 == Any()\n\
+                      var right = Any() != 1\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("left").as_deref(), Some("true"));
+        assert_eq!(vm.global("right").as_deref(), Some("false"));
+    }
+
+    #[test]
+    fn loops_over_instances() {
+        let mut vm = VirtualMachine::new();
+        let source = "class Countdown {\n\
+                          fun init(from) { this.from = from }\n\
+                          fun __next__() {\n\
+                              if this.from == 0 { return nil }\n\
+                              this.from = this.from - 1\n\
+                              return this.from + 1\n\
+                          }\n\
+                      }\n\
+                      class Bag {\n\
+                          fun init() { this.items = [\"a\", \"b\"] }\n\
+                          fun __iter__() { return this.items }\n\
+                      }\n\
+                      var counted = \"\"\n\
+                      for x in Countdown(3) { counted = counted + str(x) }\n\
+                      for x in Bag() { counted = counted + x }\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("counted").as_deref(), Some("321ab"));
+    }
+
+    #[test]
+    fn rejects_a_str_method_that_returns_no_string() {
+        let mut vm = VirtualMachine::new();
+        let source = "class Count { fun __str__() { return 1 } }\nprint Count()\n";
+        let error = run_script(&mut vm, source).unwrap_err();
+        assert!(error.message.starts_with(
+            "Invalid conversion. '__str__' of '<Count instance>' must return a string, found '1'."
+        ));
+    }
+
+    #[test]
+    fn passes_arguments_by_keyword() {
+        let mut vm = VirtualMachine::new();
+        let source = "fun f(a, b = 2, c = 3) { return [a, b, c] }\nvar list = f(c: 4, a: 1)\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("list").as_deref(), Some("[1, 2, 4]"));
+    }
+
+    #[test]
+    fn rejects_missing_and_unknown_keywords() {
+        let function = "fun f(a, b = 2) { return a + b }\n";
+        assert_eq!(
+            error(&format!("{function}f(b: 1)\n")),
+            "Missing argument. 'f' is missing required parameter(s) 'a'."
+        );
+        assert_eq!(
+            error(&format!("{function}f(1, c: 1)\n")),
+            "Unknown parameter. 'f' has no parameter named 'c'."
+        );
+        assert_eq!(
+            error(&format!("{function}f(1, a: 1)\n")),
+            "Invalid arguments. Parameter 'a' of 'f' is given more than once."
+        );
+    }
+
+    #[test]
+    fn rejects_resuming_a_finished_generator() {
+        let mut vm = VirtualMachine::new();
+        let source = "fun* g() { yield 1 }\nvar it = g()\nvar first = it()\nvar last = it()\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("first").as_deref(), Some("1"));
+        assert_eq!(vm.global("last").as_deref(), Some("nil"));
+        assert_eq!(
+            error(&format!("{source}it()\n")),
+            "Finished generator. '<generator g>' can not be resumed after it has returned."
+        );
+    }
+
+    #[test]
+    fn detects_deadlocks() {
+        assert_eq!(
+            error("var c = chan()\nrecv(c)\n"),
+            "Deadlock. All fibers are blocked."
+        );
+        assert_eq!(
+            error("var c = chan()\nfun wait() { recv(c) }\nspawn wait()\nspawn wait()\n"),
+            "Deadlock. The script finished while 2 fiber(s) are blocked."
+        );
+
+        // Fibers that can still make progress run to the end after the script finishes.
+        let mut vm = VirtualMachine::new();
+        let source = "var c = chan()\nvar got = nil\n\
+                      fun put() { send(c, 1) }\nfun take() { got = recv(c) }\n\
+                      spawn take()\nspawn put()\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("got").as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn explains_failed_assertions() {
+        assert_eq!(
+            error("var x = 1\nassert x > 2\n"),
+            "Assertion failed. x > 2 (1 <= 2)."
+        );
+        assert_eq!(
+            error("var x = 1\nassert x > 2, \"x is \" + str(x)\n"),
+            "Assertion failed. x > 2 (1 <= 2). x is 1"
+        );
+        assert_eq!(
+            error("fun ok() { return false }\nassert ok()\n"),
+            "Assertion failed. ok()."
+        );
+        assert!(run_script(&mut VirtualMachine::new(), "assert 1 < 2\n").is_ok());
+    }
+
+    #[test]
+    fn checks_variant_arity_the_compiler_can_not_see() {
+        assert_eq!(
+            error("enum E { A, B(x) }\nfun f(e) { return e.B(1, 2) }\nf(E)\n"),
+            "Invalid arguments. 'E.B' expects 1 argument(s), found 2."
+        );
     }
 }
@@ -5,28 +5,88 @@ pub(crate) type Program = Vec<Statement>;
 #[derive(Debug, Clone)]
 pub(crate) enum Statement {
     Function(FunctionStatement),
+    Class(ClassStatement),
+    Enum(EnumStatement),
     If(IfStatement),
+    For(ForStatement),
     While(WhileStatement),
     Block(BlockStatement),
     Variable(VariableStatement),
     Return(ReturnStatement),
+    Yield(YieldStatement),
     Print(PrintStatement),
+    Assert(AssertStatement),
     Expression(ExpressionStatement),
 }
 
 #[derive(Debug, Clone)]
 pub(crate) struct FunctionStatement {
     pub(crate) identifier: Token,
-    pub(crate) parameters: Vec<Token>,
+    pub(crate) parameters: Vec<Parameter>,
+    pub(crate) return_annotation: Option<Token>,
     pub(crate) block: BlockStatement,
+    pub(crate) generator: bool,
 }
 
 impl FunctionStatement {
-    pub(crate) fn new(identifier: Token, parameters: Vec<Token>, block: BlockStatement) -> Self {
+    pub(crate) fn new(
+        identifier: Token,
+        parameters: Vec<Parameter>,
+        return_annotation: Option<Token>,
+        block: BlockStatement,
+        generator: bool,
+    ) -> Self {
         Self {
             identifier,
             parameters,
+            return_annotation,
             block,
+            generator,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ForStatement {
+    pub(crate) identifier: Token,
+    pub(crate) iterable: Expression,
+    pub(crate) do_block: BlockStatement,
+}
+
+impl ForStatement {
+    pub(crate) fn new(identifier: Token, iterable: Expression, do_block: BlockStatement) -> Self {
+        Self {
+            identifier,
+            iterable,
+            do_block,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Parameter {
+    pub(crate) identifier: Token,
+    pub(crate) annotation: Option<Token>,
+    pub(crate) default: Option<Expression>,
+    /// The source text of the default, for listings.
+    pub(crate) default_source: Option<String>,
+    pub(crate) variadic: bool,
+}
+
+impl Parameter {
+    pub(crate) fn new(
+        identifier: Token,
+        annotation: Option<Token>,
+        default: Option<Expression>,
+        default_source: Option<String>,
+        variadic: bool,
+    ) -> Self {
+        Self {
+            identifier,
+            annotation,
+            default,
+            default_source,
+            variadic,
         }
     }
 }
@@ -52,9 +112,57 @@ pub(crate) enum ElseStatement {
     Block(BlockStatement),
 }
 
+/// A class and its methods. Methods named like `__add__` overload operators, and `init`
+/// initializes new instances.
+#[derive(Debug, Clone)]
+pub(crate) struct ClassStatement {
+    pub(crate) identifier: Token,
+    pub(crate) methods: Vec<FunctionStatement>,
+}
+
+impl ClassStatement {
+    pub(crate) fn new(identifier: Token, methods: Vec<FunctionStatement>) -> Self {
+        Self {
+            identifier,
+            methods,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EnumStatement {
+    pub(crate) identifier: Token,
+    pub(crate) variants: Vec<EnumVariant>,
+}
+
+impl EnumStatement {
+    pub(crate) fn new(identifier: Token, variants: Vec<EnumVariant>) -> Self {
+        Self {
+            identifier,
+            variants,
+        }
+    }
+}
+
+/// A variant of an enum, and the names of the values it carries.
+#[derive(Debug, Clone)]
+pub(crate) struct EnumVariant {
+    pub(crate) identifier: Token,
+    pub(crate) fields: Vec<Token>,
+}
+
+impl EnumVariant {
+    pub(crate) fn new(identifier: Token, fields: Vec<Token>) -> Self {
+        Self { identifier, fields }
+    }
+}
+
+/// With a pattern, `if value is Enum.Variant(a, b) {}` takes the branch when `value` is that
+/// variant, and binds its values to `a` and `b` inside it.
 #[derive(Debug, Clone)]
 pub(crate) struct IfStatement {
     pub(crate) condition: Expression,
+    pub(crate) pattern: Box<Option<VariantPattern>>,
     pub(crate) then_branch: BlockStatement,
     pub(crate) else_branch: Box<Option<ElseStatement>>,
 }
@@ -62,17 +170,36 @@ pub(crate) struct IfStatement {
 impl IfStatement {
     pub(crate) fn new(
         condition: Expression,
+        pattern: Option<VariantPattern>,
         then_branch: BlockStatement,
         else_branch: Option<ElseStatement>,
     ) -> Self {
         Self {
             condition,
+            pattern: Box::new(pattern),
             then_branch,
             else_branch: Box::new(else_branch),
         }
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct VariantPattern {
+    pub(crate) enum_identifier: Token,
+    pub(crate) variant: Token,
+    pub(crate) bindings: Vec<Token>,
+}
+
+impl VariantPattern {
+    pub(crate) fn new(enum_identifier: Token, variant: Token, bindings: Vec<Token>) -> Self {
+        Self {
+            enum_identifier,
+            variant,
+            bindings,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct BlockStatement {
     pub(crate) statements: Vec<Statement>,
@@ -87,14 +214,23 @@ impl BlockStatement {
 #[derive(Debug, Clone)]
 pub(crate) struct VariableStatement {
     pub(crate) identifier: Token,
+    pub(crate) annotation: Option<Token>,
     pub(crate) initializer: Expression,
+    pub(crate) constant: bool,
 }
 
 impl VariableStatement {
-    pub(crate) fn new(identifier: Token, initializer: Expression) -> Self {
+    pub(crate) fn new(
+        identifier: Token,
+        annotation: Option<Token>,
+        initializer: Expression,
+        constant: bool,
+    ) -> Self {
         Self {
             identifier,
+            annotation,
             initializer,
+            constant,
         }
     }
 }
@@ -110,6 +246,17 @@ impl ReturnStatement {
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct YieldStatement {
+    pub(crate) expression: Expression,
+}
+
+impl YieldStatement {
+    pub(crate) fn new(expression: Expression) -> Self {
+        Self { expression }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct PrintStatement {
     pub(crate) expression: Expression,
@@ -121,6 +268,25 @@ impl PrintStatement {
     }
 }
 
+/// `assert condition, message`, with the condition's source text kept for the failure
+/// message.
+#[derive(Debug, Clone)]
+pub(crate) struct AssertStatement {
+    pub(crate) condition: Expression,
+    pub(crate) source: String,
+    pub(crate) message: Option<Expression>,
+}
+
+impl AssertStatement {
+    pub(crate) fn new(condition: Expression, source: String, message: Option<Expression>) -> Self {
+        Self {
+            condition,
+            source,
+            message,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct ExpressionStatement {
     pub(crate) expression: Expression,
@@ -139,6 +305,11 @@ pub(crate) enum Expression {
     Unary(UnaryExpression),
     Group(GroupExpression),
     Call(CallExpression),
+    Spawn(SpawnExpression),
+    Get(GetExpression),
+    Set(SetExpression),
+    Lambda(LambdaExpression),
+    List(ListExpression),
     Literal(LiteralExpression),
     Variable(VariableExpression),
 }
@@ -151,6 +322,11 @@ impl Expression {
             Self::Unary(expression) => expression.position(),
             Self::Group(expression) => expression.position(),
             Self::Call(expression) => expression.position(),
+            Self::Spawn(expression) => expression.position(),
+            Self::Get(expression) => expression.position(),
+            Self::Set(expression) => expression.position(),
+            Self::Lambda(expression) => expression.position(),
+            Self::List(expression) => expression.position(),
             Self::Literal(expression) => expression.position(),
             Self::Variable(expression) => expression.position(),
         }
@@ -176,6 +352,35 @@ impl AssignmentExpression {
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct SetExpression {
+    pub(crate) object: Box<Expression>,
+    pub(crate) identifier: Token,
+    pub(crate) value: Box<Expression>,
+    /// Written as `object?.identifier = value`, which the compiler rejects.
+    pub(crate) optional: bool,
+}
+
+impl SetExpression {
+    pub(crate) fn new(
+        object: Expression,
+        identifier: Token,
+        value: Expression,
+        optional: bool,
+    ) -> Self {
+        Self {
+            object: Box::new(object),
+            identifier,
+            value: Box::new(value),
+            optional,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.identifier.position.clone()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct BinaryExpression {
     pub(crate) left: Box<Expression>,
@@ -237,13 +442,22 @@ impl GroupExpression {
 pub(crate) struct CallExpression {
     pub(crate) callee: Box<Expression>,
     pub(crate) arguments: Vec<Expression>,
+    pub(crate) keyword_arguments: Vec<(Token, Expression)>,
+    pub(crate) optional: bool,
 }
 
 impl CallExpression {
-    pub(crate) fn new(callee: Expression, arguments: Vec<Expression>) -> Self {
+    pub(crate) fn new(
+        callee: Expression,
+        arguments: Vec<Expression>,
+        keyword_arguments: Vec<(Token, Expression)>,
+        optional: bool,
+    ) -> Self {
         Self {
             callee: Box::new(callee),
             arguments,
+            keyword_arguments,
+            optional,
         }
     }
 
@@ -252,6 +466,102 @@ impl CallExpression {
     }
 }
 
+#[derive(Debug, Clone)]
+pub(crate) struct SpawnExpression {
+    pub(crate) keyword: Token,
+    pub(crate) call: CallExpression,
+}
+
+impl SpawnExpression {
+    pub(crate) fn new(keyword: Token, call: CallExpression) -> Self {
+        Self { keyword, call }
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.keyword.position.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct GetExpression {
+    pub(crate) object: Box<Expression>,
+    pub(crate) identifier: Token,
+    pub(crate) optional: bool,
+}
+
+impl GetExpression {
+    pub(crate) fn new(object: Expression, identifier: Token, optional: bool) -> Self {
+        Self {
+            object: Box::new(object),
+            identifier,
+            optional,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.identifier.position.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct LambdaExpression {
+    pub(crate) keyword: Token,
+    /// Where the keyword starts in its line, counting from 1, which positions do not hold.
+    pub(crate) column: usize,
+    pub(crate) parameters: Vec<Parameter>,
+    pub(crate) block: BlockStatement,
+    pub(crate) generator: bool,
+}
+
+impl LambdaExpression {
+    pub(crate) fn new(
+        keyword: Token,
+        column: usize,
+        parameters: Vec<Parameter>,
+        block: BlockStatement,
+        generator: bool,
+    ) -> Self {
+        Self {
+            keyword,
+            column,
+            parameters,
+            block,
+            generator,
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        format!(
+            "<lambda@{}:{}>",
+            self.keyword.position.line + 1,
+            self.column
+        )
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.keyword.position.clone()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ListExpression {
+    pub(crate) open_bracket: Token,
+    pub(crate) elements: Vec<Expression>,
+}
+
+impl ListExpression {
+    pub(crate) fn new(open_bracket: Token, elements: Vec<Expression>) -> Self {
+        Self {
+            open_bracket,
+            elements,
+        }
+    }
+
+    pub(crate) fn position(&self) -> Position {
+        self.open_bracket.position.clone()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub(crate) struct LiteralExpression {
     pub(crate) value: Token,
@@ -0,0 +1,249 @@
+use std::{
+    cmp::Ordering,
+    fmt::{Display, Formatter, Result},
+};
+
+/// Every integer up to this magnitude is exactly representable as an `f64`.
+pub(crate) const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_992.;
+
+const BASE: u64 = 1_000_000_000;
+const BASE_DIGITS: usize = 9;
+
+/// An integer of any size. The magnitude is kept in base 10^9 limbs, least significant
+/// first and without leading zero limbs, which makes decimal conversion exact and cheap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BigInt {
+    negative: bool,
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    fn new(negative: bool, mut limbs: Vec<u32>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        let negative = negative && !limbs.is_empty();
+        Self { negative, limbs }
+    }
+
+    /// Parses a decimal integer, like the lexeme of a number literal.
+    pub(crate) fn parse(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text),
+        };
+        if digits.is_empty() || !digits.bytes().all(|digit| digit.is_ascii_digit()) {
+            return None;
+        }
+        let mut limbs = Vec::new();
+        let mut end = digits.len();
+        while end > 0 {
+            let start = end.saturating_sub(BASE_DIGITS);
+            limbs.push(digits[start..end].parse().ok()?);
+            end = start;
+        }
+        Some(Self::new(negative, limbs))
+    }
+
+    /// Converts a number without a fractional part, exactly.
+    pub(crate) fn from_f64(number: f64) -> Option<Self> {
+        if !number.is_finite() || number.fract() != 0. {
+            return None;
+        }
+        let negative = number < 0.;
+        let number = number.abs();
+        if number < u64::MAX as f64 {
+            return Some(Self::from_u64(negative, number as u64));
+        }
+        // Above 2^64, the number is its 53 bit mantissa shifted left.
+        let bits = number.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64 - 1075;
+        let mantissa = (bits & ((1 << 52) - 1)) | (1 << 52);
+        let mut result = Self::from_u64(negative, mantissa);
+        for _ in 0..exponent {
+            result = result.multiply_small(2);
+        }
+        Some(result)
+    }
+
+    fn from_u64(negative: bool, mut number: u64) -> Self {
+        let mut limbs = Vec::new();
+        while number > 0 {
+            limbs.push((number % BASE) as u32);
+            number /= BASE;
+        }
+        Self::new(negative, limbs)
+    }
+
+    /// The nearest `f64`, rounded the same way a literal with these digits would be.
+    pub(crate) fn to_f64(&self) -> f64 {
+        self.to_string().parse().unwrap_or(f64::NAN)
+    }
+
+    /// Whether the integer is small enough to be an `f64` without losing precision.
+    pub(crate) fn is_safe(&self) -> bool {
+        let max = Self::from_u64(false, MAX_SAFE_INTEGER as u64);
+        compare_magnitudes(&self.limbs, &max.limbs) != Ordering::Greater
+    }
+
+    pub(crate) fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub(crate) fn negate(&self) -> Self {
+        Self::new(!self.negative, self.limbs.clone())
+    }
+
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        if self.negative == other.negative {
+            return Self::new(self.negative, add_magnitudes(&self.limbs, &other.limbs));
+        }
+        match compare_magnitudes(&self.limbs, &other.limbs) {
+            Ordering::Less => Self::new(
+                other.negative,
+                subtract_magnitudes(&other.limbs, &self.limbs),
+            ),
+            _ => Self::new(
+                self.negative,
+                subtract_magnitudes(&self.limbs, &other.limbs),
+            ),
+        }
+    }
+
+    pub(crate) fn subtract(&self, other: &Self) -> Self {
+        self.add(&other.negate())
+    }
+
+    pub(crate) fn multiply(&self, other: &Self) -> Self {
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let product = limbs[i + j] + a as u64 * b as u64 + carry;
+                limbs[i + j] = product % BASE;
+                carry = product / BASE;
+            }
+            limbs[i + other.limbs.len()] += carry;
+        }
+        Self::new(
+            self.negative != other.negative,
+            limbs.into_iter().map(|limb| limb as u32).collect(),
+        )
+    }
+
+    fn multiply_small(&self, factor: u32) -> Self {
+        let mut limbs = Vec::with_capacity(self.limbs.len() + 1);
+        let mut carry = 0;
+        for &limb in &self.limbs {
+            let product = limb as u64 * factor as u64 + carry;
+            limbs.push((product % BASE) as u32);
+            carry = product / BASE;
+        }
+        if carry > 0 {
+            limbs.push(carry as u32);
+        }
+        Self::new(self.negative, limbs)
+    }
+
+    /// Divides, truncating towards zero, so the remainder has the sign of `self`. Returns
+    /// `None` when dividing by zero.
+    pub(crate) fn divide(&self, other: &Self) -> Option<(Self, Self)> {
+        if other.is_zero() {
+            return None;
+        }
+        let divisor = Self::new(false, other.limbs.clone());
+        let mut quotient = vec![0; self.limbs.len()];
+        let mut remainder = Self::new(false, Vec::new());
+        for (i, &limb) in self.limbs.iter().enumerate().rev() {
+            let mut limbs = remainder.limbs;
+            limbs.insert(0, limb);
+            remainder = Self::new(false, limbs);
+
+            // Binary search for the largest digit that still fits.
+            let (mut low, mut high) = (0, BASE as u32 - 1);
+            while low < high {
+                let middle = high - (high - low) / 2;
+                if divisor.multiply_small(middle) <= remainder {
+                    low = middle;
+                } else {
+                    high = middle - 1;
+                }
+            }
+            quotient[i] = low;
+            remainder = remainder.subtract(&divisor.multiply_small(low));
+        }
+        Some((
+            Self::new(self.negative != other.negative, quotient),
+            Self::new(self.negative, remainder.limbs),
+        ))
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => compare_magnitudes(&self.limbs, &other.limbs),
+            (true, true) => compare_magnitudes(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let Some((most_significant, rest)) = self.limbs.split_last() else {
+            return write!(f, "0");
+        };
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{most_significant}")?;
+        for limb in rest.iter().rev() {
+            write!(f, "{limb:09}")?;
+        }
+        Ok(())
+    }
+}
+
+fn compare_magnitudes(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0;
+    for i in 0..a.len().max(b.len()) {
+        let sum = *a.get(i).unwrap_or(&0) as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        limbs.push((sum % BASE) as u32);
+        carry = sum / BASE;
+    }
+    if carry > 0 {
+        limbs.push(carry as u32);
+    }
+    limbs
+}
+
+/// Subtracts `b` from `a`, where `a` is at least as large as `b`.
+fn subtract_magnitudes(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut limbs = Vec::with_capacity(a.len());
+    let mut borrow = 0;
+    for (i, &limb) in a.iter().enumerate() {
+        let mut difference = limb as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = 0;
+        if difference < 0 {
+            difference += BASE as i64;
+            borrow = 1;
+        }
+        limbs.push(difference as u32);
+    }
+    limbs
+}
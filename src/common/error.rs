@@ -9,6 +9,8 @@ pub(crate) enum ErrorKind {
     Parser,
     Runtime,
     Compiler,
+    Type,
+    Bytecode,
 }
 
 impl Display for ErrorKind {
@@ -19,15 +21,64 @@ impl Display for ErrorKind {
             ErrorKind::Parser => write!(f, "ParserError"),
             ErrorKind::Compiler => write!(f, "CompilerError"),
             ErrorKind::Runtime => write!(f, "RuntimeError"),
+            ErrorKind::Type => write!(f, "TypeError"),
+            ErrorKind::Bytecode => write!(f, "BytecodeError"),
         }
     }
 }
 
+/// Stack traces longer than this keep half of it from each end.
+const MAX_TRACE_FRAMES: usize = 20;
+
 #[derive(Debug)]
 pub(crate) struct Error {
     pub(crate) kind: ErrorKind,
     pub(crate) message: String,
     pub(crate) position: Option<Position>,
+    pub(crate) stack_trace: Option<StackTrace>,
+}
+
+/// The calls that were running when a runtime error happened, innermost first. In deep
+/// recursion only the innermost and outermost calls are kept, with a count of the rest.
+#[derive(Debug)]
+pub(crate) struct StackTrace {
+    frames: Vec<StackFrame>,
+    omitted: usize,
+}
+
+#[derive(Debug)]
+pub(crate) struct StackFrame {
+    pub(crate) function: String,
+    pub(crate) file: String,
+    pub(crate) line: usize,
+}
+
+impl StackTrace {
+    /// Builds the trace of `count` calls, asking `frame` for each one it keeps by its index,
+    /// where 0 is the innermost.
+    pub(crate) fn new(count: usize, frame: impl Fn(usize) -> StackFrame) -> Self {
+        if count <= MAX_TRACE_FRAMES {
+            return Self {
+                frames: (0..count).map(frame).collect(),
+                omitted: 0,
+            };
+        }
+        let half = MAX_TRACE_FRAMES / 2;
+        Self {
+            frames: (0..half).chain(count - half..count).map(frame).collect(),
+            omitted: count - MAX_TRACE_FRAMES,
+        }
+    }
+
+    fn report(&self) {
+        let half = MAX_TRACE_FRAMES / 2;
+        for (i, frame) in self.frames.iter().enumerate() {
+            if self.omitted > 0 && i == half {
+                eprintln!("    ... {} frames omitted", self.omitted);
+            }
+            eprintln!("    at {} ({}:{})", frame.function, frame.file, frame.line);
+        }
+    }
 }
 
 impl Error {
@@ -36,9 +87,15 @@ impl Error {
             kind,
             message,
             position,
+            stack_trace: None,
         }
     }
 
+    pub(crate) fn with_stack_trace(mut self, stack_trace: StackTrace) -> Self {
+        self.stack_trace = Some(stack_trace);
+        self
+    }
+
     pub(crate) fn report(&self) {
         if let Some(position) = self.position.clone() {
             eprintln!(
@@ -49,5 +106,64 @@ impl Error {
             );
         }
         eprintln!("{}: {}", self.kind, self.message);
+        // A trace of the script alone says no more than the position does.
+        if let Some(stack_trace) = &self.stack_trace {
+            if stack_trace.frames.len() > 1 {
+                stack_trace.report();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    use super::{StackFrame, StackTrace, MAX_TRACE_FRAMES};
+
+    fn lines(trace: &StackTrace) -> Vec<usize> {
+        trace.frames.iter().map(|frame| frame.line).collect()
+    }
+
+    fn frame(index: usize) -> StackFrame {
+        StackFrame {
+            function: "f".to_string(),
+            file: "<input>".to_string(),
+            line: index,
+        }
+    }
+
+    #[test]
+    fn keeps_short_traces_whole() {
+        let trace = StackTrace::new(MAX_TRACE_FRAMES, frame);
+        assert_eq!(lines(&trace), (0..MAX_TRACE_FRAMES).collect::<Vec<_>>());
+        assert_eq!(trace.omitted, 0);
+    }
+
+    #[test]
+    fn keeps_both_ends_of_long_traces() {
+        let trace = StackTrace::new(MAX_TRACE_FRAMES + 5, frame);
+        let half = MAX_TRACE_FRAMES / 2;
+        let kept: Vec<usize> = (0..half).chain(half + 5..MAX_TRACE_FRAMES + 5).collect();
+        assert_eq!(lines(&trace), kept);
+        assert_eq!(trace.omitted, 5);
+    }
+
+    #[test]
+    fn elides_frames_of_deep_recursion() {
+        let mut vm = VirtualMachine::new();
+        vm.set_max_call_depth(100);
+        let source = "fun down(n) {\n  return down(n + 1)\n}\ndown(0)\n";
+        let error = run_script(&mut vm, source).unwrap_err();
+        let trace = error.stack_trace.unwrap();
+        assert_eq!(trace.frames.len(), MAX_TRACE_FRAMES);
+        assert!(trace.omitted > 0);
+        assert_eq!(trace.frames[0].function, "down");
+        assert_eq!(trace.frames[0].line, 2);
+        let outermost = trace.frames.last().unwrap();
+        assert_eq!(
+            (outermost.function.as_str(), outermost.line),
+            ("<script>", 4)
+        );
     }
 }
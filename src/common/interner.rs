@@ -0,0 +1,159 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Display, Formatter, Result},
+    hash::{BuildHasherDefault, Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+/// An immutable string, shared by reference counting, with its hash computed once when it
+/// is created. Hashing one for a lookup only feeds that hash to the hasher, and strings
+/// with different hashes are unequal without comparing their contents.
+#[derive(Clone)]
+pub(crate) struct Str(Rc<StrData>);
+
+struct StrData {
+    text: Box<str>,
+    hash: u64,
+    /// Interned strings are the only string with their contents, so two of them are equal
+    /// exactly when they are the same string.
+    interned: bool,
+}
+
+impl Str {
+    fn new(text: Box<str>, interned: bool) -> Self {
+        // FNV-1a, which is fast for the short strings names and literals usually are.
+        let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        Self(Rc::new(StrData {
+            text,
+            hash,
+            interned,
+        }))
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.0.text
+    }
+}
+
+impl PartialEq for Str {
+    fn eq(&self, other: &Self) -> bool {
+        if Rc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        if self.0.interned && other.0.interned {
+            return false;
+        }
+        self.0.hash == other.0.hash && self.0.text == other.0.text
+    }
+}
+
+impl Eq for Str {}
+
+impl Hash for Str {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(self.0.hash);
+    }
+}
+
+impl Deref for Str {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0.text
+    }
+}
+
+impl Display for Str {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}", self.0.text)
+    }
+}
+
+/// Hashes a `Str` by handing over its precomputed hash, which is already well mixed.
+#[derive(Default)]
+pub(crate) struct StrHasher(u64);
+
+impl Hasher for StrHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_u64(&mut self, hash: u64) {
+        self.0 = hash;
+    }
+}
+
+/// A map keyed by strings that does not hash them again.
+pub(crate) type StrMap<V> = HashMap<Str, V, BuildHasherDefault<StrHasher>>;
+
+/// Strings built at runtime, which are not interned.
+impl From<String> for Str {
+    fn from(text: String) -> Self {
+        Self::new(text.into_boxed_str(), false)
+    }
+}
+
+impl From<&str> for Str {
+    fn from(text: &str) -> Self {
+        Self::new(text.into(), false)
+    }
+}
+
+/// Hands out one shared `Str` per distinct string. Every virtual machine has one, which the
+/// compiler interns identifiers and string literals with, so names looked up at runtime are
+/// compared by pointer. Cloning an interner shares it.
+#[derive(Clone, Default)]
+pub(crate) struct Interner {
+    strings: Rc<RefCell<HashMap<Box<str>, Str>>>,
+}
+
+impl Interner {
+    pub(crate) fn intern(&self, text: &str) -> Str {
+        let mut strings = self.strings.borrow_mut();
+        if let Some(string) = strings.get(text) {
+            return string.clone();
+        }
+        let string = Str::new(text.into(), true);
+        strings.insert(text.into(), string.clone());
+        string
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::{Interner, Str, StrMap};
+
+    #[test]
+    fn interns_each_text_once() {
+        let interner = Interner::default();
+        let name = interner.intern("name");
+        assert!(Rc::ptr_eq(&name.0, &interner.intern("name").0));
+        assert!(interner.clone().intern("name") == name);
+        assert!(interner.intern("other") != name);
+    }
+
+    #[test]
+    fn compares_interned_and_built_strings_by_contents() {
+        let interner = Interner::default();
+        let interned = interner.intern("name");
+        let built = Str::from(format!("na{}", "me"));
+        assert!(built == interned);
+        assert!(Str::from("nam") != interned);
+
+        let mut map = StrMap::default();
+        map.insert(interned, 1);
+        assert_eq!(map.get(&built), Some(&1));
+    }
+}
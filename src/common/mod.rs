@@ -1,5 +1,8 @@
 pub(crate) mod ast;
+pub(crate) mod bigint;
 pub(crate) mod error;
+pub(crate) mod interner;
 pub(crate) mod object;
 pub(crate) mod position;
+pub(crate) mod regex;
 pub(crate) mod token;
@@ -1,9 +1,40 @@
-#[derive(Clone, PartialEq)]
+use std::{cell::RefCell, cmp::Ordering, collections::HashMap, rc::Rc};
+
+use crate::backend::{chunk::Chunk, heap::Gc, vm::VirtualMachine};
+
+use super::{
+    bigint::{BigInt, MAX_SAFE_INTEGER},
+    interner::{Str, StrMap},
+    regex::Regex,
+};
+
+#[derive(Clone)]
 pub(crate) enum Object {
     Nil,
     Boolean(bool),
     Number(f64),
-    String(String),
+    /// An integer beyond the range `Number` represents exactly. Smaller integers are always
+    /// numbers, so each value has one representation.
+    BigInt(Rc<BigInt>),
+    String(Str),
+    List(Gc<RefCell<Vec<Object>>>),
+    Range(Range),
+    Iterator(Rc<RefCell<IteratorState>>),
+    Function(Rc<Closure>),
+    Generator(Rc<RefCell<Generator>>),
+    Fiber(usize),
+    Channel(Rc<RefCell<Channel>>),
+    Enum(Rc<Enum>),
+    Variant(Rc<Variant>),
+    Constructor(Rc<Enum>, usize),
+    Class(Rc<Class>),
+    Instance(Gc<RefCell<Instance>>),
+    BoundMethod(Rc<BoundMethod>),
+    NativeFunction(NativeFunction),
+    Module(Rc<Module>),
+    Regex(Rc<Regex>),
+    Match(Rc<Match>),
+    Cell(Gc<RefCell<Object>>),
 }
 
 impl Object {
@@ -13,17 +44,556 @@ impl Object {
             Object::Boolean(bool) => *bool,
             Object::Number(n) => n != &0.,
             Object::String(s) => !s.is_empty(),
+            Object::List(list) => !list.borrow().is_empty(),
+            Object::BigInt(_)
+            | Object::Range(_)
+            | Object::Iterator(_)
+            | Object::Function(_)
+            | Object::Generator(_)
+            | Object::Fiber(_)
+            | Object::Channel(_)
+            | Object::Enum(_)
+            | Object::Variant(_)
+            | Object::Constructor(..)
+            | Object::Class(_)
+            | Object::Instance(_)
+            | Object::BoundMethod(_)
+            | Object::NativeFunction(_)
+            | Object::Module(_)
+            | Object::Regex(_)
+            | Object::Match(_)
+            | Object::Cell(_) => true,
+        }
+    }
+}
+
+impl Object {
+    /// The integer as a number when it is exact as one, or as a big integer otherwise.
+    pub(crate) fn integer(integer: BigInt) -> Self {
+        if integer.is_safe() {
+            Object::Number(integer.to_f64())
+        } else {
+            Object::BigInt(Rc::new(integer))
+        }
+    }
+
+    /// The value as a big integer, if it is an integer.
+    pub(crate) fn as_integer(&self) -> Option<BigInt> {
+        match self {
+            Object::Number(number) => BigInt::from_f64(*number),
+            Object::BigInt(integer) => Some(integer.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_safe_integer(number: f64) -> bool {
+        number.fract() == 0. && number.abs() <= MAX_SAFE_INTEGER
+    }
+
+    /// The value as a number, rounding big integers.
+    pub(crate) fn as_number(&self) -> Option<f64> {
+        match self {
+            Object::Number(number) => Some(*number),
+            Object::BigInt(integer) => Some(integer.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// Orders numbers and big integers, exactly when both are integers.
+    pub(crate) fn compare_numbers(a: &Object, b: &Object) -> Option<Ordering> {
+        match (a.as_integer(), b.as_integer()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => a.as_number()?.partial_cmp(&b.as_number()?),
+        }
+    }
+}
+
+impl PartialEq for Object {
+    /// Lists and variants are compared through a stack of pairs still to compare rather
+    /// than by recursing, so deeply nested values can not overflow the native stack.
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = vec![(self.clone(), other.clone())];
+        while let Some(pair) = pending.pop() {
+            let equal = match pair {
+                (Self::Nil, Self::Nil) => true,
+                (Self::Boolean(a), Self::Boolean(b)) => a == b,
+                (Self::Number(a), Self::Number(b)) => a == b,
+                (Self::BigInt(a), Self::BigInt(b)) => a == b,
+                (Self::BigInt(a), Self::Number(b)) | (Self::Number(b), Self::BigInt(a)) => {
+                    BigInt::from_f64(b).as_ref() == Some(&a)
+                }
+                (Self::String(a), Self::String(b)) => a == b,
+                (Self::List(a), Self::List(b)) if Gc::ptr_eq(&a, &b) => true,
+                (Self::List(a), Self::List(b)) => {
+                    let (a, b) = (a.borrow(), b.borrow());
+                    let equal = a.len() == b.len();
+                    if equal {
+                        pending.extend(a.iter().cloned().zip(b.iter().cloned()));
+                    }
+                    equal
+                }
+                (Self::Range(a), Self::Range(b)) => a == b,
+                (Self::Iterator(a), Self::Iterator(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Function(a), Self::Function(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Generator(a), Self::Generator(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Fiber(a), Self::Fiber(b)) => a == b,
+                (Self::Channel(a), Self::Channel(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Enum(a), Self::Enum(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Variant(a), Self::Variant(b)) => {
+                    let equal = Rc::ptr_eq(&a.enum_type, &b.enum_type)
+                        && a.index == b.index
+                        && a.values.len() == b.values.len();
+                    if equal {
+                        pending.extend(a.values.iter().cloned().zip(b.values.iter().cloned()));
+                    }
+                    equal
+                }
+                (Self::Constructor(a, i), Self::Constructor(b, j)) => Rc::ptr_eq(&a, &b) && i == j,
+                (Self::Class(a), Self::Class(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Instance(a), Self::Instance(b)) => Gc::ptr_eq(&a, &b),
+                (Self::BoundMethod(a), Self::BoundMethod(b)) => {
+                    pending.push((a.receiver.clone(), b.receiver.clone()));
+                    Rc::ptr_eq(&a.method, &b.method)
+                }
+                (Self::NativeFunction(a), Self::NativeFunction(b)) => a.name == b.name,
+                (Self::Module(a), Self::Module(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Regex(a), Self::Regex(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Match(a), Self::Match(b)) => Rc::ptr_eq(&a, &b),
+                (Self::Cell(a), Self::Cell(b)) => Gc::ptr_eq(&a, &b),
+                _ => false,
+            };
+            if !equal {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Part of a value that is still to be written.
+enum Piece {
+    Value(Object),
+    Text(&'static str),
+}
+
+impl Piece {
+    /// Queues `values` to be written in order, separated by commas.
+    fn push_values(pending: &mut Vec<Piece>, values: &[Object]) {
+        for (i, value) in values.iter().enumerate().rev() {
+            pending.push(Piece::Value(value.clone()));
+            if i > 0 {
+                pending.push(Piece::Text(", "));
+            }
         }
     }
 }
 
 impl std::fmt::Display for Object {
+    /// Lists and variants are written from a stack of pieces still to write rather than by
+    /// recursing, for the same reason as equality.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut pending = vec![Piece::Value(self.clone())];
+        while let Some(piece) = pending.pop() {
+            let object = match piece {
+                Piece::Value(object) => object,
+                Piece::Text(text) => {
+                    write!(f, "{text}")?;
+                    continue;
+                }
+            };
+            match object {
+                Self::Nil => write!(f, "nil")?,
+                Self::Boolean(object) => write!(f, "{object}")?,
+                Self::Number(object) => write!(f, "{object}")?,
+                Self::BigInt(object) => write!(f, "{object}")?,
+                Self::String(object) => write!(f, "{object}")?,
+                Self::List(object) => {
+                    write!(f, "[")?;
+                    pending.push(Piece::Text("]"));
+                    Piece::push_values(&mut pending, &object.borrow());
+                }
+                Self::Range(object) => {
+                    if object.inclusive {
+                        write!(f, "{}..={}", object.start, object.end)?
+                    } else {
+                        write!(f, "{}..{}", object.start, object.end)?
+                    }
+                }
+                Self::Iterator(_) => write!(f, "<iterator>")?,
+                Self::Function(object) => write!(f, "<fun {}>", object.function.name)?,
+                Self::Generator(object) => {
+                    write!(f, "<generator {}>", object.borrow().closure.function.name)?
+                }
+                Self::Fiber(object) => write!(f, "<fiber {object}>")?,
+                Self::Channel(_) => write!(f, "<channel>")?,
+                Self::Enum(object) => write!(f, "<enum {}>", object.name)?,
+                Self::Variant(object) => {
+                    write!(f, "{}", object.enum_type.variant_name(object.index))?;
+                    if !object.values.is_empty() {
+                        write!(f, "(")?;
+                        pending.push(Piece::Text(")"));
+                        Piece::push_values(&mut pending, &object.values);
+                    }
+                }
+                Self::Constructor(object, index) => {
+                    write!(f, "<variant {}>", object.variant_name(index))?
+                }
+                Self::Class(object) => write!(f, "<class {}>", object.name)?,
+                Self::Instance(object) => write!(f, "<{} instance>", object.borrow().class.name)?,
+                Self::BoundMethod(object) => write!(f, "<fun {}>", object.method.function.name)?,
+                Self::NativeFunction(object) => write!(f, "<native fun {}>", object.name)?,
+                Self::Module(object) => write!(f, "<module {}>", object.name)?,
+                Self::Regex(object) => write!(f, "<regex {}>", object.pattern)?,
+                Self::Match(object) => write!(f, "<match {}>", object.text)?,
+                Self::Cell(object) => pending.push(Piece::Value(object.borrow().clone())),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub(crate) struct Range {
+    pub(crate) start: f64,
+    pub(crate) end: f64,
+    pub(crate) inclusive: bool,
+}
+
+impl Range {
+    pub(crate) fn new(start: f64, end: f64, inclusive: bool) -> Self {
+        Self {
+            start,
+            end,
+            inclusive,
+        }
+    }
+}
+
+/// State of a `for .. in` loop. Callables are iterated by calling them with no arguments
+/// until they return nil, and generators by resuming them until they return, which the
+/// virtual machine does itself.
+pub(crate) enum IteratorState {
+    Range(Range),
+    String(Vec<char>, usize),
+    List(Gc<RefCell<Vec<Object>>>, usize),
+    Callable(Object),
+    Generator(Rc<RefCell<Generator>>),
+}
+
+impl IteratorState {
+    pub(crate) fn next_element(&mut self) -> Option<Object> {
         match self {
-            Self::Nil => write!(f, "nil"),
-            Self::Boolean(object) => write!(f, "{object}"),
-            Self::Number(object) => write!(f, "{object}"),
-            Self::String(object) => write!(f, "{object}"),
+            Self::Range(range) => {
+                let in_range = if range.inclusive {
+                    range.start <= range.end
+                } else {
+                    range.start < range.end
+                };
+                if in_range {
+                    range.start += 1.;
+                    Some(Object::Number(range.start - 1.))
+                } else {
+                    None
+                }
+            }
+            Self::String(characters, index) => {
+                let character = characters.get(*index)?;
+                *index += 1;
+                Some(Object::String(character.to_string().into()))
+            }
+            Self::List(list, index) => {
+                let element = list.borrow().get(*index)?.clone();
+                *index += 1;
+                Some(element)
+            }
+            Self::Callable(_) | Self::Generator(_) => None,
+        }
+    }
+}
+
+/// Where a closure finds a captured variable when it is created: a boxed local slot of the
+/// enclosing frame, or one of the enclosing closure's own captures.
+#[derive(Clone)]
+pub(crate) enum Capture {
+    Local(usize),
+    Upvalue(usize),
+}
+
+/// Parameter names of a function, without the rest parameter. Parameters after the first
+/// `required` ones have defaults, and a function with a rest parameter collects extra
+/// positional arguments into a list in the slot after the last parameter.
+#[derive(Default)]
+pub(crate) struct Signature {
+    pub(crate) parameters: Vec<String>,
+    pub(crate) required: usize,
+    /// The source text of each default, which is only kept for listings.
+    pub(crate) defaults: Vec<String>,
+    pub(crate) rest: Option<String>,
+}
+
+impl Signature {
+    pub(crate) fn new(
+        parameters: Vec<String>,
+        required: usize,
+        defaults: Vec<String>,
+        rest: Option<String>,
+    ) -> Self {
+        Self {
+            parameters,
+            required,
+            defaults,
+            rest,
+        }
+    }
+
+    pub(crate) fn variadic(&self) -> bool {
+        self.rest.is_some()
+    }
+
+    pub(crate) fn arity(&self) -> usize {
+        self.parameters.len()
+    }
+
+    pub(crate) fn is_simple(&self) -> bool {
+        self.required == self.parameters.len() && !self.variadic()
+    }
+}
+
+pub(crate) struct Function {
+    pub(crate) name: String,
+    pub(crate) signature: Signature,
+    pub(crate) chunk: Chunk,
+    pub(crate) captures: Vec<Capture>,
+    pub(crate) generator: bool,
+}
+
+impl Function {
+    pub(crate) fn new(
+        name: String,
+        signature: Signature,
+        chunk: Chunk,
+        captures: Vec<Capture>,
+        generator: bool,
+    ) -> Self {
+        Self {
+            name,
+            signature,
+            chunk,
+            captures,
+            generator,
+        }
+    }
+}
+
+pub(crate) struct Closure {
+    pub(crate) function: Rc<Function>,
+    pub(crate) upvalues: Vec<Gc<RefCell<Object>>>,
+    pub(crate) defaults: Vec<Object>,
+}
+
+impl Closure {
+    pub(crate) fn new(
+        function: Rc<Function>,
+        upvalues: Vec<Gc<RefCell<Object>>>,
+        defaults: Vec<Object>,
+    ) -> Self {
+        Self {
+            function,
+            upvalues,
+            defaults,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum GeneratorState {
+    Suspended,
+    Running,
+    Finished,
+}
+
+/// A call to a generator function, suspended at its last `yield`. `slots` holds the frame's
+/// part of the stack while it is not running.
+pub(crate) struct Generator {
+    pub(crate) closure: Rc<Closure>,
+    pub(crate) ip: usize,
+    pub(crate) slots: Vec<Object>,
+    pub(crate) state: GeneratorState,
+}
+
+impl Generator {
+    pub(crate) fn new(closure: Rc<Closure>, slots: Vec<Object>) -> Self {
+        Self {
+            closure,
+            ip: 0,
+            slots,
+            state: GeneratorState::Suspended,
         }
     }
 }
+
+/// A channel between fibers, holding at most one value. Sending to a full channel or
+/// receiving from an empty one blocks the fiber until another fiber makes room.
+#[derive(Default)]
+pub(crate) struct Channel {
+    pub(crate) value: Option<Object>,
+}
+
+/// An enum declaration, with the name of each variant and how many values it carries.
+pub(crate) struct Enum {
+    pub(crate) name: String,
+    pub(crate) variants: Vec<(String, usize)>,
+}
+
+impl Enum {
+    pub(crate) fn new(name: String, variants: Vec<(String, usize)>) -> Self {
+        Self { name, variants }
+    }
+
+    pub(crate) fn find_variant(&self, name: &str) -> Option<usize> {
+        self.variants
+            .iter()
+            .position(|(variant, _)| variant == name)
+    }
+
+    pub(crate) fn variant_name(&self, index: usize) -> String {
+        format!("{}.{}", self.name, self.variants[index].0)
+    }
+
+    /// Variants without values are values themselves, the others construct them.
+    pub(crate) fn member(self: &Rc<Self>, index: usize) -> Object {
+        if self.variants[index].1 == 0 {
+            Object::Variant(Rc::new(Variant::new(self.clone(), index, Vec::new())))
+        } else {
+            Object::Constructor(self.clone(), index)
+        }
+    }
+}
+
+pub(crate) struct Variant {
+    pub(crate) enum_type: Rc<Enum>,
+    pub(crate) index: usize,
+    pub(crate) values: Vec<Object>,
+}
+
+impl Variant {
+    pub(crate) fn new(enum_type: Rc<Enum>, index: usize, values: Vec<Object>) -> Self {
+        Self {
+            enum_type,
+            index,
+            values,
+        }
+    }
+}
+
+impl Drop for Variant {
+    /// Takes apart the variants only this one holds with a stack of values still to drop,
+    /// since dropping them recursively overflows the native stack on long chains.
+    fn drop(&mut self) {
+        let mut pending = std::mem::take(&mut self.values);
+        while let Some(value) = pending.pop() {
+            if let Object::Variant(variant) = value {
+                if let Ok(mut variant) = Rc::try_unwrap(variant) {
+                    pending.append(&mut variant.values);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) struct Class {
+    pub(crate) name: String,
+    pub(crate) methods: StrMap<Rc<Closure>>,
+}
+
+impl Class {
+    pub(crate) fn new(name: String, methods: StrMap<Rc<Closure>>) -> Self {
+        Self { name, methods }
+    }
+}
+
+pub(crate) struct Instance {
+    pub(crate) class: Rc<Class>,
+    pub(crate) fields: StrMap<Object>,
+}
+
+impl Instance {
+    pub(crate) fn new(class: Rc<Class>) -> Self {
+        Self {
+            class,
+            fields: StrMap::default(),
+        }
+    }
+}
+
+/// A method looked up on an instance, which is passed to the method as `this`.
+pub(crate) struct BoundMethod {
+    pub(crate) receiver: Object,
+    pub(crate) method: Rc<Closure>,
+}
+
+impl BoundMethod {
+    pub(crate) fn new(receiver: Object, method: Rc<Closure>) -> Self {
+        Self { receiver, method }
+    }
+}
+
+pub(crate) type NativeFn = fn(&mut VirtualMachine, Vec<Object>) -> Result<Object, String>;
+
+#[derive(Clone)]
+pub(crate) struct NativeFunction {
+    pub(crate) name: String,
+    pub(crate) arity: usize,
+    pub(crate) function: NativeFn,
+}
+
+impl NativeFunction {
+    pub(crate) fn new(name: String, arity: usize, function: NativeFn) -> Self {
+        Self {
+            name,
+            arity,
+            function,
+        }
+    }
+}
+
+pub(crate) struct Module {
+    pub(crate) name: String,
+    pub(crate) members: HashMap<String, Object>,
+}
+
+impl Module {
+    pub(crate) fn new(name: &str, functions: &[(&str, usize, NativeFn)]) -> Self {
+        let members = functions
+            .iter()
+            .map(|(function_name, arity, function)| {
+                (
+                    function_name.to_string(),
+                    Object::NativeFunction(NativeFunction::new(
+                        format!("{name}.{function_name}"),
+                        *arity,
+                        *function,
+                    )),
+                )
+            })
+            .collect();
+
+        Self {
+            name: name.to_string(),
+            members,
+        }
+    }
+}
+
+/// A match found by the `re` module. Its properties are the matched `text`, where it
+/// `start`s and `end`s, the list of its `groups` and one for each named group.
+pub(crate) struct Match {
+    pub(crate) text: String,
+    pub(crate) properties: HashMap<String, Object>,
+}
+
+impl Match {
+    pub(crate) fn new(text: String, properties: HashMap<String, Object>) -> Self {
+        Self { text, properties }
+    }
+}
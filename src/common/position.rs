@@ -3,10 +3,17 @@ pub(crate) struct Position {
     pub(crate) start: usize,
     pub(crate) end: usize,
     pub(crate) line: usize,
+    /// How far `start` is from the beginning of its line, counting from 0 like `line`.
+    pub(crate) column: usize,
 }
 
 impl Position {
-    pub(crate) fn new(start: usize, end: usize, line: usize) -> Self {
-        Self { start, end, line }
+    pub(crate) fn new(start: usize, end: usize, line: usize, column: usize) -> Self {
+        Self {
+            start,
+            end,
+            line,
+            column,
+        }
     }
 }
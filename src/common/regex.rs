@@ -0,0 +1,655 @@
+use std::iter::Peekable;
+use std::str::CharIndices;
+
+/// Programs larger than this are rejected, which bounds what counted repetition like
+/// `(a{100}){100}` can expand to.
+const MAX_PROGRAM_SIZE: usize = 10_000;
+
+/// Properties every match has, which named groups can not shadow.
+const RESERVED_NAMES: [&str; 4] = ["text", "start", "end", "groups"];
+
+/// A compiled regular expression. Patterns are compiled to a program for a Thompson NFA,
+/// which is run as a Pike VM: every thread advances in lockstep over the text, so matching
+/// takes time linear in the text no matter the pattern, and threads are kept in priority
+/// order, so the match found is the one a backtracking engine would find.
+pub(crate) struct Regex {
+    pub(crate) pattern: String,
+    /// The number of capture groups, not counting the whole match.
+    pub(crate) groups: usize,
+    /// The names of the named groups, with their numbers.
+    pub(crate) names: Vec<(String, usize)>,
+    program: Vec<Instruction>,
+}
+
+/// Where a match and each of its groups start and end, in characters. Groups that did not
+/// take part in the match are `None`.
+pub(crate) type Captures = Vec<Option<(usize, usize)>>;
+
+#[derive(Clone)]
+enum Instruction {
+    Character(char),
+    Any,
+    Class(Class),
+    /// Continues at both targets, preferring the first.
+    Split(usize, usize),
+    Jump(usize),
+    Save(usize),
+    Start,
+    End,
+    WordBoundary(bool),
+    Match,
+}
+
+#[derive(Clone)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn new(negated: bool, ranges: Vec<(char, char)>) -> Self {
+        Self { negated, ranges }
+    }
+
+    fn digit(negated: bool) -> Self {
+        Self::new(negated, vec![('0', '9')])
+    }
+
+    fn word(negated: bool) -> Self {
+        Self::new(
+            negated,
+            vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        )
+    }
+
+    fn space(negated: bool) -> Self {
+        Self::new(negated, vec![('\t', '\r'), (' ', ' ')])
+    }
+
+    fn matches(&self, character: char) -> bool {
+        let in_ranges = self
+            .ranges
+            .iter()
+            .any(|(low, high)| (*low..=*high).contains(&character));
+        in_ranges != self.negated
+    }
+
+    /// The ranges of every character the class does not match. Used for `\D`, `\W` and
+    /// `\S` inside brackets.
+    fn complement(&self) -> Vec<(char, char)> {
+        let mut complement = Vec::new();
+        let mut next = Some('\0');
+        for &(low, high) in &self.ranges {
+            if let Some(start) = next {
+                if start < low {
+                    complement.push((start, previous(low)));
+                }
+            }
+            next = following(high);
+        }
+        if let Some(start) = next {
+            complement.push((start, char::MAX));
+        }
+        complement
+    }
+}
+
+fn previous(character: char) -> char {
+    (0..character as u32)
+        .rev()
+        .find_map(char::from_u32)
+        .unwrap_or('\0')
+}
+
+fn following(character: char) -> Option<char> {
+    (character as u32 + 1..=char::MAX as u32).find_map(char::from_u32)
+}
+
+enum Node {
+    Empty,
+    Character(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    WordBoundary(bool),
+    Group(Option<usize>, Box<Node>),
+    Concatenation(Vec<Node>),
+    Alternation(Vec<Node>),
+    Repetition {
+        node: Box<Node>,
+        min: usize,
+        max: Option<usize>,
+        greedy: bool,
+    },
+}
+
+struct Parser<'a> {
+    pattern: &'a str,
+    characters: Peekable<CharIndices<'a>>,
+    groups: usize,
+    names: Vec<(String, usize)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(pattern: &'a str) -> Self {
+        Self {
+            pattern,
+            characters: pattern.char_indices().peekable(),
+            groups: 0,
+            names: Vec::new(),
+        }
+    }
+
+    fn error(&self, problem: String) -> String {
+        format!("Invalid pattern. '{}' {problem}.", self.pattern)
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.characters.peek().map(|(_, character)| *character)
+    }
+
+    fn next(&mut self) -> Option<char> {
+        self.characters.next().map(|(_, character)| character)
+    }
+
+    fn parse(&mut self) -> Result<Node, String> {
+        let node = self.parse_alternation()?;
+        match self.next() {
+            Some(_) => Err(self.error("has an unmatched ')'".to_string())),
+            None => Ok(node),
+        }
+    }
+
+    fn parse_alternation(&mut self) -> Result<Node, String> {
+        let mut alternatives = vec![self.parse_concatenation()?];
+        while self.peek() == Some('|') {
+            self.next();
+            alternatives.push(self.parse_concatenation()?);
+        }
+        if alternatives.len() == 1 {
+            Ok(alternatives.remove(0))
+        } else {
+            Ok(Node::Alternation(alternatives))
+        }
+    }
+
+    fn parse_concatenation(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(character) = self.peek() {
+            if character == '|' || character == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifiers(atom)?);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.remove(0)),
+            _ => Ok(Node::Concatenation(nodes)),
+        }
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let (index, character) = self.characters.next().expect("Caller peeked a character.");
+        match character {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Start),
+            '$' => Ok(Node::End),
+            '[' => self.parse_class(),
+            '(' => self.parse_group(),
+            '\\' => match self.parse_escape()? {
+                Escape::Character(character) => Ok(Node::Character(character)),
+                Escape::Class(class) => Ok(Node::Class(class)),
+                Escape::WordBoundary(negated) => Ok(Node::WordBoundary(negated)),
+            },
+            '*' | '+' | '?' => Err(self.error(format!("has nothing to repeat at {index}"))),
+            character => Ok(Node::Character(character)),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let index = if self.peek() == Some('?') {
+            self.next();
+            match self.next() {
+                Some(':') => None,
+                Some('<') => Some(self.parse_group_name()?),
+                Some('P') if self.next() == Some('<') => Some(self.parse_group_name()?),
+                _ => return Err(self.error("has an unknown group kind after '(?'".to_string())),
+            }
+        } else {
+            self.groups += 1;
+            Some(self.groups)
+        };
+        let node = self.parse_alternation()?;
+        if self.next() != Some(')') {
+            return Err(self.error("has an unclosed group".to_string()));
+        }
+        Ok(Node::Group(index, Box::new(node)))
+    }
+
+    /// Parses the name of a `(?<name>...)` group, up to and including the '>'.
+    fn parse_group_name(&mut self) -> Result<usize, String> {
+        let mut name = String::new();
+        loop {
+            match self.next() {
+                Some('>') => break,
+                Some(character) if character.is_alphanumeric() || character == '_' => {
+                    name.push(character)
+                }
+                _ => return Err(self.error("has an unclosed or invalid group name".to_string())),
+            }
+        }
+        if name.is_empty() || name.starts_with(|character: char| character.is_ascii_digit()) {
+            return Err(self.error(format!("has an invalid group name '{name}'")));
+        }
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            return Err(self.error(format!(
+                "names a group '{name}', which is already a property of matches"
+            )));
+        }
+        if self.names.iter().any(|(existing, _)| existing == &name) {
+            return Err(self.error(format!("names two groups '{name}'")));
+        }
+        self.groups += 1;
+        self.names.push((name, self.groups));
+        Ok(self.groups)
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.next();
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let low = match self.next() {
+                None => return Err(self.error("has an unclosed character class".to_string())),
+                Some(']') if !first => break,
+                Some('\\') => match self.parse_escape()? {
+                    Escape::Character(character) => character,
+                    Escape::Class(class) => {
+                        if class.negated {
+                            ranges.extend(class.complement());
+                        } else {
+                            ranges.extend(class.ranges);
+                        }
+                        first = false;
+                        continue;
+                    }
+                    Escape::WordBoundary(_) => {
+                        return Err(self.error("has a '\\b' inside a character class".to_string()))
+                    }
+                },
+                Some(character) => character,
+            };
+            first = false;
+
+            let mut lookahead = self.characters.clone();
+            let is_range = lookahead.next().map(|(_, character)| character) == Some('-')
+                && !matches!(lookahead.next(), None | Some((_, ']')));
+            if !is_range {
+                ranges.push((low, low));
+                continue;
+            }
+            self.next();
+            let high = match self.next() {
+                Some('\\') => match self.parse_escape()? {
+                    Escape::Character(character) => character,
+                    _ => return Err(self.error("has a class as the end of a range".to_string())),
+                },
+                Some(character) => character,
+                None => unreachable!("The lookahead found a character."),
+            };
+            if high < low {
+                return Err(self.error(format!("has an invalid range '{low}-{high}'")));
+            }
+            ranges.push((low, high));
+        }
+        ranges.sort_unstable();
+        Ok(Node::Class(Class::new(negated, merge(ranges))))
+    }
+
+    fn parse_escape(&mut self) -> Result<Escape, String> {
+        let escape = match self.next() {
+            None => return Err(self.error("ends with a '\\'".to_string())),
+            Some('d') => Escape::Class(Class::digit(false)),
+            Some('D') => Escape::Class(Class::digit(true)),
+            Some('w') => Escape::Class(Class::word(false)),
+            Some('W') => Escape::Class(Class::word(true)),
+            Some('s') => Escape::Class(Class::space(false)),
+            Some('S') => Escape::Class(Class::space(true)),
+            Some('b') => Escape::WordBoundary(false),
+            Some('B') => Escape::WordBoundary(true),
+            Some('n') => Escape::Character('\n'),
+            Some('t') => Escape::Character('\t'),
+            Some('r') => Escape::Character('\r'),
+            Some(character) if character.is_alphanumeric() => {
+                return Err(self.error(format!("has an unknown escape '\\{character}'")))
+            }
+            Some(character) => Escape::Character(character),
+        };
+        Ok(escape)
+    }
+
+    fn parse_quantifiers(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => match self.parse_counts()? {
+                    Some(counts) => counts,
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            self.next();
+            let greedy = self.peek() != Some('?');
+            if !greedy {
+                self.next();
+            }
+            node = Node::Repetition {
+                node: Box::new(node),
+                min,
+                max,
+                greedy,
+            };
+        }
+    }
+
+    /// Parses `{n}`, `{n,}` or `{n,m}`, leaving the closing brace to be consumed. A brace
+    /// that does not start one of those is not a quantifier, and is matched literally.
+    fn parse_counts(&mut self) -> Result<Option<(usize, Option<usize>)>, String> {
+        let mut lookahead = self.characters.clone();
+        lookahead.next();
+        let mut text = String::new();
+        let closed = loop {
+            match lookahead.peek() {
+                Some((_, '}')) => break true,
+                Some((_, character)) if character.is_ascii_digit() || *character == ',' => {
+                    text.push(*character);
+                    lookahead.next();
+                }
+                _ => break false,
+            }
+        };
+        if !closed {
+            return Ok(None);
+        }
+        let counts = match text.split_once(',') {
+            None => text.parse().ok().map(|count| (count, Some(count))),
+            Some((min, "")) => min.parse().ok().map(|min| (min, None)),
+            Some((min, max)) => min.parse().ok().zip(max.parse().ok().map(Some)),
+        };
+        let Some((min, max)) = counts else {
+            return Ok(None);
+        };
+        if matches!(max, Some(max) if max < min) {
+            return Err(self.error(format!("has a repetition '{{{text}}}' with min above max")));
+        }
+        self.characters = lookahead;
+        Ok(Some((min, max)))
+    }
+}
+
+enum Escape {
+    Character(char),
+    Class(Class),
+    WordBoundary(bool),
+}
+
+/// Merges sorted ranges that overlap or touch.
+fn merge(ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    let mut merged: Vec<(char, char)> = Vec::with_capacity(ranges.len());
+    for (low, high) in ranges {
+        match merged.last_mut() {
+            Some(last) if !matches!(following(last.1), Some(next) if low > next) => {
+                last.1 = last.1.max(high);
+            }
+            _ => merged.push((low, high)),
+        }
+    }
+    merged
+}
+
+struct Compiler {
+    program: Vec<Instruction>,
+}
+
+impl Compiler {
+    fn emit(&mut self, instruction: Instruction) -> Result<usize, ()> {
+        if self.program.len() >= MAX_PROGRAM_SIZE {
+            return Err(());
+        }
+        self.program.push(instruction);
+        Ok(self.program.len() - 1)
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), ()> {
+        match node {
+            Node::Empty => {}
+            Node::Character(character) => {
+                self.emit(Instruction::Character(*character))?;
+            }
+            Node::Any => {
+                self.emit(Instruction::Any)?;
+            }
+            Node::Class(class) => {
+                self.emit(Instruction::Class(class.clone()))?;
+            }
+            Node::Start => {
+                self.emit(Instruction::Start)?;
+            }
+            Node::End => {
+                self.emit(Instruction::End)?;
+            }
+            Node::WordBoundary(negated) => {
+                self.emit(Instruction::WordBoundary(*negated))?;
+            }
+            Node::Group(index, node) => match index {
+                Some(index) => {
+                    self.emit(Instruction::Save(index * 2))?;
+                    self.compile(node)?;
+                    self.emit(Instruction::Save(index * 2 + 1))?;
+                }
+                None => self.compile(node)?,
+            },
+            Node::Concatenation(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alternation(alternatives) => {
+                let mut exits = Vec::new();
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i == alternatives.len() - 1 {
+                        self.compile(alternative)?;
+                        break;
+                    }
+                    let split = self.emit(Instruction::Split(0, 0))?;
+                    self.compile(alternative)?;
+                    exits.push(self.emit(Instruction::Jump(0))?);
+                    self.program[split] = Instruction::Split(split + 1, self.program.len());
+                }
+                let end = self.program.len();
+                for exit in exits {
+                    self.program[exit] = Instruction::Jump(end);
+                }
+            }
+            Node::Repetition {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    None => {
+                        let split = self.emit(Instruction::Split(0, 0))?;
+                        self.compile(node)?;
+                        self.emit(Instruction::Jump(split))?;
+                        self.patch_split(split, self.program.len(), *greedy);
+                    }
+                    Some(max) => {
+                        let mut splits = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.emit(Instruction::Split(0, 0))?);
+                            self.compile(node)?;
+                        }
+                        let end = self.program.len();
+                        for split in splits {
+                            self.patch_split(split, end, *greedy);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Points a split at the instruction after it and at `exit`, preferring to stay in the
+    /// repetition when it is greedy.
+    fn patch_split(&mut self, split: usize, exit: usize, greedy: bool) {
+        self.program[split] = if greedy {
+            Instruction::Split(split + 1, exit)
+        } else {
+            Instruction::Split(exit, split + 1)
+        };
+    }
+}
+
+struct Thread {
+    pc: usize,
+    slots: Vec<Option<usize>>,
+}
+
+impl Regex {
+    pub(crate) fn new(pattern: &str) -> Result<Self, String> {
+        let mut parser = Parser::new(pattern);
+        let node = parser.parse()?;
+        let mut compiler = Compiler {
+            program: Vec::new(),
+        };
+        compiler
+            .compile(&node)
+            .and_then(|_| compiler.emit(Instruction::Match).map(|_| ()))
+            .map_err(|_| parser.error("is too large".to_string()))?;
+        Ok(Self {
+            pattern: pattern.to_string(),
+            groups: parser.groups,
+            names: parser.names,
+            program: compiler.program,
+        })
+    }
+
+    /// Finds the leftmost match that starts at or after `start`.
+    pub(crate) fn find_at(&self, text: &[char], start: usize) -> Option<Captures> {
+        self.search(text, start, false)
+    }
+
+    /// Finds a match that starts exactly at `start`.
+    pub(crate) fn match_at(&self, text: &[char], start: usize) -> Option<Captures> {
+        self.search(text, start, true)
+    }
+
+    /// Runs every thread in lockstep. Unless `anchored`, a new thread starts at every
+    /// position until something matches.
+    fn search(&self, text: &[char], start: usize, anchored: bool) -> Option<Captures> {
+        let slot_count = (self.groups + 1) * 2;
+        let mut seen = vec![usize::MAX; self.program.len()];
+        let mut current = Vec::new();
+        let mut next = Vec::new();
+        let mut matched = None;
+
+        for position in start..=text.len() {
+            if matched.is_none() && (!anchored || position == start) {
+                let mut slots = vec![None; slot_count];
+                slots[0] = Some(position);
+                self.add_thread(&mut current, &mut seen, text, position, 0, slots);
+            }
+            if current.is_empty() && (matched.is_some() || anchored) {
+                break;
+            }
+            for thread in current.drain(..) {
+                let advances = match &self.program[thread.pc] {
+                    Instruction::Character(character) => text.get(position) == Some(character),
+                    Instruction::Any => matches!(text.get(position), Some(c) if *c != '\n'),
+                    Instruction::Class(class) => {
+                        matches!(text.get(position), Some(c) if class.matches(*c))
+                    }
+                    Instruction::Match => {
+                        let mut slots = thread.slots;
+                        slots[1] = Some(position);
+                        matched = Some(slots);
+                        // Every remaining thread has a lower priority than this one.
+                        break;
+                    }
+                    _ => false,
+                };
+                if advances {
+                    let pc = thread.pc + 1;
+                    self.add_thread(&mut next, &mut seen, text, position + 1, pc, thread.slots);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            next.clear();
+        }
+
+        matched.map(|slots| slots.chunks(2).map(|pair| pair[0].zip(pair[1])).collect())
+    }
+
+    /// Follows the instructions that do not consume a character from `pc`, adding a thread
+    /// for each instruction that does. `seen` marks the instructions already added at this
+    /// position, since a thread reaching one later always has a lower priority.
+    fn add_thread(
+        &self,
+        threads: &mut Vec<Thread>,
+        seen: &mut [usize],
+        text: &[char],
+        position: usize,
+        pc: usize,
+        slots: Vec<Option<usize>>,
+    ) {
+        let mut pending = vec![(pc, slots)];
+        while let Some((pc, mut slots)) = pending.pop() {
+            if seen[pc] == position {
+                continue;
+            }
+            seen[pc] = position;
+            match &self.program[pc] {
+                Instruction::Split(first, second) => {
+                    pending.push((*second, slots.clone()));
+                    pending.push((*first, slots));
+                }
+                Instruction::Jump(target) => pending.push((*target, slots)),
+                Instruction::Save(slot) => {
+                    slots[*slot] = Some(position);
+                    pending.push((pc + 1, slots));
+                }
+                Instruction::Start => {
+                    if position == 0 {
+                        pending.push((pc + 1, slots));
+                    }
+                }
+                Instruction::End => {
+                    if position == text.len() {
+                        pending.push((pc + 1, slots));
+                    }
+                }
+                Instruction::WordBoundary(negated) => {
+                    let is_word = |c: &char| c.is_alphanumeric() || *c == '_';
+                    let before = position > 0 && is_word(&text[position - 1]);
+                    let after = matches!(text.get(position), Some(c) if is_word(c));
+                    if (before != after) != *negated {
+                        pending.push((pc + 1, slots));
+                    }
+                }
+                _ => threads.push(Thread { pc, slots }),
+            }
+        }
+    }
+}
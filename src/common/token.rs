@@ -12,6 +12,7 @@ pub(crate) enum TokenKind {
     Minus,
     Star,
     Slash,
+    Percent,
 
     Assign,
     Equal,
@@ -26,25 +27,42 @@ pub(crate) enum TokenKind {
     CloseParen,
     OpenBrace,
     CloseBrace,
+    OpenBracket,
+    CloseBracket,
     Comma,
+    Pipe,
     Dot,
+    DotDot,
+    DotDotEqual,
+    DotDotDot,
+    Colon,
+    Arrow,
+    QuestionQuestion,
+    QuestionDot,
 
     And,
+    Assert,
     Class,
+    Const,
     Else,
+    Enum,
     False,
     Fun,
     For,
     If,
+    In,
+    Is,
     Nil,
     Or,
     Print,
     Return,
+    Spawn,
     Super,
     This,
     True,
     Var,
     While,
+    Yield,
 
     Eof,
 }
@@ -60,6 +78,7 @@ impl Display for TokenKind {
             Self::Minus => write!(f, "-"),
             Self::Star => write!(f, "*"),
             Self::Slash => write!(f, "/"),
+            Self::Percent => write!(f, "%"),
             Self::Assign => write!(f, "="),
             Self::Equal => write!(f, "=="),
             Self::Not => write!(f, "!"),
@@ -72,25 +91,42 @@ impl Display for TokenKind {
             Self::CloseParen => write!(f, ")"),
             Self::OpenBrace => write!(f, "{{"),
             Self::CloseBrace => write!(f, "}}"),
+            Self::OpenBracket => write!(f, "["),
+            Self::CloseBracket => write!(f, "]"),
             Self::Comma => write!(f, ","),
+            Self::Pipe => write!(f, "|"),
             Self::Dot => write!(f, "."),
+            Self::DotDot => write!(f, ".."),
+            Self::DotDotEqual => write!(f, "..="),
+            Self::DotDotDot => write!(f, "..."),
+            Self::Colon => write!(f, ":"),
+            Self::Arrow => write!(f, "->"),
+            Self::QuestionQuestion => write!(f, "??"),
+            Self::QuestionDot => write!(f, "?."),
 
             Self::And => write!(f, "and"),
+            Self::Assert => write!(f, "assert"),
             Self::Class => write!(f, "class"),
+            Self::Const => write!(f, "const"),
             Self::Else => write!(f, "else"),
+            Self::Enum => write!(f, "enum"),
             Self::False => write!(f, "false"),
             Self::Fun => write!(f, "fun"),
             Self::For => write!(f, "for"),
             Self::If => write!(f, "if"),
+            Self::In => write!(f, "in"),
+            Self::Is => write!(f, "is"),
             Self::Nil => write!(f, "nil"),
             Self::Or => write!(f, "or"),
             Self::Print => write!(f, "print"),
             Self::Return => write!(f, "return"),
+            Self::Spawn => write!(f, "spawn"),
             Self::Super => write!(f, "super"),
             Self::This => write!(f, "this"),
             Self::True => write!(f, "true"),
             Self::Var => write!(f, "var"),
             Self::While => write!(f, "while"),
+            Self::Yield => write!(f, "yield"),
             Self::Eof => write!(f, "\0"),
         }
     }
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+
+use crate::common::ast::{
+    BlockStatement, CallExpression, ElseStatement, Expression, IfStatement, Parameter, Statement,
+};
+
+/// Collects every name referenced from a function nested somewhere in `statements`. Locals
+/// with one of these names are boxed into cells, so closures share them with their
+/// enclosing function instead of copying them.
+pub(crate) fn captured_names(statements: &[Statement]) -> HashSet<String> {
+    let mut names = Names::new(false);
+    for statement in statements {
+        visit_statement(statement, false, &mut names);
+    }
+    names.names
+}
+
+/// Collects every name that is assigned to anywhere in `statements`.
+pub(crate) fn assigned_names(statements: &[Statement]) -> HashSet<String> {
+    let mut names = Names::new(true);
+    for statement in statements {
+        visit_statement(statement, false, &mut names);
+    }
+    names.names
+}
+
+struct Names {
+    assigned_only: bool,
+    names: HashSet<String>,
+}
+
+impl Names {
+    fn new(assigned_only: bool) -> Self {
+        Self {
+            assigned_only,
+            names: HashSet::new(),
+        }
+    }
+}
+
+fn visit_block(block: &BlockStatement, nested: bool, names: &mut Names) {
+    for statement in &block.statements {
+        visit_statement(statement, nested, names);
+    }
+}
+
+fn visit_statement(statement: &Statement, nested: bool, names: &mut Names) {
+    match statement {
+        Statement::Function(statement) => {
+            visit_function(&statement.parameters, &statement.block, nested, names)
+        }
+        Statement::Class(statement) => {
+            for method in &statement.methods {
+                visit_function(&method.parameters, &method.block, nested, names);
+            }
+        }
+        Statement::Enum(_) => {}
+        Statement::If(statement) => visit_if(statement, nested, names),
+        Statement::For(statement) => {
+            visit_expression(&statement.iterable, nested, names);
+            visit_block(&statement.do_block, nested, names);
+        }
+        Statement::While(statement) => {
+            visit_expression(&statement.condition, nested, names);
+            visit_block(&statement.do_block, nested, names);
+        }
+        Statement::Block(statement) => visit_block(statement, nested, names),
+        Statement::Variable(statement) => visit_expression(&statement.initializer, nested, names),
+        Statement::Return(statement) => visit_expression(&statement.expression, nested, names),
+        Statement::Yield(statement) => visit_expression(&statement.expression, nested, names),
+        Statement::Print(statement) => visit_expression(&statement.expression, nested, names),
+        Statement::Assert(statement) => {
+            visit_expression(&statement.condition, nested, names);
+            if let Some(message) = &statement.message {
+                visit_expression(message, nested, names);
+            }
+        }
+        Statement::Expression(statement) => visit_expression(&statement.expression, nested, names),
+    }
+}
+
+/// Defaults are evaluated where the function is defined, so only its body is nested.
+fn visit_function(
+    parameters: &[Parameter],
+    block: &BlockStatement,
+    nested: bool,
+    names: &mut Names,
+) {
+    for default in parameters
+        .iter()
+        .filter_map(|parameter| parameter.default.as_ref())
+    {
+        visit_expression(default, nested, names);
+    }
+    visit_block(block, true, names);
+}
+
+fn visit_if(statement: &IfStatement, nested: bool, names: &mut Names) {
+    visit_expression(&statement.condition, nested, names);
+    visit_block(&statement.then_branch, nested, names);
+    match &*statement.else_branch {
+        Some(ElseStatement::If(statement)) => visit_if(statement, nested, names),
+        Some(ElseStatement::Block(block)) => visit_block(block, nested, names),
+        None => {}
+    }
+}
+
+fn visit_call(expression: &CallExpression, nested: bool, names: &mut Names) {
+    visit_expression(&expression.callee, nested, names);
+    for argument in &expression.arguments {
+        visit_expression(argument, nested, names);
+    }
+    for (_, argument) in &expression.keyword_arguments {
+        visit_expression(argument, nested, names);
+    }
+}
+
+fn visit_expression(expression: &Expression, nested: bool, names: &mut Names) {
+    match expression {
+        Expression::Assignment(expression) => {
+            if nested || names.assigned_only {
+                names.names.insert(expression.identifier.lexeme.clone());
+            }
+            visit_expression(&expression.initializer, nested, names);
+        }
+        Expression::Binary(expression) => {
+            visit_expression(&expression.left, nested, names);
+            visit_expression(&expression.right, nested, names);
+        }
+        Expression::Unary(expression) => visit_expression(&expression.right, nested, names),
+        Expression::Group(expression) => visit_expression(&expression.child, nested, names),
+        Expression::Call(expression) => visit_call(expression, nested, names),
+        Expression::Spawn(expression) => visit_call(&expression.call, nested, names),
+        Expression::Get(expression) => visit_expression(&expression.object, nested, names),
+        Expression::Set(expression) => {
+            visit_expression(&expression.object, nested, names);
+            visit_expression(&expression.value, nested, names);
+        }
+        Expression::Lambda(expression) => {
+            visit_function(&expression.parameters, &expression.block, nested, names)
+        }
+        Expression::List(expression) => {
+            for element in &expression.elements {
+                visit_expression(element, nested, names);
+            }
+        }
+        Expression::Literal(_) => {}
+        Expression::Variable(expression) => {
+            if nested && !names.assigned_only {
+                names.names.insert(expression.identifier.lexeme.clone());
+            }
+        }
+    }
+}
@@ -0,0 +1,652 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    rc::Rc,
+};
+
+use crate::{
+    common::{
+        ast::{
+            AssignmentExpression, BinaryExpression, BlockStatement, CallExpression, ElseStatement,
+            Expression, FunctionStatement, IfStatement, LiteralExpression, Parameter, Program,
+            Statement, UnaryExpression, VariableStatement,
+        },
+        error::{Error, ErrorKind},
+        object::Object,
+        position::Position,
+        regex::Regex,
+        token::{Token, TokenKind},
+    },
+    stdlib,
+};
+
+use super::captures::assigned_names;
+
+#[derive(Clone)]
+enum Type {
+    Any,
+    Nil,
+    Boolean,
+    Number,
+    String,
+    List,
+    Range,
+    Module,
+    /// A function, with its signature when the checker knows which function it is.
+    Function(Option<Rc<FunctionType>>),
+}
+
+impl Type {
+    fn from_annotation(annotation: &Token) -> Option<Self> {
+        match annotation.lexeme.as_str() {
+            "any" => Some(Self::Any),
+            "nil" => Some(Self::Nil),
+            "bool" => Some(Self::Boolean),
+            "number" => Some(Self::Number),
+            "string" => Some(Self::String),
+            "list" => Some(Self::List),
+            "range" => Some(Self::Range),
+            "module" => Some(Self::Module),
+            "fun" => Some(Self::Function(None)),
+            _ => None,
+        }
+    }
+
+    /// Whether a value of type `other` may be used where `self` is expected.
+    fn accepts(&self, other: &Type) -> bool {
+        matches!(
+            (self, other),
+            (Self::Any, _)
+                | (_, Self::Any)
+                | (Self::Nil, Self::Nil)
+                | (Self::Boolean, Self::Boolean)
+                | (Self::Number, Self::Number)
+                | (Self::String, Self::String)
+                | (Self::List, Self::List)
+                | (Self::Range, Self::Range)
+                | (Self::Module, Self::Module)
+                | (Self::Function(_), Self::Function(_))
+        )
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Any => write!(f, "any"),
+            Self::Nil => write!(f, "nil"),
+            Self::Boolean => write!(f, "bool"),
+            Self::Number => write!(f, "number"),
+            Self::String => write!(f, "string"),
+            Self::List => write!(f, "list"),
+            Self::Range => write!(f, "range"),
+            Self::Module => write!(f, "module"),
+            Self::Function(_) => write!(f, "fun"),
+        }
+    }
+}
+
+struct FunctionType {
+    name: String,
+    parameters: Vec<(String, Type)>,
+    returns: Type,
+}
+
+/// Checks a program before it runs. Types come from annotations and, for variables that
+/// are never reassigned, from their initializers; everything else is `any`, which is
+/// compatible with every type, so unannotated programs are only rejected for operations
+/// that would fail at runtime.
+pub(crate) struct Checker {
+    scopes: Vec<HashMap<String, Type>>,
+    assigned_names: HashSet<String>,
+    return_types: Vec<Type>,
+    errors: Vec<Error>,
+}
+
+impl Checker {
+    pub(crate) fn new() -> Self {
+        Self {
+            scopes: vec![stdlib::globals()
+                .into_iter()
+                .map(|(name, object)| match object {
+                    Object::Module(_) => (name, Type::Module),
+                    _ => (name, Type::Function(None)),
+                })
+                .collect()],
+            assigned_names: HashSet::new(),
+            return_types: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub(crate) fn check(&mut self, program: &Program) -> Vec<Error> {
+        self.assigned_names = assigned_names(program);
+        self.declare_functions(program);
+        for statement in program {
+            self.check_statement(statement);
+        }
+        std::mem::take(&mut self.errors)
+    }
+
+    fn check_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Function(statement) => self.check_function_statement(statement),
+            Statement::Class(statement) => {
+                self.declare(&statement.identifier, Type::Any);
+                for method in &statement.methods {
+                    self.check_function_statement(method);
+                }
+            }
+            Statement::Enum(statement) => self.declare(&statement.identifier, Type::Any),
+            Statement::If(statement) => self.check_if_statement(statement),
+            Statement::For(statement) => {
+                let element_type = match self.check_expression(&statement.iterable) {
+                    Type::Range => Type::Number,
+                    Type::String => Type::String,
+                    Type::List | Type::Function(_) | Type::Any => Type::Any,
+                    iterable_type => {
+                        self.report(
+                            format!("Invalid iterable. Can not iterate over '{iterable_type}'."),
+                            statement.iterable.position(),
+                        );
+                        Type::Any
+                    }
+                };
+                self.scopes.push(HashMap::new());
+                self.declare(&statement.identifier, element_type);
+                self.check_block(&statement.do_block);
+                self.scopes.pop();
+            }
+            Statement::While(statement) => {
+                self.check_expression(&statement.condition);
+                self.check_block(&statement.do_block);
+            }
+            Statement::Block(statement) => self.check_block(statement),
+            Statement::Variable(statement) => self.check_variable_statement(statement),
+            Statement::Return(statement) => {
+                let value_type = self.check_expression(&statement.expression);
+                if let Some(return_type) = self.return_types.last().cloned() {
+                    if !return_type.accepts(&value_type) {
+                        self.report(
+                            format!(
+                                "Type mismatch. Expected a return value of type '{return_type}', found '{value_type}'."
+                            ),
+                            statement.expression.position(),
+                        );
+                    }
+                }
+            }
+            Statement::Yield(statement) => {
+                self.check_expression(&statement.expression);
+            }
+            Statement::Print(statement) => {
+                self.check_expression(&statement.expression);
+            }
+            Statement::Assert(statement) => {
+                self.check_expression(&statement.condition);
+                if let Some(message) = &statement.message {
+                    self.check_expression(message);
+                }
+            }
+            Statement::Expression(statement) => {
+                self.check_expression(&statement.expression);
+            }
+        }
+    }
+
+    fn check_block(&mut self, block: &BlockStatement) {
+        self.scopes.push(HashMap::new());
+        self.declare_functions(&block.statements);
+        for statement in &block.statements {
+            self.check_statement(statement);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_if_statement(&mut self, statement: &IfStatement) {
+        self.check_expression(&statement.condition);
+        self.scopes.push(HashMap::new());
+        for binding in statement
+            .pattern
+            .iter()
+            .flat_map(|pattern| &pattern.bindings)
+        {
+            self.declare(binding, Type::Any);
+        }
+        self.check_block(&statement.then_branch);
+        self.scopes.pop();
+        match &*statement.else_branch {
+            Some(ElseStatement::If(statement)) => self.check_if_statement(statement),
+            Some(ElseStatement::Block(block)) => self.check_block(block),
+            None => {}
+        }
+    }
+
+    fn check_variable_statement(&mut self, statement: &VariableStatement) {
+        let value_type = self.check_expression(&statement.initializer);
+        let variable_type = match &statement.annotation {
+            Some(annotation) => {
+                let annotated_type = self.check_annotation(annotation);
+                if !annotated_type.accepts(&value_type) {
+                    self.report(
+                        format!(
+                            "Type mismatch. '{}' is declared as '{annotated_type}' but initialized with '{value_type}'.",
+                            statement.identifier.lexeme
+                        ),
+                        statement.initializer.position(),
+                    );
+                }
+                annotated_type
+            }
+            None if self.assigned_names.contains(&statement.identifier.lexeme) => Type::Any,
+            None => value_type,
+        };
+        self.declare(&statement.identifier, variable_type);
+    }
+
+    /// The function itself was declared with the statements around it.
+    fn check_function_statement(&mut self, statement: &FunctionStatement) {
+        self.check_function(
+            &statement.parameters,
+            &statement.block,
+            statement.return_annotation.as_ref(),
+        );
+    }
+
+    fn check_function(
+        &mut self,
+        parameters: &[Parameter],
+        block: &BlockStatement,
+        return_annotation: Option<&Token>,
+    ) {
+        let mut scope = HashMap::new();
+        for parameter in parameters {
+            if let Some(annotation) = &parameter.annotation {
+                self.check_annotation(annotation);
+            }
+            let parameter_type = Self::parameter_type(parameter);
+            if let Some(default) = &parameter.default {
+                let default_type = self.check_expression(default);
+                if !parameter_type.accepts(&default_type) {
+                    self.report(
+                        format!(
+                            "Type mismatch. Parameter '{}' is declared as '{parameter_type}' but defaults to '{default_type}'.",
+                            parameter.identifier.lexeme
+                        ),
+                        default.position(),
+                    );
+                }
+            }
+            scope.insert(parameter.identifier.lexeme.clone(), parameter_type);
+        }
+
+        let return_type = match return_annotation {
+            Some(annotation) => self.check_annotation(annotation),
+            None => Type::Any,
+        };
+        self.scopes.push(scope);
+        self.return_types.push(return_type);
+        self.declare_functions(&block.statements);
+        for statement in &block.statements {
+            self.check_statement(statement);
+        }
+        self.return_types.pop();
+        self.scopes.pop();
+    }
+
+    fn check_expression(&mut self, expression: &Expression) -> Type {
+        match expression {
+            Expression::Assignment(expression) => self.check_assignment_expression(expression),
+            Expression::Binary(expression) => self.check_binary_expression(expression),
+            Expression::Unary(expression) => self.check_unary_expression(expression),
+            Expression::Group(expression) => self.check_expression(&expression.child),
+            Expression::Call(expression) => self.check_call_expression(expression),
+            Expression::Spawn(expression) => {
+                self.check_call_expression(&expression.call);
+                Type::Any
+            }
+            Expression::Get(expression) => {
+                self.check_expression(&expression.object);
+                Type::Any
+            }
+            Expression::Set(expression) => {
+                self.check_expression(&expression.object);
+                self.check_expression(&expression.value)
+            }
+            Expression::Lambda(expression) => {
+                self.check_function(&expression.parameters, &expression.block, None);
+                Type::Function(Some(Rc::new(FunctionType {
+                    name: expression.name(),
+                    parameters: Self::parameter_types(&expression.parameters),
+                    returns: Type::Any,
+                })))
+            }
+            Expression::List(expression) => {
+                for element in &expression.elements {
+                    self.check_expression(element);
+                }
+                Type::List
+            }
+            Expression::Literal(expression) => Self::literal_type(expression),
+            Expression::Variable(expression) => self.lookup(&expression.identifier.lexeme),
+        }
+    }
+
+    fn check_assignment_expression(&mut self, expression: &AssignmentExpression) -> Type {
+        let value_type = self.check_expression(&expression.initializer);
+        let variable_type = self.lookup(&expression.identifier.lexeme);
+        if !variable_type.accepts(&value_type) {
+            self.report(
+                format!(
+                    "Type mismatch. Can not assign '{value_type}' to '{}' of type '{variable_type}'.",
+                    expression.identifier.lexeme
+                ),
+                expression.initializer.position(),
+            );
+        }
+        value_type
+    }
+
+    fn check_binary_expression(&mut self, expression: &BinaryExpression) -> Type {
+        let left = self.check_expression(&expression.left);
+        let right = self.check_expression(&expression.right);
+        let operator = &expression.operator;
+        if operator.kind == TokenKind::QuestionQuestion {
+            return match left {
+                Type::Nil => right,
+                Type::Any => Type::Any,
+                left => left,
+            };
+        }
+        let result = match operator.kind {
+            TokenKind::Plus => match (&left, &right) {
+                (Type::Number, Type::Number | Type::Any) | (Type::Any, Type::Number) => {
+                    Some(Type::Number)
+                }
+                (Type::String, Type::String | Type::Any) | (Type::Any, Type::String) => {
+                    Some(Type::String)
+                }
+                (Type::Any, Type::Any) => Some(Type::Any),
+                _ => None,
+            },
+            TokenKind::Minus | TokenKind::Star | TokenKind::Slash | TokenKind::Percent => {
+                Self::numeric(&left, &right).then_some(Type::Number)
+            }
+            TokenKind::Greater
+            | TokenKind::GreaterEqual
+            | TokenKind::Lesser
+            | TokenKind::LesserEqual => Self::numeric(&left, &right).then_some(Type::Boolean),
+            TokenKind::DotDot | TokenKind::DotDotEqual => {
+                Self::numeric(&left, &right).then_some(Type::Range)
+            }
+            _ => Some(Type::Boolean),
+        };
+        result.unwrap_or_else(|| {
+            self.report(
+                format!(
+                    "Type mismatch. Binary operator '{}' is not defined for '{left}' and '{right}'.",
+                    operator.lexeme
+                ),
+                expression.position(),
+            );
+            Type::Any
+        })
+    }
+
+    fn check_unary_expression(&mut self, expression: &UnaryExpression) -> Type {
+        let operand = self.check_expression(&expression.right);
+        let expected = if expression.operator.kind == TokenKind::Not {
+            Type::Boolean
+        } else {
+            Type::Number
+        };
+        if !expected.accepts(&operand) {
+            self.report(
+                format!(
+                    "Type mismatch. Unary operator '{}' is not defined for '{operand}'.",
+                    expression.operator.lexeme
+                ),
+                expression.position(),
+            );
+        }
+        expected
+    }
+
+    fn check_call_expression(&mut self, expression: &CallExpression) -> Type {
+        let callee = self.check_expression(&expression.callee);
+        let arguments: Vec<Type> = expression
+            .arguments
+            .iter()
+            .map(|argument| self.check_expression(argument))
+            .collect();
+        let keyword_arguments: Vec<(&Token, Type)> = expression
+            .keyword_arguments
+            .iter()
+            .map(|(keyword, argument)| (keyword, self.check_expression(argument)))
+            .collect();
+        self.check_pattern(expression);
+
+        let function = match callee {
+            Type::Function(Some(function)) => function,
+            Type::Function(None) | Type::Any => return Type::Any,
+            Type::Nil if expression.optional => return Type::Nil,
+            callee => {
+                self.report(
+                    format!("Invalid call. A value of type '{callee}' is not callable."),
+                    expression.callee.position(),
+                );
+                return Type::Any;
+            }
+        };
+
+        let positional = expression.arguments.iter().zip(arguments);
+        for (i, (argument, argument_type)) in positional.enumerate() {
+            if let Some((name, parameter_type)) = function.parameters.get(i) {
+                self.check_argument(&function, name, parameter_type, &argument_type, argument);
+            }
+        }
+        for ((keyword, argument_type), (_, argument)) in keyword_arguments
+            .into_iter()
+            .zip(&expression.keyword_arguments)
+        {
+            if let Some((name, parameter_type)) = function
+                .parameters
+                .iter()
+                .find(|(name, _)| name == &keyword.lexeme)
+            {
+                self.check_argument(&function, name, parameter_type, &argument_type, argument);
+            }
+        }
+        function.returns.clone()
+    }
+
+    /// Compiles a pattern given to the `re` module as a literal, so a mistake in it is
+    /// reported without running the script.
+    fn check_pattern(&mut self, expression: &CallExpression) {
+        let Expression::Get(callee) = expression.callee.as_ref() else {
+            return;
+        };
+        let is_re = matches!(
+            callee.object.as_ref(),
+            Expression::Variable(module) if module.identifier.lexeme == "re"
+        );
+        if !is_re || !matches!(self.lookup("re"), Type::Module) {
+            return;
+        }
+        if let Some(Expression::Literal(pattern)) = expression.arguments.first() {
+            if pattern.value.kind == TokenKind::String {
+                if let Err(message) = Regex::new(&pattern.value.lexeme) {
+                    self.report(message, pattern.value.position.clone());
+                }
+            }
+        }
+    }
+
+    fn check_argument(
+        &mut self,
+        function: &FunctionType,
+        name: &str,
+        parameter_type: &Type,
+        argument_type: &Type,
+        argument: &Expression,
+    ) {
+        if !parameter_type.accepts(argument_type) {
+            self.report(
+                format!(
+                    "Type mismatch. Parameter '{name}' of '{}' expects '{parameter_type}', found '{argument_type}'.",
+                    function.name
+                ),
+                argument.position(),
+            );
+        }
+    }
+
+    /// Functions are declared before the statements around them are checked, so calls
+    /// that come before a definition still see its signature.
+    fn declare_functions(&mut self, statements: &[Statement]) {
+        for statement in statements {
+            if let Statement::Function(statement) = statement {
+                let function_type = self.function_type(statement);
+                self.declare(&statement.identifier, function_type);
+            }
+        }
+    }
+
+    fn function_type(&self, statement: &FunctionStatement) -> Type {
+        if self.assigned_names.contains(&statement.identifier.lexeme) {
+            return Type::Function(None);
+        }
+        // Calling a generator function creates a generator, which has no type of its own.
+        let returns = match &statement.return_annotation {
+            Some(annotation) if !statement.generator => Self::annotation_type(annotation),
+            _ => Type::Any,
+        };
+        Type::Function(Some(Rc::new(FunctionType {
+            name: statement.identifier.lexeme.clone(),
+            parameters: Self::parameter_types(&statement.parameters),
+            returns,
+        })))
+    }
+
+    fn parameter_types(parameters: &[Parameter]) -> Vec<(String, Type)> {
+        parameters
+            .iter()
+            .filter(|parameter| !parameter.variadic)
+            .map(|parameter| {
+                (
+                    parameter.identifier.lexeme.clone(),
+                    Self::parameter_type(parameter),
+                )
+            })
+            .collect()
+    }
+
+    fn parameter_type(parameter: &Parameter) -> Type {
+        if parameter.variadic {
+            Type::List
+        } else if let Some(annotation) = &parameter.annotation {
+            Self::annotation_type(annotation)
+        } else {
+            Type::Any
+        }
+    }
+
+    /// Unknown type names are reported once, where they are written, and treated as `any`
+    /// everywhere else.
+    fn annotation_type(annotation: &Token) -> Type {
+        Type::from_annotation(annotation).unwrap_or(Type::Any)
+    }
+
+    fn check_annotation(&mut self, annotation: &Token) -> Type {
+        Type::from_annotation(annotation).unwrap_or_else(|| {
+            self.report(
+                format!("Unknown type. '{}' is not a type.", annotation.lexeme),
+                annotation.position.clone(),
+            );
+            Type::Any
+        })
+    }
+
+    fn literal_type(expression: &LiteralExpression) -> Type {
+        match expression.value.kind {
+            TokenKind::Nil => Type::Nil,
+            TokenKind::Number => Type::Number,
+            TokenKind::True | TokenKind::False => Type::Boolean,
+            _ => Type::String,
+        }
+    }
+
+    fn numeric(left: &Type, right: &Type) -> bool {
+        Type::Number.accepts(left) && Type::Number.accepts(right)
+    }
+
+    fn declare(&mut self, identifier: &Token, declared_type: Type) {
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(identifier.lexeme.clone(), declared_type);
+    }
+
+    fn lookup(&self, name: &str) -> Type {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .unwrap_or(Type::Any)
+    }
+
+    fn report(&mut self, message: String, position: Position) {
+        self.errors
+            .push(Error::new(ErrorKind::Type, message, Some(position)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::check_source;
+
+    fn messages(source: &str) -> Vec<String> {
+        let errors = check_source(source).unwrap();
+        errors.into_iter().map(|error| error.message).collect()
+    }
+
+    #[test]
+    fn reports_mismatches() {
+        let source = concat!(
+            "var a: number = \"x\"\n",
+            "fun f(x: number) -> string { return x }\n",
+            "f(\"y\")\n",
+            "var b = 1 - \"z\"\n",
+            "var n: number = 1\n",
+            "n = \"s\"\n",
+        );
+        assert_eq!(
+            messages(source),
+            [
+                "Type mismatch. 'a' is declared as 'number' but initialized with 'string'.",
+                "Type mismatch. Expected a return value of type 'string', found 'number'.",
+                "Type mismatch. Parameter 'x' of 'f' expects 'number', found 'string'.",
+                "Type mismatch. Binary operator '-' is not defined for 'number' and 'string'.",
+                "Type mismatch. Can not assign 'string' to 'n' of type 'number'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_what_it_can_not_tell_apart() {
+        let examples = [
+            include_str!("../../examples/arguments.in"),
+            include_str!("../../examples/class.in"),
+            include_str!("../../examples/enum.in"),
+            include_str!("../../examples/generator.in"),
+            include_str!("../../examples/lambda.in"),
+            include_str!("../../examples/types.in"),
+        ];
+        for example in examples {
+            assert_eq!(messages(example), Vec::<String>::new());
+        }
+        // Values of unknown type, like parameters without annotations, can be anything.
+        let source = "fun f(x) { return x + 1 }\nvar s: string = f(\"a\")\nvar t = f(1) - 2\n";
+        assert_eq!(messages(source), Vec::<String>::new());
+    }
+}
@@ -1,119 +1,667 @@
-use std::{cell::RefCell, collections::HashMap};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
     backend::{chunk::Chunk, instruction::Instruction},
     common::{
         ast::{
-            AssignmentExpression, BinaryExpression, BlockStatement, ElseStatement, Expression,
-            ExpressionStatement, IfStatement, LiteralExpression, PrintStatement, Program,
-            ReturnStatement, Statement, UnaryExpression, VariableExpression, VariableStatement,
+            AssertStatement, AssignmentExpression, BinaryExpression, BlockStatement,
+            CallExpression, ClassStatement, ElseStatement, EnumStatement, Expression,
+            ExpressionStatement, ForStatement, FunctionStatement, GetExpression, IfStatement,
+            LambdaExpression, ListExpression, LiteralExpression, Parameter, PrintStatement,
+            Program, ReturnStatement, SetExpression, SpawnExpression, Statement, UnaryExpression,
+            VariableExpression, VariableStatement, VariantPattern, WhileStatement, YieldStatement,
         },
+        bigint::BigInt,
         error::{Error, ErrorKind},
-        object::Object,
+        interner::{Interner, Str},
+        object::{Capture, Enum, Function, Object, Signature},
         position::Position,
         token::{Token, TokenKind},
     },
+    stdlib,
 };
 
-pub(crate) type SymbolTable = RefCell<HashMap<String, Expression>>;
+use super::captures::captured_names;
 
-pub(crate) struct Compiler {
+pub(crate) type SymbolTable = RefCell<HashMap<String, Symbol>>;
+
+#[derive(Clone)]
+pub(crate) enum Binding {
+    Global,
+    Local(usize),
+    Cell(usize),
+    Upvalue(usize),
+    /// A constant known while compiling, a literal or an enum, pushed directly wherever it
+    /// is used.
+    Constant(Object),
+}
+
+/// Methods get the instance they are called on in slot zero, as `this`, and initializers
+/// return it.
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone)]
+pub(crate) struct Symbol {
+    binding: Binding,
+    /// Where the symbol was declared, if it is a constant.
+    constant: Option<Position>,
+}
+
+impl Symbol {
+    fn new(binding: Binding, constant: Option<Position>) -> Self {
+        Self { binding, constant }
+    }
+}
+
+struct FunctionContext {
     chunk: Chunk,
     bindings: Vec<SymbolTable>,
+    local_count: usize,
+    captured_names: HashSet<String>,
+    captures: Vec<(String, Capture)>,
 }
 
-impl Compiler {
-    pub(crate) fn new(bindings: Option<Vec<SymbolTable>>) -> Self {
+impl FunctionContext {
+    fn new(
+        bindings: Vec<SymbolTable>,
+        local_count: usize,
+        captured_names: HashSet<String>,
+    ) -> Self {
         Self {
             chunk: Chunk::new(),
-            bindings: bindings.unwrap_or(vec![SymbolTable::default()]),
+            bindings,
+            local_count,
+            captured_names,
+            captures: Vec::new(),
+        }
+    }
+}
+
+pub(crate) struct Compiler {
+    globals: SymbolTable,
+    contexts: Vec<FunctionContext>,
+    /// Jumps taken by the `?.` links of the chain being compiled, when their operand is nil.
+    optional_jumps: Vec<usize>,
+    interner: Interner,
+}
+
+impl Compiler {
+    pub(crate) fn new(globals: Option<SymbolTable>, interner: Interner) -> Self {
+        Self {
+            globals: globals.unwrap_or_else(|| {
+                SymbolTable::new(
+                    stdlib::globals()
+                        .into_iter()
+                        .map(|(name, _)| (name, Symbol::new(Binding::Global, None)))
+                        .collect(),
+                )
+            }),
+            // Slot zero of the script frame holds the script itself.
+            contexts: vec![FunctionContext::new(Vec::new(), 1, HashSet::new())],
+            optional_jumps: Vec::new(),
+            interner,
         }
     }
 
     pub(crate) fn compile(&mut self, program: Program) -> Result<Chunk, Error> {
+        self.declare_globals(&program)?;
+        self.context().captured_names = captured_names(&program);
         for statement in &program {
             self.compile_statement(statement)?;
         }
-        self.chunk
-            .add_instruction(Instruction::Return, Position::new(0, 0, 0));
-        Ok(self.chunk.clone())
+        self.chunk()
+            .add_instruction(Instruction::Push(Object::Nil), Position::new(0, 0, 0, 0));
+        self.chunk()
+            .add_instruction(Instruction::Return, Position::new(0, 0, 0, 0));
+        Ok(self.chunk().clone())
     }
 
     fn compile_statement(&mut self, statement: &Statement) -> Result<(), Error> {
         match statement {
-            Statement::Function(_) => todo!(),
+            Statement::Function(statement) => self.compile_function_statement(statement),
+            Statement::Class(statement) => self.compile_class_statement(statement),
+            Statement::Enum(statement) => self.compile_enum_statement(statement),
             Statement::If(statement) => self.compile_if_statement(statement),
-            Statement::While(_) => todo!(),
+            Statement::For(statement) => self.compile_for_statement(statement),
+            Statement::While(statement) => self.compile_while_statement(statement),
             Statement::Block(statement) => self.compile_block_statement(statement),
             Statement::Variable(statement) => self.compile_variable_statement(statement),
             Statement::Return(statement) => self.compile_return_statement(statement),
+            Statement::Yield(statement) => self.compile_yield_statement(statement),
             Statement::Print(statement) => self.compile_print_statement(statement),
+            Statement::Assert(statement) => self.compile_assert_statement(statement),
             Statement::Expression(statement) => self.compile_expression_statement(statement),
         }
     }
 
+    fn compile_function_statement(&mut self, statement: &FunctionStatement) -> Result<(), Error> {
+        self.compile_declaration(&statement.identifier, |compiler| {
+            compiler.compile_function(
+                &statement.identifier.lexeme,
+                &statement.parameters,
+                &statement.block,
+                statement.identifier.position.clone(),
+                statement.generator,
+                FunctionKind::Function,
+            )
+        })
+    }
+
+    fn compile_class_statement(&mut self, statement: &ClassStatement) -> Result<(), Error> {
+        self.compile_declaration(&statement.identifier, |compiler| {
+            let class_name = &statement.identifier.lexeme;
+            let mut names: Vec<Str> = Vec::new();
+            for method in &statement.methods {
+                let name = compiler.interner.intern(&method.identifier.lexeme);
+                if names.contains(&name) {
+                    return Err(Error::new(
+                        ErrorKind::Compiler,
+                        format!("Duplicate method. '{class_name}' already has a method '{name}'."),
+                        Some(method.identifier.position.clone()),
+                    ));
+                }
+                let kind = if &*name == "init" {
+                    FunctionKind::Initializer
+                } else {
+                    FunctionKind::Method
+                };
+                compiler.compile_function(
+                    &format!("{class_name}.{name}"),
+                    &method.parameters,
+                    &method.block,
+                    method.identifier.position.clone(),
+                    method.generator,
+                    kind,
+                )?;
+                names.push(name);
+            }
+            compiler.chunk().add_instruction(
+                Instruction::BuildClass(class_name.clone(), names),
+                statement.identifier.position.clone(),
+            );
+            Ok(())
+        })
+    }
+
+    /// Binds the value `compile_value` pushes to `identifier`. Local declarations are bound
+    /// before their value is compiled, so functions and methods can refer to themselves.
+    fn compile_declaration(
+        &mut self,
+        identifier: &Token,
+        compile_value: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        if self.context().bindings.is_empty() {
+            compile_value(self)?;
+            return self.define_binding(identifier, false);
+        }
+
+        self.chunk()
+            .add_instruction(Instruction::Push(Object::Nil), identifier.position.clone());
+        self.define_binding(identifier, false)?;
+        compile_value(self)?;
+        let binding = self.find_binding(identifier)?;
+        let instruction = self.set_instruction(binding, identifier);
+        self.chunk()
+            .add_instruction(instruction, identifier.position.clone());
+        self.chunk()
+            .add_instruction(Instruction::Pop, identifier.position.clone());
+        Ok(())
+    }
+
+    fn compile_function(
+        &mut self,
+        name: &str,
+        parameters: &[Parameter],
+        block: &BlockStatement,
+        position: Position,
+        generator: bool,
+        kind: FunctionKind,
+    ) -> Result<(), Error> {
+        let signature = self.compile_signature(name, parameters)?;
+
+        let captured_names = captured_names(&block.statements);
+        let bindings = SymbolTable::default();
+        let mut boxed_slots = Vec::new();
+        let this = Token::new(TokenKind::This, "this".to_string(), position.clone());
+        if kind != FunctionKind::Function {
+            let binding = if captured_names.contains(&this.lexeme) {
+                boxed_slots.push(0);
+                Binding::Cell(0)
+            } else {
+                Binding::Local(0)
+            };
+            bindings
+                .borrow_mut()
+                .insert(this.lexeme.clone(), Symbol::new(binding, None));
+        }
+        for (i, parameter) in parameters.iter().map(|p| &p.identifier).enumerate() {
+            let binding = if captured_names.contains(&parameter.lexeme) {
+                boxed_slots.push(i + 1);
+                Binding::Cell(i + 1)
+            } else {
+                Binding::Local(i + 1)
+            };
+            if bindings
+                .borrow_mut()
+                .insert(parameter.lexeme.clone(), Symbol::new(binding, None))
+                .is_some()
+            {
+                return Err(Error::new(
+                    ErrorKind::Compiler,
+                    format!(
+                        "Duplicate parameter. '{}' is already a parameter of '{name}'.",
+                        parameter.lexeme
+                    ),
+                    Some(parameter.position.clone()),
+                ));
+            }
+        }
+
+        self.contexts.push(FunctionContext::new(
+            vec![bindings],
+            parameters.len() + 1,
+            captured_names,
+        ));
+        for slot in boxed_slots {
+            self.chunk()
+                .add_instruction(Instruction::BoxLocal(slot), position.clone());
+        }
+        for statement in &block.statements {
+            self.compile_statement(statement)?;
+        }
+        if kind == FunctionKind::Initializer {
+            self.compile_variable_expression(&VariableExpression::new(this))?;
+        } else {
+            self.chunk()
+                .add_instruction(Instruction::Push(Object::Nil), position.clone());
+        }
+        self.chunk()
+            .add_instruction(Instruction::Return, position.clone());
+        let context = self.contexts.pop().unwrap();
+
+        let function = Function::new(
+            name.to_string(),
+            signature,
+            context.chunk,
+            context
+                .captures
+                .into_iter()
+                .map(|(_, capture)| capture)
+                .collect(),
+            generator,
+        );
+        self.chunk()
+            .add_instruction(Instruction::Closure(Rc::new(function)), position);
+        Ok(())
+    }
+
+    /// Checks the order of parameters and compiles their defaults, which are evaluated once
+    /// when the function is created.
+    fn compile_signature(
+        &mut self,
+        name: &str,
+        parameters: &[Parameter],
+    ) -> Result<Signature, Error> {
+        let mut names = Vec::new();
+        let mut required = 0;
+        let mut defaults = Vec::new();
+        let mut rest = None;
+        for (i, parameter) in parameters.iter().enumerate() {
+            let identifier = &parameter.identifier;
+            if parameter.variadic {
+                if i + 1 != parameters.len() || parameter.default.is_some() {
+                    return Err(Error::new(
+                        ErrorKind::Compiler,
+                        format!(
+                            "Invalid parameter. Rest parameter '{}' of '{name}' must be the last parameter and can not have a default.",
+                            identifier.lexeme
+                        ),
+                        Some(identifier.position.clone()),
+                    ));
+                }
+                rest = Some(identifier.lexeme.clone());
+                continue;
+            }
+            if let Some(default) = &parameter.default {
+                self.compile_expression(default)?;
+                defaults.push(parameter.default_source.clone().unwrap_or_default());
+            } else if required < names.len() {
+                return Err(Error::new(
+                    ErrorKind::Compiler,
+                    format!(
+                        "Invalid parameter. Required parameter '{}' can not follow optional parameter '{}'.",
+                        identifier.lexeme,
+                        names[required]
+                    ),
+                    Some(identifier.position.clone()),
+                ));
+            } else {
+                required += 1;
+            }
+            names.push(identifier.lexeme.clone());
+        }
+        Ok(Signature::new(names, required, defaults, rest))
+    }
+
+    /// Top level enums are declared with the other globals, so only nested ones are
+    /// defined here.
+    fn compile_enum_statement(&mut self, statement: &EnumStatement) -> Result<(), Error> {
+        if self.context().bindings.is_empty() {
+            return Ok(());
+        }
+        let enum_type = Self::enum_type(statement)?;
+        self.define_constant(&statement.identifier, Object::Enum(enum_type))
+    }
+
+    fn enum_type(statement: &EnumStatement) -> Result<Rc<Enum>, Error> {
+        let mut variants: Vec<(String, usize)> = Vec::new();
+        for variant in &statement.variants {
+            let name = &variant.identifier.lexeme;
+            if variants.iter().any(|(other, _)| other == name) {
+                return Err(Error::new(
+                    ErrorKind::Compiler,
+                    format!(
+                        "Duplicate variant. '{}' already has a variant '{name}'.",
+                        statement.identifier.lexeme
+                    ),
+                    Some(variant.identifier.position.clone()),
+                ));
+            }
+            variants.push((name.clone(), variant.fields.len()));
+        }
+        Ok(Rc::new(Enum::new(
+            statement.identifier.lexeme.clone(),
+            variants,
+        )))
+    }
+
     fn compile_if_statement(&mut self, statement: &IfStatement) -> Result<(), Error> {
+        let position = statement.condition.position();
         self.compile_expression(&statement.condition)?;
-        let patch_index = self.chunk.add_instruction(
-            Instruction::JumpIfFalse(usize::MAX),
-            statement.condition.position(),
-        );
-        self.compile_block_statement(&statement.then_branch)?;
-        if let Some(else_statement) = &*statement.else_branch {
-            match else_statement {
-                ElseStatement::If(if_statement) => {
-                    self.patch_if_statement(patch_index, statement);
-                    self.compile_if_statement(if_statement)?;
+        let patch_index = match &*statement.pattern {
+            Some(pattern) => {
+                let (enum_type, index) = self.pattern_variant(pattern)?;
+                self.chunk()
+                    .add_instruction(Instruction::TestVariant(enum_type, index), position.clone());
+                let patch_index = self
+                    .chunk()
+                    .add_instruction(Instruction::JumpIfFalse(usize::MAX), position.clone());
+                self.begin_scope();
+                self.chunk()
+                    .add_instruction(Instruction::UnpackVariant, position.clone());
+                for binding in &pattern.bindings {
+                    self.define_binding(binding, false)?;
                 }
-                ElseStatement::Block(block) => {
-                    self.patch_if_statement(patch_index, statement);
-                    self.compile_block_statement(block)?;
+                for inner_statement in &statement.then_branch.statements {
+                    self.compile_statement(inner_statement)?;
                 }
+                self.end_scope();
+                patch_index
             }
-        } else {
-            self.patch_if_statement(patch_index, statement);
+            None => {
+                let patch_index = self
+                    .chunk()
+                    .add_instruction(Instruction::JumpIfFalse(usize::MAX), position.clone());
+                self.compile_block_statement(&statement.then_branch)?;
+                patch_index
+            }
+        };
+        if statement.pattern.is_none() && statement.else_branch.is_none() {
+            self.patch_jump(patch_index, position);
+            return Ok(());
+        }
+
+        let end_index = self
+            .chunk()
+            .add_instruction(Instruction::Jump(usize::MAX), position.clone());
+        self.patch_jump(patch_index, position.clone());
+        // A value that did not match the pattern is still on the stack.
+        if statement.pattern.is_some() {
+            self.chunk()
+                .add_instruction(Instruction::Pop, position.clone());
+        }
+        match &*statement.else_branch {
+            Some(ElseStatement::If(if_statement)) => self.compile_if_statement(if_statement)?,
+            Some(ElseStatement::Block(block)) => self.compile_block_statement(block)?,
+            None => {}
+        }
+        self.patch_jump(end_index, position);
+        Ok(())
+    }
+
+    fn pattern_variant(&self, pattern: &VariantPattern) -> Result<(Rc<Enum>, usize), Error> {
+        let Some((enum_type, index)) =
+            self.find_variant(&pattern.enum_identifier, &pattern.variant)?
+        else {
+            return Err(Error::new(
+                ErrorKind::Compiler,
+                format!(
+                    "Invalid pattern. '{}' is not an enum.",
+                    pattern.enum_identifier.lexeme
+                ),
+                Some(pattern.enum_identifier.position.clone()),
+            ));
+        };
+        let field_count = enum_type.variants[index].1;
+        if field_count != pattern.bindings.len() {
+            return Err(Error::new(
+                ErrorKind::Compiler,
+                format!(
+                    "Invalid pattern. '{}' carries {field_count} value(s), found {} name(s).",
+                    enum_type.variant_name(index),
+                    pattern.bindings.len()
+                ),
+                Some(pattern.variant.position.clone()),
+            ));
+        }
+        Ok((enum_type, index))
+    }
+
+    /// Looks up `Enum.Variant` when `enum_identifier` names an enum, which is always a
+    /// constant, so unknown variants are caught before the program runs.
+    fn find_variant(
+        &self,
+        enum_identifier: &Token,
+        variant: &Token,
+    ) -> Result<Option<(Rc<Enum>, usize)>, Error> {
+        let Some(Symbol {
+            binding: Binding::Constant(Object::Enum(enum_type)),
+            ..
+        }) = self.resolve(&enum_identifier.lexeme)
+        else {
+            return Ok(None);
+        };
+        match enum_type.find_variant(&variant.lexeme) {
+            Some(index) => Ok(Some((enum_type, index))),
+            None => Err(Error::new(
+                ErrorKind::Compiler,
+                format!(
+                    "Unknown variant. '{}' has no variant '{}'.",
+                    enum_identifier.lexeme, variant.lexeme
+                ),
+                Some(variant.position.clone()),
+            )),
+        }
+    }
+
+    fn get_variant(&self, expression: &GetExpression) -> Result<Option<(Rc<Enum>, usize)>, Error> {
+        match &*expression.object {
+            Expression::Variable(object) => {
+                self.find_variant(&object.identifier, &expression.identifier)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn compile_for_statement(&mut self, statement: &ForStatement) -> Result<(), Error> {
+        self.compile_expression(&statement.iterable)?;
+        self.chunk()
+            .add_instruction(Instruction::GetIterator, statement.iterable.position());
+        self.begin_scope();
+        // The iterator lives in a hidden slot, named after a keyword so scripts can't refer to it.
+        self.define_binding(
+            &Token::new(
+                TokenKind::For,
+                "for".to_string(),
+                statement.identifier.position.clone(),
+            ),
+            false,
+        )?;
+
+        let loop_start = self.chunk().len();
+        let patch_index = self.chunk().add_instruction(
+            Instruction::IterateNext(usize::MAX),
+            statement.identifier.position.clone(),
+        );
+        self.begin_scope();
+        self.define_binding(&statement.identifier, false)?;
+        for inner_statement in &statement.do_block.statements {
+            self.compile_statement(inner_statement)?;
         }
+        self.end_scope();
+        self.chunk().add_instruction(
+            Instruction::Jump(loop_start),
+            statement.identifier.position.clone(),
+        );
+        self.patch_jump(patch_index, statement.identifier.position.clone());
+        self.end_scope();
+        Ok(())
+    }
+
+    fn compile_while_statement(&mut self, statement: &WhileStatement) -> Result<(), Error> {
+        let loop_start = self.chunk().len();
+        self.compile_expression(&statement.condition)?;
+        let patch_index = self.chunk().add_instruction(
+            Instruction::JumpIfFalse(usize::MAX),
+            statement.condition.position(),
+        );
+        self.compile_block_statement(&statement.do_block)?;
+        self.chunk().add_instruction(
+            Instruction::Jump(loop_start),
+            statement.condition.position(),
+        );
+        self.patch_jump(patch_index, statement.condition.position());
         Ok(())
     }
 
     fn compile_block_statement(&mut self, statement: &BlockStatement) -> Result<(), Error> {
-        self.bindings.push(RefCell::new(HashMap::new()));
+        self.begin_scope();
         for statement in &statement.statements {
             self.compile_statement(statement)?;
         }
-        self.bindings.pop().unwrap();
+        self.end_scope();
         Ok(())
     }
 
     fn compile_variable_statement(&mut self, statement: &VariableStatement) -> Result<(), Error> {
+        if statement.constant {
+            if let Expression::Literal(literal) = &statement.initializer {
+                return self.define_constant(&statement.identifier, self.literal_value(literal));
+            }
+        }
         self.compile_expression(&statement.initializer)?;
-        self.bindings.last().unwrap().borrow_mut().insert(
-            statement.identifier.lexeme.clone(),
-            statement.initializer.clone(),
-        );
-        Ok(())
+        self.define_binding(&statement.identifier, statement.constant)
     }
 
     fn compile_return_statement(&mut self, statement: &ReturnStatement) -> Result<(), Error> {
         self.compile_expression(&statement.expression)?;
-        self.chunk
+        self.chunk()
             .add_instruction(Instruction::Return, statement.expression.position());
         Ok(())
     }
 
+    fn compile_yield_statement(&mut self, statement: &YieldStatement) -> Result<(), Error> {
+        self.compile_expression(&statement.expression)?;
+        self.chunk()
+            .add_instruction(Instruction::Yield, statement.expression.position());
+        Ok(())
+    }
+
     fn compile_print_statement(&mut self, statement: &PrintStatement) -> Result<(), Error> {
         self.compile_expression(&statement.expression)?;
-        self.chunk
+        self.chunk()
             .add_instruction(Instruction::Print, statement.expression.position());
         Ok(())
     }
 
+    /// A failing comparison keeps both of its operands on the stack, so the failure message
+    /// can show them. The message itself is only evaluated when the assertion fails.
+    fn compile_assert_statement(&mut self, statement: &AssertStatement) -> Result<(), Error> {
+        let position = statement.condition.position();
+        let comparison = match &statement.condition {
+            Expression::Binary(expression) => Self::comparison(&expression.operator.kind)
+                .map(|comparison| (expression, comparison)),
+            _ => None,
+        };
+
+        if let Some((expression, (instruction, _))) = &comparison {
+            self.compile_expression(&expression.left)?;
+            self.compile_expression(&expression.right)?;
+            self.chunk()
+                .add_instruction(Instruction::Duplicate(2), position.clone());
+            self.chunk()
+                .add_instruction(instruction.clone(), expression.position());
+        } else {
+            self.compile_expression(&statement.condition)?;
+        }
+        let fail_jump = self
+            .chunk()
+            .add_instruction(Instruction::JumpIfFalse(usize::MAX), position.clone());
+        if comparison.is_some() {
+            self.chunk()
+                .add_instruction(Instruction::Pop, position.clone());
+            self.chunk()
+                .add_instruction(Instruction::Pop, position.clone());
+        }
+        let end_jump = self
+            .chunk()
+            .add_instruction(Instruction::Jump(usize::MAX), position.clone());
+
+        self.patch_jump(fail_jump, position.clone());
+        match &statement.message {
+            Some(message) => self.compile_expression(message)?,
+            None => {
+                self.chunk()
+                    .add_instruction(Instruction::Push(Object::Nil), position.clone());
+            }
+        }
+        let negated = comparison.map(|(_, (_, negated))| negated.to_string());
+        self.chunk().add_instruction(
+            Instruction::AssertFailed(statement.source.clone(), negated),
+            position.clone(),
+        );
+        self.patch_jump(end_jump, position);
+        Ok(())
+    }
+
+    /// The instruction for a comparison operator, and the operator that holds when it fails.
+    fn comparison(kind: &TokenKind) -> Option<(Instruction, &'static str)> {
+        match kind {
+            TokenKind::Equal => Some((Instruction::Equal, "!=")),
+            TokenKind::NotEqual => Some((Instruction::NotEqual, "==")),
+            TokenKind::Greater => Some((Instruction::Greater, "<=")),
+            TokenKind::GreaterEqual => Some((Instruction::GreaterEqual, "<")),
+            TokenKind::Lesser => Some((Instruction::Lesser, ">=")),
+            TokenKind::LesserEqual => Some((Instruction::LesserEqual, ">")),
+            _ => None,
+        }
+    }
+
     fn compile_expression_statement(
         &mut self,
         statement: &ExpressionStatement,
     ) -> Result<(), Error> {
         self.compile_expression(&statement.expression)?;
-        self.chunk
+        self.chunk()
             .add_instruction(Instruction::Pop, statement.expression.position());
         Ok(())
     }
@@ -124,7 +672,11 @@ impl Compiler {
             Expression::Binary(expression) => self.compile_binary_expression(expression),
             Expression::Unary(expression) => self.compile_unary_expression(expression),
             Expression::Group(expression) => self.compile_expression(&expression.child),
-            Expression::Call(_) => todo!(),
+            Expression::Call(_) | Expression::Get(_) => self.compile_chain(expression),
+            Expression::Set(expression) => self.compile_set_expression(expression),
+            Expression::Spawn(expression) => self.compile_spawn_expression(expression),
+            Expression::Lambda(expression) => self.compile_lambda_expression(expression),
+            Expression::List(expression) => self.compile_list_expression(expression),
             Expression::Literal(expression) => self.compile_literal_expression(expression),
             Expression::Variable(expression) => self.compile_variable_expression(expression),
         }
@@ -134,78 +686,112 @@ impl Compiler {
         &mut self,
         expression: &AssignmentExpression,
     ) -> Result<(), Error> {
-        let (i, _) = self.find_binding(expression.identifier.clone())?;
+        if let Some(declaration) = self
+            .resolve(&expression.identifier.lexeme)
+            .and_then(|symbol| symbol.constant)
+        {
+            return Err(Error::new(
+                ErrorKind::Compiler,
+                format!(
+                    "Invalid assignment. '{}' is a constant, declared at line {}, column {}.",
+                    expression.identifier.lexeme,
+                    declaration.line + 1,
+                    declaration.column + 1
+                ),
+                Some(expression.identifier.position.clone()),
+            ));
+        }
+        let binding = self.find_binding(&expression.identifier)?;
         self.compile_expression(&expression.initializer)?;
-        self.bindings[i].borrow_mut().insert(
-            expression.identifier.lexeme.clone(),
-            *expression.initializer.clone(),
-        );
+        let instruction = self.set_instruction(binding, &expression.identifier);
+        self.chunk()
+            .add_instruction(instruction, expression.position());
         Ok(())
     }
 
     fn compile_binary_expression(&mut self, expression: &BinaryExpression) -> Result<(), Error> {
+        if expression.operator.kind == TokenKind::QuestionQuestion {
+            return self.compile_nil_coalescing_expression(expression);
+        }
+
         self.compile_expression(&expression.left)?;
         self.compile_expression(&expression.right)?;
         match expression.operator.kind {
             TokenKind::Plus => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Add, expression.position());
                 Ok(())
             }
             TokenKind::Minus => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Subtract, expression.position());
                 Ok(())
             }
             TokenKind::Star => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Multiply, expression.position());
                 Ok(())
             }
             TokenKind::Slash => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Divide, expression.position());
                 Ok(())
             }
+            TokenKind::Percent => {
+                self.chunk()
+                    .add_instruction(Instruction::Modulo, expression.position());
+                Ok(())
+            }
 
             TokenKind::Equal => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Equal, expression.position());
                 Ok(())
             }
             TokenKind::NotEqual => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::NotEqual, expression.position());
                 Ok(())
             }
             TokenKind::Greater => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Greater, expression.position());
                 Ok(())
             }
             TokenKind::GreaterEqual => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::GreaterEqual, expression.position());
                 Ok(())
             }
             TokenKind::Lesser => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Lesser, expression.position());
                 Ok(())
             }
             TokenKind::LesserEqual => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::LesserEqual, expression.position());
                 Ok(())
             }
 
+            TokenKind::DotDot => {
+                self.chunk()
+                    .add_instruction(Instruction::BuildRange(false), expression.position());
+                Ok(())
+            }
+            TokenKind::DotDotEqual => {
+                self.chunk()
+                    .add_instruction(Instruction::BuildRange(true), expression.position());
+                Ok(())
+            }
+
             TokenKind::And => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::And, expression.position());
                 Ok(())
             }
             TokenKind::Or => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Or, expression.position());
                 Ok(())
             }
@@ -221,23 +807,43 @@ impl Compiler {
         }
     }
 
+    /// `a ?? b` keeps `a` unless it is nil, and only then evaluates `b`.
+    fn compile_nil_coalescing_expression(
+        &mut self,
+        expression: &BinaryExpression,
+    ) -> Result<(), Error> {
+        self.compile_expression(&expression.left)?;
+        let nil_jump = self
+            .chunk()
+            .add_instruction(Instruction::JumpIfNil(usize::MAX), expression.position());
+        let end_jump = self
+            .chunk()
+            .add_instruction(Instruction::Jump(usize::MAX), expression.position());
+        self.patch_jump(nil_jump, expression.position());
+        self.chunk()
+            .add_instruction(Instruction::Pop, expression.position());
+        self.compile_expression(&expression.right)?;
+        self.patch_jump(end_jump, expression.position());
+        Ok(())
+    }
+
     fn compile_unary_expression(&mut self, expression: &UnaryExpression) -> Result<(), Error> {
         self.compile_expression(&expression.right)?;
         match expression.operator.kind {
             TokenKind::Plus => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Identify, expression.position());
                 Ok(())
             }
 
             TokenKind::Minus => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Negate, expression.position());
                 Ok(())
             }
 
             TokenKind::Not => {
-                self.chunk
+                self.chunk()
                     .add_instruction(Instruction::Not, expression.position());
                 Ok(())
             }
@@ -253,67 +859,512 @@ impl Compiler {
         }
     }
 
+    /// Compiles a chain of calls and property accesses. A `?.` link that finds nil skips the
+    /// rest of the chain, leaving nil as its value.
+    fn compile_chain(&mut self, expression: &Expression) -> Result<(), Error> {
+        let enclosing_jumps = std::mem::take(&mut self.optional_jumps);
+        let result = self.compile_link(expression);
+        let jumps = std::mem::replace(&mut self.optional_jumps, enclosing_jumps);
+        result?;
+        for jump in jumps {
+            self.patch_jump(jump, expression.position());
+        }
+        Ok(())
+    }
+
+    fn compile_link(&mut self, expression: &Expression) -> Result<(), Error> {
+        match expression {
+            Expression::Call(expression) => {
+                if let Expression::Get(callee) = &*expression.callee {
+                    if let Some((enum_type, index)) = self.get_variant(callee)? {
+                        Self::check_constructor_call(&enum_type, index, expression)?;
+                    }
+                }
+                self.compile_link(&expression.callee)?;
+                self.compile_optional_jump(expression.optional, expression.position());
+                let keywords = self.compile_arguments(expression)?;
+                let argument_count = expression.arguments.len() + keywords.len();
+                let instruction = if keywords.is_empty() {
+                    Instruction::Call(argument_count)
+                } else {
+                    Instruction::CallWithKeywords(argument_count, keywords)
+                };
+                self.chunk()
+                    .add_instruction(instruction, expression.position());
+                Ok(())
+            }
+            Expression::Get(expression) => {
+                if let Some((enum_type, index)) = self.get_variant(expression)? {
+                    self.chunk().add_instruction(
+                        Instruction::Push(enum_type.member(index)),
+                        expression.position(),
+                    );
+                    return Ok(());
+                }
+                self.compile_link(&expression.object)?;
+                self.compile_optional_jump(expression.optional, expression.position());
+                let name = self.interner.intern(&expression.identifier.lexeme);
+                self.chunk()
+                    .add_instruction(Instruction::GetProperty(name), expression.position());
+                Ok(())
+            }
+            _ => self.compile_expression(expression),
+        }
+    }
+
+    fn check_constructor_call(
+        enum_type: &Enum,
+        index: usize,
+        expression: &CallExpression,
+    ) -> Result<(), Error> {
+        let name = enum_type.variant_name(index);
+        let field_count = enum_type.variants[index].1;
+        let argument_count = expression.arguments.len() + expression.keyword_arguments.len();
+        let message = if field_count == 0 {
+            format!("Invalid call. '{name}' is not callable.")
+        } else if !expression.keyword_arguments.is_empty() {
+            format!("Invalid arguments. '{name}' does not accept keyword arguments.")
+        } else if field_count != argument_count {
+            format!(
+                "Invalid arguments. '{name}' expects {field_count} argument(s), found {argument_count}."
+            )
+        } else {
+            return Ok(());
+        };
+        Err(Error::new(
+            ErrorKind::Compiler,
+            message,
+            Some(expression.position()),
+        ))
+    }
+
+    fn compile_optional_jump(&mut self, optional: bool, position: Position) {
+        if optional {
+            let jump = self
+                .chunk()
+                .add_instruction(Instruction::JumpIfNil(usize::MAX), position);
+            self.optional_jumps.push(jump);
+        }
+    }
+
+    fn compile_spawn_expression(&mut self, expression: &SpawnExpression) -> Result<(), Error> {
+        self.compile_expression(&expression.call.callee)?;
+        let keywords = self.compile_arguments(&expression.call)?;
+        let argument_count = expression.call.arguments.len() + keywords.len();
+        self.chunk().add_instruction(
+            Instruction::Spawn(argument_count, keywords),
+            expression.position(),
+        );
+        Ok(())
+    }
+
+    /// Pushes the positional and keyword arguments of a call, and returns the keywords in
+    /// order.
+    fn compile_arguments(&mut self, expression: &CallExpression) -> Result<Vec<String>, Error> {
+        for argument in &expression.arguments {
+            self.compile_expression(argument)?;
+        }
+        let mut keywords = Vec::new();
+        for (keyword, argument) in &expression.keyword_arguments {
+            self.compile_expression(argument)?;
+            keywords.push(keyword.lexeme.clone());
+        }
+        Ok(keywords)
+    }
+
+    fn compile_set_expression(&mut self, expression: &SetExpression) -> Result<(), Error> {
+        if expression.optional {
+            return Err(Error::new(
+                ErrorKind::Compiler,
+                format!(
+                    "Invalid assignment. Can not assign to '{}' through '?.'.",
+                    expression.identifier.lexeme
+                ),
+                Some(expression.position()),
+            ));
+        }
+        self.compile_expression(&expression.object)?;
+        self.compile_expression(&expression.value)?;
+        let name = self.interner.intern(&expression.identifier.lexeme);
+        self.chunk()
+            .add_instruction(Instruction::SetProperty(name), expression.position());
+        Ok(())
+    }
+
+    fn compile_lambda_expression(&mut self, expression: &LambdaExpression) -> Result<(), Error> {
+        self.compile_function(
+            &expression.name(),
+            &expression.parameters,
+            &expression.block,
+            expression.position(),
+            expression.generator,
+            FunctionKind::Function,
+        )
+    }
+
+    fn compile_list_expression(&mut self, expression: &ListExpression) -> Result<(), Error> {
+        for element in &expression.elements {
+            self.compile_expression(element)?;
+        }
+        self.chunk().add_instruction(
+            Instruction::BuildList(expression.elements.len()),
+            expression.position(),
+        );
+        Ok(())
+    }
+
     fn compile_literal_expression(&mut self, expression: &LiteralExpression) -> Result<(), Error> {
+        let value = self.literal_value(expression);
+        self.chunk()
+            .add_instruction(Instruction::Push(value), expression.position());
+        Ok(())
+    }
+
+    fn literal_value(&self, expression: &LiteralExpression) -> Object {
         if expression.value.kind == TokenKind::Nil {
-            self.chunk
-                .add_instruction(Instruction::Push(Object::Nil), expression.position());
+            Object::Nil
         } else if expression.value.kind == TokenKind::Number {
-            self.chunk.add_instruction(
-                Instruction::Push(Object::Number(expression.value.lexeme.parse().unwrap())),
-                expression.position(),
-            );
+            let lexeme = &expression.value.lexeme;
+            // Integer literals past the exact range of numbers become big integers.
+            match BigInt::parse(lexeme) {
+                Some(integer) => Object::integer(integer),
+                None => Object::Number(lexeme.parse().unwrap()),
+            }
         } else if expression.value.kind == TokenKind::True
             || expression.value.kind == TokenKind::False
         {
-            self.chunk.add_instruction(
-                Instruction::Push(Object::Boolean(expression.value.lexeme.parse().unwrap())),
-                expression.position(),
-            );
+            Object::Boolean(expression.value.lexeme.parse().unwrap())
         } else {
-            self.chunk.add_instruction(
-                Instruction::Push(Object::String(expression.value.lexeme.clone())),
-                expression.position(),
-            );
+            Object::String(self.interner.intern(&expression.value.lexeme))
         }
-
-        Ok(())
     }
 
     fn compile_variable_expression(
         &mut self,
         expression: &VariableExpression,
     ) -> Result<(), Error> {
-        let (_, initializer) = self.find_binding(expression.identifier.clone())?;
-        self.compile_expression(&initializer.clone())?;
+        let instruction = match self.find_binding(&expression.identifier)? {
+            Binding::Global => {
+                Instruction::GetGlobal(self.interner.intern(&expression.identifier.lexeme))
+            }
+            Binding::Local(slot) => Instruction::GetLocal(slot),
+            Binding::Cell(slot) => Instruction::GetCell(slot),
+            Binding::Upvalue(index) => Instruction::GetUpvalue(index),
+            Binding::Constant(value) => Instruction::Push(value),
+        };
+        self.chunk()
+            .add_instruction(instruction, expression.position());
+        Ok(())
+    }
+
+    fn begin_scope(&mut self) {
+        self.context().bindings.push(SymbolTable::default());
+    }
+
+    fn end_scope(&mut self) {
+        let bindings = self.context().bindings.pop().unwrap();
+        let slot_count = bindings
+            .borrow()
+            .values()
+            .filter(|symbol| !matches!(symbol.binding, Binding::Constant(_)))
+            .count();
+        for _ in 0..slot_count {
+            self.chunk()
+                .add_instruction(Instruction::Pop, Position::new(0, 0, 0, 0));
+        }
+        self.context().local_count -= slot_count;
+    }
+
+    /// Top level functions, enums and variables are declared up front, so functions can refer
+    /// to globals that are defined after them. Constants can not be declared twice.
+    fn declare_globals(&mut self, program: &Program) -> Result<(), Error> {
+        for statement in program {
+            let (identifier, symbol) = match statement {
+                Statement::Function(statement) => {
+                    (&statement.identifier, Symbol::new(Binding::Global, None))
+                }
+                Statement::Class(statement) => {
+                    (&statement.identifier, Symbol::new(Binding::Global, None))
+                }
+                Statement::Enum(statement) => (
+                    &statement.identifier,
+                    Symbol::new(
+                        Binding::Constant(Object::Enum(Self::enum_type(statement)?)),
+                        Some(statement.identifier.position.clone()),
+                    ),
+                ),
+                Statement::Variable(statement) => {
+                    let constant = statement
+                        .constant
+                        .then(|| statement.identifier.position.clone());
+                    let binding = match &statement.initializer {
+                        Expression::Literal(literal) if statement.constant => {
+                            Binding::Constant(self.literal_value(literal))
+                        }
+                        _ => Binding::Global,
+                    };
+                    (&statement.identifier, Symbol::new(binding, constant))
+                }
+                _ => continue,
+            };
+            let previous = self
+                .globals
+                .borrow_mut()
+                .insert(identifier.lexeme.clone(), symbol.clone());
+            let message = match previous {
+                Some(Symbol {
+                    constant: Some(declaration),
+                    ..
+                }) => format!(
+                    "Duplicate identifier. '{}' is already declared as a constant at line {}, column {}.",
+                    identifier.lexeme,
+                    declaration.line + 1,
+                    declaration.column + 1
+                ),
+                Some(_) if symbol.constant.is_some() => format!(
+                    "Duplicate identifier. '{}' is already declared and can not be redeclared as a constant.",
+                    identifier.lexeme
+                ),
+                _ => continue,
+            };
+            return Err(Error::new(
+                ErrorKind::Compiler,
+                message,
+                Some(identifier.position.clone()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Binds the value on top of the stack to `identifier`, either as a global or as a new
+    /// local slot in the innermost scope.
+    fn define_binding(&mut self, identifier: &Token, constant: bool) -> Result<(), Error> {
+        self.check_duplicate(identifier)?;
+        let constant = constant.then(|| identifier.position.clone());
+        let context = self.context();
+        if let Some(bindings) = context.bindings.last() {
+            let slot = context.local_count;
+            context.local_count += 1;
+            if context.captured_names.contains(&identifier.lexeme) {
+                bindings.borrow_mut().insert(
+                    identifier.lexeme.clone(),
+                    Symbol::new(Binding::Cell(slot), constant),
+                );
+                self.chunk()
+                    .add_instruction(Instruction::BoxLocal(slot), identifier.position.clone());
+            } else {
+                bindings.borrow_mut().insert(
+                    identifier.lexeme.clone(),
+                    Symbol::new(Binding::Local(slot), constant),
+                );
+            }
+        } else {
+            self.globals.borrow_mut().insert(
+                identifier.lexeme.clone(),
+                Symbol::new(Binding::Global, constant),
+            );
+            let name = self.interner.intern(&identifier.lexeme);
+            self.chunk()
+                .add_instruction(Instruction::DefineGlobal(name), identifier.position.clone());
+        }
+        Ok(())
+    }
+
+    /// Binds `identifier` to a constant value that needs no slot at runtime.
+    fn define_constant(&mut self, identifier: &Token, value: Object) -> Result<(), Error> {
+        self.check_duplicate(identifier)?;
+        let symbol = Symbol::new(Binding::Constant(value), Some(identifier.position.clone()));
+        match self.context().bindings.last() {
+            Some(bindings) => bindings
+                .borrow_mut()
+                .insert(identifier.lexeme.clone(), symbol),
+            None => self
+                .globals
+                .borrow_mut()
+                .insert(identifier.lexeme.clone(), symbol),
+        };
         Ok(())
     }
 
-    fn find_binding(&self, identifier: Token) -> Result<(usize, Expression), Error> {
-        for i in (0..self.bindings.len()).rev() {
-            let bindings = self.bindings[i].borrow();
-            if let Some(expression) = bindings.get(&identifier.lexeme) {
-                return Ok((i, expression.clone()));
+    fn check_duplicate(&mut self, identifier: &Token) -> Result<(), Error> {
+        if let Some(bindings) = self.context().bindings.last() {
+            if bindings.borrow().contains_key(&identifier.lexeme) {
+                return Err(Error::new(
+                    ErrorKind::Compiler,
+                    format!(
+                        "Duplicate identifier. '{}' is already defined in this scope.",
+                        identifier.lexeme
+                    ),
+                    Some(identifier.position.clone()),
+                ));
             }
         }
+        Ok(())
+    }
+
+    /// Finds the symbol `name` refers to, looking through every enclosing function.
+    fn resolve(&self, name: &str) -> Option<Symbol> {
+        (0..self.contexts.len())
+            .rev()
+            .find_map(|context_index| self.find_local(context_index, name))
+            .or_else(|| self.globals.borrow().get(name).cloned())
+    }
+
+    fn find_binding(&mut self, identifier: &Token) -> Result<Binding, Error> {
+        if let Some(Symbol {
+            binding: Binding::Constant(value),
+            ..
+        }) = self.resolve(&identifier.lexeme)
+        {
+            return Ok(Binding::Constant(value));
+        }
+        let context_index = self.contexts.len() - 1;
+        if let Some(symbol) = self.find_local(context_index, &identifier.lexeme) {
+            return Ok(symbol.binding);
+        }
+        if let Some(index) = self.find_upvalue(context_index, identifier)? {
+            return Ok(Binding::Upvalue(index));
+        }
+        if let Some(symbol) = self.globals.borrow().get(&identifier.lexeme) {
+            return Ok(symbol.binding.clone());
+        }
         Err(Error::new(
             ErrorKind::Compiler,
             format!(
                 "Undefined identifier. '{}' is not defined.",
                 identifier.lexeme
             ),
-            Some(identifier.position),
+            Some(identifier.position.clone()),
         ))
     }
 
-    fn patch_if_statement(&mut self, patch_index: usize, statement: &IfStatement) {
+    fn find_local(&self, context_index: usize, name: &str) -> Option<Symbol> {
+        self.contexts[context_index]
+            .bindings
+            .iter()
+            .rev()
+            .find_map(|bindings| bindings.borrow().get(name).cloned())
+    }
+
+    /// Resolves `identifier` as a variable of an enclosing function, recording it as a
+    /// capture of every function in between.
+    fn find_upvalue(
+        &mut self,
+        context_index: usize,
+        identifier: &Token,
+    ) -> Result<Option<usize>, Error> {
+        if context_index == 0 {
+            return Ok(None);
+        }
+        if let Some(index) = self.contexts[context_index]
+            .captures
+            .iter()
+            .position(|(name, _)| name == &identifier.lexeme)
+        {
+            return Ok(Some(index));
+        }
+
+        let capture = match self
+            .find_local(context_index - 1, &identifier.lexeme)
+            .map(|symbol| symbol.binding)
+        {
+            Some(Binding::Cell(slot)) => Capture::Local(slot),
+            Some(_) => {
+                return Err(Error::new(
+                    ErrorKind::Compiler,
+                    format!(
+                        "Invalid capture. Can not capture local '{}' from an enclosing function.",
+                        identifier.lexeme
+                    ),
+                    Some(identifier.position.clone()),
+                ))
+            }
+            None => match self.find_upvalue(context_index - 1, identifier)? {
+                Some(index) => Capture::Upvalue(index),
+                None => return Ok(None),
+            },
+        };
+        let captures = &mut self.contexts[context_index].captures;
+        captures.push((identifier.lexeme.clone(), capture));
+        Ok(Some(captures.len() - 1))
+    }
+
+    fn set_instruction(&self, binding: Binding, identifier: &Token) -> Instruction {
+        match binding {
+            Binding::Global => Instruction::SetGlobal(self.interner.intern(&identifier.lexeme)),
+            Binding::Local(slot) => Instruction::SetLocal(slot),
+            Binding::Cell(slot) => Instruction::SetCell(slot),
+            Binding::Upvalue(index) => Instruction::SetUpvalue(index),
+            Binding::Constant(_) => unreachable!("constants are never assigned"),
+        }
+    }
+
+    fn patch_jump(&mut self, patch_index: usize, position: Position) {
         let jump_address = self
-            .chunk
-            .add_instruction(Instruction::Continue, statement.condition.position());
-        self.chunk
-            .edit_instruction(patch_index, Instruction::JumpIfFalse(jump_address));
+            .chunk()
+            .add_instruction(Instruction::Continue, position);
+        self.chunk().patch_jump(patch_index, jump_address);
     }
 
-    pub(crate) fn get_bindings(&self) -> Vec<SymbolTable> {
-        self.bindings.clone()
+    fn context(&mut self) -> &mut FunctionContext {
+        self.contexts.last_mut().unwrap()
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.context().chunk
+    }
+
+    pub(crate) fn get_globals(&self) -> SymbolTable {
+        self.globals.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{common::error::ErrorKind, compile_source};
+
+    #[test]
+    fn rejects_assigning_through_an_optional_chain() {
+        let error = compile_source("var a = nil\na?.x = 1\n").err().unwrap();
+        assert!(matches!(error.kind, ErrorKind::Compiler));
+        assert_eq!(
+            error.message,
+            "Invalid assignment. Can not assign to 'x' through '?.'."
+        );
+    }
+
+    #[test]
+    fn rejects_assigning_to_constants() {
+        let message = |source| compile_source(source).err().unwrap().message;
+        assert_eq!(
+            message("const LIMIT = 1\nLIMIT = 2\n"),
+            "Invalid assignment. 'LIMIT' is a constant, declared at line 1, column 7."
+        );
+        assert_eq!(
+            message("fun f() {\n  const step = [1]\n  return fun() { step = 2 }\n}\n"),
+            "Invalid assignment. 'step' is a constant, declared at line 2, column 9."
+        );
+        assert!(compile_source("const LIMIT = 1\nvar limit = LIMIT\nlimit = 2\n").is_ok());
+    }
+
+    #[test]
+    fn rejects_wrong_arity_and_unknown_variants() {
+        let message = |code| {
+            let source = format!("enum E {{ A, B(x) }}\nvar v = E.A\n{code}\n");
+            compile_source(&source).err().unwrap().message
+        };
+        assert_eq!(
+            message("E.B(1, 2)"),
+            "Invalid arguments. 'E.B' expects 1 argument(s), found 2."
+        );
+        assert_eq!(message("E.C"), "Unknown variant. 'E' has no variant 'C'.");
+        assert_eq!(
+            message("if v is E.C { }"),
+            "Unknown variant. 'E' has no variant 'C'."
+        );
+        assert_eq!(
+            message("if v is E.B(x, y) { }"),
+            "Invalid pattern. 'E.B' carries 1 value(s), found 2 name(s)."
+        );
     }
 }
@@ -1,3 +1,5 @@
+pub(crate) mod captures;
+pub(crate) mod checker;
 pub(crate) mod compiler;
 pub(crate) mod parser;
 pub(crate) mod scanner;
@@ -1,9 +1,11 @@
 use crate::common::{
     ast::{
-        AssignmentExpression, BinaryExpression, BlockStatement, CallExpression, ElseStatement,
-        Expression, ExpressionStatement, FunctionStatement, GroupExpression, IfStatement,
-        LiteralExpression, PrintStatement, Program, ReturnStatement, Statement, UnaryExpression,
-        VariableExpression, VariableStatement, WhileStatement,
+        AssertStatement, AssignmentExpression, BinaryExpression, BlockStatement, CallExpression,
+        ClassStatement, ElseStatement, EnumStatement, EnumVariant, Expression, ExpressionStatement,
+        ForStatement, FunctionStatement, GetExpression, GroupExpression, IfStatement,
+        LambdaExpression, ListExpression, LiteralExpression, Parameter, PrintStatement, Program,
+        ReturnStatement, SetExpression, SpawnExpression, Statement, UnaryExpression,
+        VariableExpression, VariableStatement, VariantPattern, WhileStatement, YieldStatement,
     },
     error::{Error, ErrorKind},
     token::{Token, TokenKind},
@@ -11,14 +13,20 @@ use crate::common::{
 
 pub(crate) struct Parser {
     tokens: Vec<Token>,
+    /// Source the tokens were scanned from, for statements that keep their own text.
+    source: Vec<char>,
     current_index: usize,
+    /// One entry per function being parsed, set once a `yield` is found in its body.
+    yields: Vec<bool>,
 }
 
 impl Parser {
-    pub(crate) fn new(tokens: Vec<Token>) -> Self {
+    pub(crate) fn new(tokens: Vec<Token>, source: &str) -> Self {
         Self {
             tokens,
+            source: source.chars().collect(),
             current_index: 0,
+            yields: Vec::new(),
         }
     }
 
@@ -34,26 +42,80 @@ impl Parser {
 
     fn parse_statement(&mut self) -> Result<Statement, Error> {
         match self.current_token().kind {
-            TokenKind::Fun => Ok(Statement::Function(self.parse_function_statement()?)),
+            TokenKind::Fun if !self.lambda_follows() => {
+                Ok(Statement::Function(self.parse_function_statement()?))
+            }
+            TokenKind::Class => Ok(Statement::Class(self.parse_class_statement()?)),
+            TokenKind::Enum => Ok(Statement::Enum(self.parse_enum_statement()?)),
             TokenKind::If => Ok(Statement::If(self.parse_if_statement()?)),
-            TokenKind::For => Ok(Statement::Block(self.parse_for_statement()?)),
+            TokenKind::For => self.parse_for_statement(),
             TokenKind::While => Ok(Statement::While(self.parse_while_statement()?)),
             TokenKind::OpenBrace => Ok(Statement::Block(self.parse_block_statement()?)),
-            TokenKind::Var => Ok(Statement::Variable(self.parse_var_statement()?)),
+            TokenKind::Var | TokenKind::Const => {
+                Ok(Statement::Variable(self.parse_var_statement()?))
+            }
             TokenKind::Return => Ok(Statement::Return(self.parse_return_statement()?)),
+            TokenKind::Yield => Ok(Statement::Yield(self.parse_yield_statement()?)),
             TokenKind::Print => Ok(Statement::Print(self.parse_print_statement()?)),
+            TokenKind::Assert => Ok(Statement::Assert(self.parse_assert_statement()?)),
             _ => Ok(Statement::Expression(self.parse_expression_statement()?)),
         }
     }
 
     fn parse_function_statement(&mut self) -> Result<FunctionStatement, Error> {
         self.consume_token(TokenKind::Fun)?;
+        let generator = self.parse_generator_marker()?;
         let identifier = self.consume_token(TokenKind::Identifier)?;
         self.consume_token(TokenKind::OpenParen)?;
+        let parameters = self.parse_parameters(TokenKind::CloseParen)?;
+        self.consume_token(TokenKind::CloseParen)?;
+        let return_annotation = if self.current_token_matches(&[TokenKind::Arrow]) {
+            self.consume_token(TokenKind::Arrow)?;
+            Some(self.parse_type_annotation()?)
+        } else {
+            None
+        };
+        let (block, yields) = self.parse_function_body()?;
+
+        Ok(FunctionStatement::new(
+            identifier,
+            parameters,
+            return_annotation,
+            block,
+            generator || yields,
+        ))
+    }
+
+    fn parse_parameters(&mut self, closing_kind: TokenKind) -> Result<Vec<Parameter>, Error> {
         let mut parameters = Vec::new();
-        if !self.current_token_matches(&[TokenKind::CloseParen]) {
+        if !self.current_token_matches(&[closing_kind]) {
             loop {
-                parameters.push(self.consume_token(TokenKind::Identifier)?);
+                let variadic = self.current_token_matches(&[TokenKind::DotDotDot]);
+                if variadic {
+                    self.consume_token(TokenKind::DotDotDot)?;
+                }
+                let identifier = self.consume_token(TokenKind::Identifier)?;
+                let annotation = self.parse_optional_annotation()?;
+                let (default, default_source) = if self.current_token_matches(&[TokenKind::Assign])
+                {
+                    self.consume_token(TokenKind::Assign)?;
+                    let start = self.current_token().position.start;
+                    let default = self.parse_expression()?;
+                    let end = self.tokens[self.current_index - 1].position.end;
+                    (
+                        Some(default),
+                        Some(self.source[start..end].iter().collect()),
+                    )
+                } else {
+                    (None, None)
+                };
+                parameters.push(Parameter::new(
+                    identifier,
+                    annotation,
+                    default,
+                    default_source,
+                    variadic,
+                ));
                 if self.current_token_matches(&[TokenKind::Comma]) {
                     self.consume_token(TokenKind::Comma)?;
                 } else {
@@ -61,15 +123,110 @@ impl Parser {
                 }
             }
         }
-        self.consume_token(TokenKind::CloseParen)?;
-        let block = self.parse_block_statement()?;
 
-        Ok(FunctionStatement::new(identifier, parameters, block))
+        Ok(parameters)
+    }
+
+    /// `fun*` marks a generator, even if it never yields.
+    fn parse_generator_marker(&mut self) -> Result<bool, Error> {
+        if self.current_token_matches(&[TokenKind::Star]) {
+            self.consume_token(TokenKind::Star)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Parses a function's block, and tells whether it yields.
+    fn parse_function_body(&mut self) -> Result<(BlockStatement, bool), Error> {
+        self.yields.push(false);
+        let block = self.parse_block_statement();
+        let yields = self.yields.pop().unwrap();
+        Ok((block?, yields))
+    }
+
+    fn parse_optional_annotation(&mut self) -> Result<Option<Token>, Error> {
+        if self.current_token_matches(&[TokenKind::Colon]) {
+            self.consume_token(TokenKind::Colon)?;
+            Ok(Some(self.parse_type_annotation()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Type names are checked by `indu check`; `nil` and `fun` are keywords, so they are
+    /// accepted here as well.
+    fn parse_type_annotation(&mut self) -> Result<Token, Error> {
+        if self.current_token_matches(&[TokenKind::Nil, TokenKind::Fun]) {
+            Ok(self.next_token())
+        } else {
+            self.consume_token(TokenKind::Identifier)
+        }
+    }
+
+    fn parse_class_statement(&mut self) -> Result<ClassStatement, Error> {
+        self.consume_token(TokenKind::Class)?;
+        let identifier = self.consume_token(TokenKind::Identifier)?;
+        self.consume_token(TokenKind::OpenBrace)?;
+        let mut methods = Vec::new();
+        while !self.current_token_matches(&[TokenKind::CloseBrace]) && !self.current_token_is_eof()
+        {
+            methods.push(self.parse_function_statement()?);
+        }
+        self.consume_token(TokenKind::CloseBrace)?;
+        Ok(ClassStatement::new(identifier, methods))
+    }
+
+    fn parse_enum_statement(&mut self) -> Result<EnumStatement, Error> {
+        self.consume_token(TokenKind::Enum)?;
+        let identifier = self.consume_token(TokenKind::Identifier)?;
+        self.consume_token(TokenKind::OpenBrace)?;
+        let mut variants = Vec::new();
+        while !self.current_token_matches(&[TokenKind::CloseBrace]) {
+            let variant = self.consume_token(TokenKind::Identifier)?;
+            let fields = self.parse_identifier_list()?;
+            variants.push(EnumVariant::new(variant, fields));
+            if self.current_token_matches(&[TokenKind::Comma]) {
+                self.consume_token(TokenKind::Comma)?;
+            } else {
+                break;
+            }
+        }
+        self.consume_token(TokenKind::CloseBrace)?;
+        Ok(EnumStatement::new(identifier, variants))
+    }
+
+    /// Parses an optional list of names in parentheses, like `(w, h)`.
+    fn parse_identifier_list(&mut self) -> Result<Vec<Token>, Error> {
+        let mut identifiers = Vec::new();
+        if self.current_token_matches(&[TokenKind::OpenParen]) {
+            self.consume_token(TokenKind::OpenParen)?;
+            while !self.current_token_matches(&[TokenKind::CloseParen]) {
+                identifiers.push(self.consume_token(TokenKind::Identifier)?);
+                if self.current_token_matches(&[TokenKind::Comma]) {
+                    self.consume_token(TokenKind::Comma)?;
+                } else {
+                    break;
+                }
+            }
+            self.consume_token(TokenKind::CloseParen)?;
+        }
+        Ok(identifiers)
     }
 
     fn parse_if_statement(&mut self) -> Result<IfStatement, Error> {
         self.consume_token(TokenKind::If)?;
         let condition = self.parse_expression()?;
+        let pattern = if self.current_token_matches(&[TokenKind::Is]) {
+            self.consume_token(TokenKind::Is)?;
+            let enum_identifier = self.consume_token(TokenKind::Identifier)?;
+            self.consume_token(TokenKind::Dot)?;
+            let variant = self.consume_token(TokenKind::Identifier)?;
+            let bindings = self.parse_identifier_list()?;
+            Some(VariantPattern::new(enum_identifier, variant, bindings))
+        } else {
+            None
+        };
         let then_branch = self.parse_block_statement()?;
         if self.current_token_matches(&[TokenKind::Else]) {
             self.consume_token(TokenKind::Else)?;
@@ -77,6 +234,7 @@ impl Parser {
                 let else_branch = self.parse_if_statement()?;
                 Ok(IfStatement::new(
                     condition,
+                    pattern,
                     then_branch,
                     Some(ElseStatement::If(else_branch)),
                 ))
@@ -84,17 +242,28 @@ impl Parser {
                 let else_branch = self.parse_block_statement()?;
                 Ok(IfStatement::new(
                     condition,
+                    pattern,
                     then_branch,
                     Some(ElseStatement::Block(else_branch)),
                 ))
             }
         } else {
-            Ok(IfStatement::new(condition, then_branch, None))
+            Ok(IfStatement::new(condition, pattern, then_branch, None))
         }
     }
 
-    fn parse_for_statement(&mut self) -> Result<BlockStatement, Error> {
+    fn parse_for_statement(&mut self) -> Result<Statement, Error> {
         self.consume_token(TokenKind::For)?;
+        if !self.current_token_matches(&[TokenKind::Var]) {
+            let identifier = self.consume_token(TokenKind::Identifier)?;
+            self.consume_token(TokenKind::In)?;
+            let iterable = self.parse_expression()?;
+            let do_block = self.parse_block_statement()?;
+            return Ok(Statement::For(ForStatement::new(
+                identifier, iterable, do_block,
+            )));
+        }
+
         let variable_initialization = self.parse_var_statement()?;
         self.consume_token(TokenKind::Comma)?;
         let condition = self.parse_comparison_expression()?;
@@ -109,10 +278,10 @@ impl Parser {
             ]),
         ));
 
-        Ok(BlockStatement::new(vec![
+        Ok(Statement::Block(BlockStatement::new(vec![
             Statement::Variable(variable_initialization),
             while_statement,
-        ]))
+        ])))
     }
 
     fn parse_while_statement(&mut self) -> Result<WhileStatement, Error> {
@@ -136,12 +305,23 @@ impl Parser {
     }
 
     fn parse_var_statement(&mut self) -> Result<VariableStatement, Error> {
-        self.consume_token(TokenKind::Var)?;
+        let constant = self.current_token_matches(&[TokenKind::Const]);
+        self.consume_token(if constant {
+            TokenKind::Const
+        } else {
+            TokenKind::Var
+        })?;
         let identifier = self.consume_token(TokenKind::Identifier)?;
+        let annotation = self.parse_optional_annotation()?;
         self.consume_token(TokenKind::Assign)?;
         let initializer = self.parse_expression()?;
 
-        Ok(VariableStatement::new(identifier, initializer))
+        Ok(VariableStatement::new(
+            identifier,
+            annotation,
+            initializer,
+            constant,
+        ))
     }
 
     fn parse_return_statement(&mut self) -> Result<ReturnStatement, Error> {
@@ -150,17 +330,51 @@ impl Parser {
         Ok(ReturnStatement::new(expression))
     }
 
+    fn parse_yield_statement(&mut self) -> Result<YieldStatement, Error> {
+        let keyword = self.consume_token(TokenKind::Yield)?;
+        if let Some(yields) = self.yields.last_mut() {
+            *yields = true;
+        } else {
+            return Err(Error::new(
+                ErrorKind::Parser,
+                "Invalid yield. 'yield' can only be used inside a function.".to_string(),
+                Some(keyword.position),
+            ));
+        }
+        let expression = self.parse_expression()?;
+        Ok(YieldStatement::new(expression))
+    }
+
     fn parse_print_statement(&mut self) -> Result<PrintStatement, Error> {
         self.consume_token(TokenKind::Print)?;
         let expression = self.parse_expression()?;
         Ok(PrintStatement::new(expression))
     }
 
+    fn parse_assert_statement(&mut self) -> Result<AssertStatement, Error> {
+        self.consume_token(TokenKind::Assert)?;
+        let start = self.current_token().position.start;
+        let condition = self.parse_expression()?;
+        let end = self.tokens[self.current_index - 1].position.end;
+        let source = self.source[start..end].iter().collect();
+        let message = if self.current_token_matches(&[TokenKind::Comma]) {
+            self.consume_token(TokenKind::Comma)?;
+            Some(self.parse_expression()?)
+        } else {
+            None
+        };
+        Ok(AssertStatement::new(condition, source, message))
+    }
+
     fn parse_expression_statement(&mut self) -> Result<ExpressionStatement, Error> {
         let expression = self.parse_expression()?;
         Ok(ExpressionStatement::new(expression))
     }
 
+    /// Expressions are parsed by precedence, from lowest to highest: assignment, `??`, `or`,
+    /// `and`, equality, comparison, ranges, additive, multiplicative, unary and `spawn`, then
+    /// calls, property access and their optional forms (`?.`), and finally primary
+    /// expressions.
     fn parse_expression(&mut self) -> Result<Expression, Error> {
         self.parse_assignment_expression()
     }
@@ -177,6 +391,13 @@ impl Parser {
                     expression.identifier,
                     initializer,
                 )));
+            } else if let Expression::Get(expression) = expression {
+                return Ok(Expression::Set(SetExpression::new(
+                    *expression.object,
+                    expression.identifier,
+                    initializer,
+                    expression.optional,
+                )));
             } else {
                 return Err(Error::new(
                     ErrorKind::Parser,
@@ -193,7 +414,18 @@ impl Parser {
     }
 
     fn parse_binary_expression(&mut self) -> Result<Expression, Error> {
-        self.parse_logical_or_expression()
+        self.parse_nil_coalescing_expression()
+    }
+
+    fn parse_nil_coalescing_expression(&mut self) -> Result<Expression, Error> {
+        let mut left = self.parse_logical_or_expression()?;
+        while self.current_token_matches(&[TokenKind::QuestionQuestion]) {
+            let operator = self.next_token();
+            let right = self.parse_logical_or_expression()?;
+            left = Expression::Binary(BinaryExpression::new(left, operator, right));
+        }
+
+        Ok(left)
     }
 
     fn parse_logical_or_expression(&mut self) -> Result<Expression, Error> {
@@ -230,7 +462,7 @@ impl Parser {
     }
 
     fn parse_comparison_expression(&mut self) -> Result<Expression, Error> {
-        let mut left = self.parse_additive_expression()?;
+        let mut left = self.parse_range_expression()?;
         while self.current_token_matches(&[
             TokenKind::Greater,
             TokenKind::Lesser,
@@ -238,13 +470,26 @@ impl Parser {
             TokenKind::LesserEqual,
         ]) {
             let operator = self.next_token();
-            let right = self.parse_additive_expression()?;
+            let right = self.parse_range_expression()?;
             left = Expression::Binary(BinaryExpression::new(left, operator, right));
         }
 
         Ok(left)
     }
 
+    fn parse_range_expression(&mut self) -> Result<Expression, Error> {
+        let left = self.parse_additive_expression()?;
+        if self.current_token_matches(&[TokenKind::DotDot, TokenKind::DotDotEqual]) {
+            let operator = self.next_token();
+            let right = self.parse_additive_expression()?;
+            return Ok(Expression::Binary(BinaryExpression::new(
+                left, operator, right,
+            )));
+        }
+
+        Ok(left)
+    }
+
     fn parse_additive_expression(&mut self) -> Result<Expression, Error> {
         let mut left = self.parse_multiplicative_expression()?;
         while self.current_token_matches(&[TokenKind::Plus, TokenKind::Minus]) {
@@ -258,7 +503,7 @@ impl Parser {
 
     fn parse_multiplicative_expression(&mut self) -> Result<Expression, Error> {
         let mut left = self.parse_unary_expression()?;
-        while self.current_token_matches(&[TokenKind::Star, TokenKind::Slash]) {
+        while self.current_token_matches(&[TokenKind::Star, TokenKind::Slash, TokenKind::Percent]) {
             let operator = self.next_token();
             let right = self.parse_unary_expression()?;
             left = Expression::Binary(BinaryExpression::new(left, operator, right));
@@ -272,6 +517,18 @@ impl Parser {
             let operator = self.next_token();
             let right = self.parse_unary_expression()?;
             Ok(Expression::Unary(UnaryExpression::new(operator, right)))
+        } else if self.current_token_matches(&[TokenKind::Spawn]) {
+            let keyword = self.consume_token(TokenKind::Spawn)?;
+            match self.parse_call_expression()? {
+                Expression::Call(call) if !call.optional => {
+                    Ok(Expression::Spawn(SpawnExpression::new(keyword, call)))
+                }
+                _ => Err(Error::new(
+                    ErrorKind::Parser,
+                    "Invalid spawn. Expected a function call after 'spawn'.".to_string(),
+                    Some(keyword.position),
+                )),
+            }
         } else {
             self.parse_call_expression()
         }
@@ -280,13 +537,46 @@ impl Parser {
     fn parse_call_expression(&mut self) -> Result<Expression, Error> {
         let mut expression = self.parse_primary_expression()?;
 
-        while self.current_token_matches(&[TokenKind::OpenParen]) {
+        while self.current_token_matches(&[
+            TokenKind::OpenParen,
+            TokenKind::Dot,
+            TokenKind::QuestionDot,
+        ]) {
+            let optional = self.current_token_matches(&[TokenKind::QuestionDot]);
+            if optional {
+                self.consume_token(TokenKind::QuestionDot)?;
+            }
+            if self.current_token_matches(&[TokenKind::Dot])
+                || optional && !self.current_token_matches(&[TokenKind::OpenParen])
+            {
+                if !optional {
+                    self.consume_token(TokenKind::Dot)?;
+                }
+                let identifier = self.consume_token(TokenKind::Identifier)?;
+                expression = Expression::Get(GetExpression::new(expression, identifier, optional));
+                continue;
+            }
+
             self.consume_token(TokenKind::OpenParen)?;
             let mut arguments = Vec::new();
+            let mut keyword_arguments = Vec::new();
 
             if !self.current_token_matches(&[TokenKind::CloseParen]) {
                 loop {
-                    arguments.push(self.parse_expression()?);
+                    if self.current_token_matches(&[TokenKind::Identifier])
+                        && self.peek_token_matches(&[TokenKind::Colon])
+                    {
+                        let identifier = self.consume_token(TokenKind::Identifier)?;
+                        self.consume_token(TokenKind::Colon)?;
+                        keyword_arguments.push((identifier, self.parse_expression()?));
+                    } else if keyword_arguments.is_empty() {
+                        arguments.push(self.parse_expression()?);
+                    } else {
+                        return Err(self.generate_error(
+                            "Invalid argument. Positional arguments can not follow keyword arguments."
+                                .to_string(),
+                        ));
+                    }
                     if self.current_token_matches(&[TokenKind::Comma]) {
                         self.consume_token(TokenKind::Comma)?;
                     } else {
@@ -296,7 +586,12 @@ impl Parser {
             }
 
             self.consume_token(TokenKind::CloseParen)?;
-            expression = Expression::Call(CallExpression::new(expression, arguments));
+            expression = Expression::Call(CallExpression::new(
+                expression,
+                arguments,
+                keyword_arguments,
+                optional,
+            ));
         }
 
         Ok(expression)
@@ -313,7 +608,7 @@ impl Parser {
             Ok(Expression::Literal(LiteralExpression::new(
                 self.next_token(),
             )))
-        } else if self.current_token_matches(&[TokenKind::Identifier]) {
+        } else if self.current_token_matches(&[TokenKind::Identifier, TokenKind::This]) {
             Ok(Expression::Variable(VariableExpression::new(
                 self.next_token(),
             )))
@@ -322,6 +617,60 @@ impl Parser {
             let child = self.parse_expression()?;
             self.consume_token(TokenKind::CloseParen)?;
             Ok(Expression::Group(GroupExpression::new(child)))
+        } else if self.current_token_matches(&[TokenKind::Fun]) {
+            let keyword = self.consume_token(TokenKind::Fun)?;
+            let generator = self.parse_generator_marker()?;
+            self.consume_token(TokenKind::OpenParen)?;
+            let parameters = self.parse_parameters(TokenKind::CloseParen)?;
+            self.consume_token(TokenKind::CloseParen)?;
+            let (block, yields) = self.parse_function_body()?;
+            let column = self.column(&keyword);
+            Ok(Expression::Lambda(LambdaExpression::new(
+                keyword,
+                column,
+                parameters,
+                block,
+                generator || yields,
+            )))
+        } else if self.current_token_matches(&[TokenKind::Pipe, TokenKind::Or]) {
+            let keyword = self.next_token();
+            let parameters = if keyword.kind == TokenKind::Pipe {
+                let parameters = self.parse_parameters(TokenKind::Pipe)?;
+                self.consume_token(TokenKind::Pipe)?;
+                parameters
+            } else {
+                Vec::new()
+            };
+            let (block, yields) = if self.current_token_matches(&[TokenKind::OpenBrace]) {
+                self.parse_function_body()?
+            } else {
+                let expression = self.parse_expression()?;
+                let block =
+                    BlockStatement::new(vec![Statement::Return(ReturnStatement::new(expression))]);
+                (block, false)
+            };
+            let column = self.column(&keyword);
+            Ok(Expression::Lambda(LambdaExpression::new(
+                keyword, column, parameters, block, yields,
+            )))
+        } else if self.current_token_matches(&[TokenKind::OpenBracket]) {
+            let open_bracket = self.consume_token(TokenKind::OpenBracket)?;
+            let mut elements = Vec::new();
+            if !self.current_token_matches(&[TokenKind::CloseBracket]) {
+                loop {
+                    elements.push(self.parse_expression()?);
+                    if self.current_token_matches(&[TokenKind::Comma]) {
+                        self.consume_token(TokenKind::Comma)?;
+                    } else {
+                        break;
+                    }
+                }
+            }
+            self.consume_token(TokenKind::CloseBracket)?;
+            Ok(Expression::List(ListExpression::new(
+                open_bracket,
+                elements,
+            )))
         } else {
             Err(self.generate_error(format!(
                 "Unexpected token. Expected 'literal', found '{}'.",
@@ -356,6 +705,28 @@ impl Parser {
         kinds.contains(&self.current_token().kind)
     }
 
+    /// Whether the `fun` keyword at the current token starts a lambda, like `fun (x) {}` or
+    /// `fun* () {}`, rather than a function statement.
+    fn lambda_follows(&self) -> bool {
+        let next_kind = |offset: usize| {
+            self.tokens
+                .get(self.current_index + offset)
+                .map(|token| token.kind.clone())
+        };
+        match next_kind(1) {
+            Some(TokenKind::OpenParen) => true,
+            Some(TokenKind::Star) => next_kind(2) == Some(TokenKind::OpenParen),
+            _ => false,
+        }
+    }
+
+    fn peek_token_matches(&self, kinds: &[TokenKind]) -> bool {
+        matches!(
+            self.tokens.get(self.current_index + 1),
+            Some(token) if kinds.contains(&token.kind)
+        )
+    }
+
     fn consume_token(&mut self, kind: TokenKind) -> Result<Token, Error> {
         if self.current_token().kind == kind {
             Ok(self.next_token())
@@ -368,6 +739,11 @@ impl Parser {
         }
     }
 
+    /// The column `token` starts at in its line, counting from 1.
+    fn column(&self, token: &Token) -> usize {
+        token.position.column + 1
+    }
+
     fn generate_error(&self, message: String) -> Error {
         Error::new(
             ErrorKind::Parser,
@@ -376,3 +752,22 @@ impl Parser {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    #[test]
+    fn names_lambdas_by_line_and_column() {
+        let mut vm = VirtualMachine::new();
+        let source = concat!(
+            "var a = fun(x) { return x }\n",
+            "  var b = |x| |y| x + y\n",
+            "var c = b(1)\n",
+        );
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("a").as_deref(), Some("<fun <lambda@1:9>>"));
+        assert_eq!(vm.global("b").as_deref(), Some("<fun <lambda@2:11>>"));
+        assert_eq!(vm.global("c").as_deref(), Some("<fun <lambda@2:15>>"));
+    }
+}
@@ -12,6 +12,7 @@ pub(crate) struct Scanner {
     start_index: usize,
     current_index: usize,
     current_line: usize,
+    line_start: usize,
 
     keywords: HashMap<String, TokenKind>,
 }
@@ -24,6 +25,7 @@ impl Scanner {
             start_index: 0,
             current_index: 0,
             current_line: 0,
+            line_start: 0,
 
             keywords: HashMap::new(),
         }
@@ -41,6 +43,7 @@ impl Scanner {
             }
         }
 
+        self.start_index = self.current_index;
         tokens.push(Token::new(
             TokenKind::Eof,
             String::from("\0"),
@@ -57,6 +60,7 @@ impl Scanner {
 
             '\n' => {
                 self.current_line += 1;
+                self.line_start = self.current_index;
                 Ok(None)
             }
 
@@ -65,14 +69,31 @@ impl Scanner {
                 self.generate_lexeme(),
                 self.generate_position(),
             ))),
-            '-' => Ok(Some(Token::new(
-                TokenKind::Minus,
+            '-' => {
+                if self.current_character() == '>' {
+                    self.advance_current_index();
+                    Ok(Some(Token::new(
+                        TokenKind::Arrow,
+                        self.generate_lexeme(),
+                        self.generate_position(),
+                    )))
+                } else {
+                    Ok(Some(Token::new(
+                        TokenKind::Minus,
+                        self.generate_lexeme(),
+                        self.generate_position(),
+                    )))
+                }
+            }
+
+            '*' => Ok(Some(Token::new(
+                TokenKind::Star,
                 self.generate_lexeme(),
                 self.generate_position(),
             ))),
 
-            '*' => Ok(Some(Token::new(
-                TokenKind::Star,
+            '%' => Ok(Some(Token::new(
+                TokenKind::Percent,
                 self.generate_lexeme(),
                 self.generate_position(),
             ))),
@@ -116,18 +137,63 @@ impl Scanner {
                 self.generate_position(),
             ))),
 
-            ',' => Ok(Some(Token::new(
-                TokenKind::Comma,
+            '[' => Ok(Some(Token::new(
+                TokenKind::OpenBracket,
+                self.generate_lexeme(),
+                self.generate_position(),
+            ))),
+
+            ']' => Ok(Some(Token::new(
+                TokenKind::CloseBracket,
                 self.generate_lexeme(),
                 self.generate_position(),
             ))),
 
-            '.' => Ok(Some(Token::new(
-                TokenKind::Dot,
+            ':' => Ok(Some(Token::new(
+                TokenKind::Colon,
                 self.generate_lexeme(),
                 self.generate_position(),
             ))),
 
+            ',' => Ok(Some(Token::new(
+                TokenKind::Comma,
+                self.generate_lexeme(),
+                self.generate_position(),
+            ))),
+
+            '.' => {
+                if self.current_character() == '.' {
+                    self.advance_current_index();
+                    if self.current_character() == '=' {
+                        self.advance_current_index();
+                        Ok(Some(Token::new(
+                            TokenKind::DotDotEqual,
+                            self.generate_lexeme(),
+                            self.generate_position(),
+                        )))
+                    } else if self.current_character() == '.' {
+                        self.advance_current_index();
+                        Ok(Some(Token::new(
+                            TokenKind::DotDotDot,
+                            self.generate_lexeme(),
+                            self.generate_position(),
+                        )))
+                    } else {
+                        Ok(Some(Token::new(
+                            TokenKind::DotDot,
+                            self.generate_lexeme(),
+                            self.generate_position(),
+                        )))
+                    }
+                } else {
+                    Ok(Some(Token::new(
+                        TokenKind::Dot,
+                        self.generate_lexeme(),
+                        self.generate_position(),
+                    )))
+                }
+            }
+
             '=' => {
                 if self.current_character() == '=' {
                     self.advance_current_index();
@@ -218,10 +284,32 @@ impl Scanner {
                         self.generate_position(),
                     )))
                 } else {
-                    Ok(None)
+                    Ok(Some(Token::new(
+                        TokenKind::Pipe,
+                        self.generate_lexeme(),
+                        self.generate_position(),
+                    )))
                 }
             }
 
+            '?' if self.current_character() == '?' => {
+                self.advance_current_index();
+                Ok(Some(Token::new(
+                    TokenKind::QuestionQuestion,
+                    self.generate_lexeme(),
+                    self.generate_position(),
+                )))
+            }
+
+            '?' if self.current_character() == '.' => {
+                self.advance_current_index();
+                Ok(Some(Token::new(
+                    TokenKind::QuestionDot,
+                    self.generate_lexeme(),
+                    self.generate_position(),
+                )))
+            }
+
             _ => {
                 if current_char.is_alphabetic() || current_char == '_' {
                     self.make_identifier()
@@ -279,7 +367,7 @@ impl Scanner {
         while self.current_character().is_ascii_digit() {
             self.advance_current_index();
         }
-        if self.current_character() == '.' {
+        if self.current_character() == '.' && self.peek_character().is_ascii_digit() {
             self.advance_current_index();
             while self.current_character().is_ascii_digit() {
                 self.advance_current_index();
@@ -322,21 +410,29 @@ impl Scanner {
     }
 
     fn init_keywords(&mut self) {
+        self.keywords
+            .insert("assert".to_string(), TokenKind::Assert);
         self.keywords.insert("class".to_string(), TokenKind::Class);
+        self.keywords.insert("const".to_string(), TokenKind::Const);
         self.keywords.insert("else".to_string(), TokenKind::Else);
+        self.keywords.insert("enum".to_string(), TokenKind::Enum);
         self.keywords.insert("false".to_string(), TokenKind::False);
         self.keywords.insert("fun".to_string(), TokenKind::Fun);
         self.keywords.insert("for".to_string(), TokenKind::For);
         self.keywords.insert("if".to_string(), TokenKind::If);
+        self.keywords.insert("in".to_string(), TokenKind::In);
+        self.keywords.insert("is".to_string(), TokenKind::Is);
         self.keywords.insert("nil".to_string(), TokenKind::Nil);
         self.keywords.insert("print".to_string(), TokenKind::Print);
         self.keywords
             .insert("return".to_string(), TokenKind::Return);
+        self.keywords.insert("spawn".to_string(), TokenKind::Spawn);
         self.keywords.insert("super".to_string(), TokenKind::Super);
         self.keywords.insert("this".to_string(), TokenKind::This);
         self.keywords.insert("true".to_string(), TokenKind::True);
         self.keywords.insert("var".to_string(), TokenKind::Var);
         self.keywords.insert("while".to_string(), TokenKind::While);
+        self.keywords.insert("yield".to_string(), TokenKind::Yield);
     }
 
     fn index_in_bound(&self) -> bool {
@@ -351,6 +447,14 @@ impl Scanner {
         }
     }
 
+    fn peek_character(&self) -> char {
+        if self.current_index + 1 < self.source.len() {
+            self.source[self.current_index + 1]
+        } else {
+            '\0'
+        }
+    }
+
     fn advance_current_index(&mut self) {
         self.current_index += 1;
     }
@@ -368,7 +472,12 @@ impl Scanner {
     }
 
     fn generate_position(&self) -> Position {
-        Position::new(self.start_index, self.current_index, self.current_line)
+        Position::new(
+            self.start_index,
+            self.current_index,
+            self.current_line,
+            self.start_index - self.line_start,
+        )
     }
 
     fn generate_error(&self, message: String) -> Error {
@@ -1,84 +1,392 @@
 mod backend;
 mod common;
 mod frontend;
+mod stdlib;
 
 use std::{
     env::args,
-    fs::read_to_string,
-    io::{stdin, stdout, Write},
+    fs::{read, read_to_string, write, File},
+    io::{stderr, stdin, stdout, BufWriter, Write},
+    path::Path,
 };
 
 use backend::vm::VirtualMachine;
 use common::error::{Error, ErrorKind};
-use frontend::compiler::Compiler;
+use frontend::{checker::Checker, compiler::Compiler};
 
 use crate::{
-    backend::chunk::Chunk,
-    common::ast::Program,
+    backend::{
+        bytecode,
+        chunk::Chunk,
+        disassembler,
+        trace::{Trace, TraceFormat},
+    },
+    common::{ast::Program, interner::Interner},
     frontend::{compiler::SymbolTable, parser::Parser, scanner::Scanner},
+    stdlib::time::{ManualClock, SystemClock},
 };
 
 const COMMANDS: &str = "\
 @cmd                    : prints available commands.
+@clock [seconds]        : freeze the clock at given unix time, or restore the system clock.
 @exit, @e               : exit from the REPL.
 ";
 const USAGE: &str = "\
 Usage:
-indu                        : run the REPL.
-indu [file_path]    : execute given file.
+indu [options]              : run the REPL.
+indu [options] [file_path]  : execute given file, or a '.indc' file built by 'indu build'.
+indu check [file_path]      : check given file for type errors, without running it.
+indu build [file_path] [-o output_path]
+                            : compile given file to bytecode, by default next to it as '.indc'.
+indu disasm [file_path]     : print the bytecode of given file, or of a '.indc' file.
+
+Options:
+--seed [integer]            : seed the random module, for reproducible runs.
+--gc-stress                 : collect garbage before every instruction that follows an allocation,
+                              to test the collector.
+--max-stack [integer]       : how deeply calls can nest before a stack overflow, 10000 by default.
+--max-stack-values [integer]
+                            : how many values the stack can hold, 1000000 by default.
+--trace                     : print every executed instruction and the stack to stderr.
+--trace-file [file_path]    : write the trace to given file instead.
+--trace-json                : write the trace as JSON lines.
+--trace-function [name]     : only trace instructions of functions with given name.
+--trace-lines [first-last]  : only trace instructions compiled from given lines.
 ";
 
+#[derive(Default)]
+struct Options {
+    seed: Option<u64>,
+    gc_stress: bool,
+    max_stack: Option<usize>,
+    max_stack_values: Option<usize>,
+    trace: bool,
+    trace_file: Option<String>,
+    trace_json: bool,
+    trace_function: Option<String>,
+    trace_lines: Option<(usize, usize)>,
+}
+
+impl Options {
+    /// A virtual machine for running the script read from `file`.
+    fn virtual_machine(&self, file: &str) -> Result<VirtualMachine, Error> {
+        let mut vm = VirtualMachine::new();
+        vm.set_file(file.to_string());
+        if let Some(seed) = self.seed {
+            vm.random().seed(seed);
+        }
+        vm.heap().set_stress(self.gc_stress);
+        if let Some(max_stack) = self.max_stack {
+            vm.set_max_call_depth(max_stack);
+        }
+        if let Some(max_stack_values) = self.max_stack_values {
+            vm.set_max_stack_size(max_stack_values);
+        }
+        if self.trace {
+            vm.set_trace(Some(self.trace()?));
+        }
+        Ok(vm)
+    }
+
+    fn trace(&self) -> Result<Trace, Error> {
+        let output: Box<dyn Write> = match &self.trace_file {
+            Some(trace_path) => Box::new(BufWriter::new(File::create(trace_path).or(Err(
+                Error::new(
+                    ErrorKind::System,
+                    format!("Could not write file to '{trace_path}'."),
+                    None,
+                ),
+            ))?)),
+            None => Box::new(BufWriter::new(stderr())),
+        };
+        let format = if self.trace_json {
+            TraceFormat::Json
+        } else {
+            TraceFormat::Text
+        };
+        let mut trace = Trace::new(output, format);
+        if let Some(name) = &self.trace_function {
+            trace.set_function(name.clone());
+        }
+        if let Some((first, last)) = self.trace_lines {
+            trace.set_lines(first, last);
+        }
+        Ok(trace)
+    }
+}
+
+enum Command {
+    Run,
+    Check,
+    Build,
+    Disassemble,
+}
+
 pub fn start() {
     run().unwrap_or_else(|error| error.report());
 }
 
 fn run() -> Result<(), Error> {
-    let args: Vec<String> = args().collect();
+    let mut options = Options::default();
+    let mut command = Command::Run;
+    let mut source_path = None;
+    let mut output_path = None;
+
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let seed = args.next().and_then(|seed| seed.parse::<i64>().ok());
+                if let Some(seed) = seed {
+                    options.seed = Some(seed as u64);
+                } else {
+                    return Err(Error::new(
+                        ErrorKind::System,
+                        "Invalid option. '--seed' expects an integer.".to_string(),
+                        None,
+                    ));
+                }
+            }
+            "--gc-stress" => options.gc_stress = true,
+            "--max-stack" | "--max-stack-values" => {
+                let limit = args
+                    .next()
+                    .and_then(|limit| limit.parse().ok())
+                    .filter(|limit| *limit > 0);
+                let Some(limit) = limit else {
+                    return Err(Error::new(
+                        ErrorKind::System,
+                        format!("Invalid option. '{arg}' expects a positive integer."),
+                        None,
+                    ));
+                };
+                if arg == "--max-stack" {
+                    options.max_stack = Some(limit);
+                } else {
+                    options.max_stack_values = Some(limit);
+                }
+            }
+            "--trace" => options.trace = true,
+            "--trace-json" => {
+                options.trace = true;
+                options.trace_json = true;
+            }
+            "--trace-file" | "--trace-function" => {
+                let Some(value) = args.next() else {
+                    return Err(Error::new(
+                        ErrorKind::System,
+                        format!("Invalid option. '{arg}' expects a value."),
+                        None,
+                    ));
+                };
+                options.trace = true;
+                if arg == "--trace-file" {
+                    options.trace_file = Some(value);
+                } else {
+                    options.trace_function = Some(value);
+                }
+            }
+            "--trace-lines" => {
+                let lines = args.next().and_then(|lines| parse_lines(&lines));
+                if let Some(lines) = lines {
+                    options.trace = true;
+                    options.trace_lines = Some(lines);
+                } else {
+                    return Err(Error::new(
+                        ErrorKind::System,
+                        "Invalid option. '--trace-lines' expects a line or a range of lines, like '10-20'.".to_string(),
+                        None,
+                    ));
+                }
+            }
+            "-o" => {
+                if let Some(path) = args.next() {
+                    output_path = Some(path);
+                } else {
+                    return Err(Error::new(
+                        ErrorKind::System,
+                        "Invalid option. '-o' expects a file path.".to_string(),
+                        None,
+                    ));
+                }
+            }
+            "check" if source_path.is_none() => command = Command::Check,
+            "build" if source_path.is_none() => command = Command::Build,
+            "disasm" if source_path.is_none() => command = Command::Disassemble,
+            _ if !arg.starts_with("--") && source_path.is_none() => source_path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return Ok(());
+            }
+        }
+    }
 
-    match args.len() {
-        1 => run_repl(),
-        2 => run_file(&args[1]),
-        _ => {
+    match (command, source_path) {
+        (Command::Run, Some(source_path)) => run_file(&source_path, &options),
+        (Command::Run, None) => run_repl(&options),
+        (Command::Check, Some(source_path)) => check_file(&source_path),
+        (Command::Build, Some(source_path)) => {
+            let output_path = output_path.unwrap_or_else(|| {
+                let path = Path::new(&source_path).with_extension("indc");
+                path.to_string_lossy().into_owned()
+            });
+            build_file(&source_path, &output_path)
+        }
+        (Command::Disassemble, Some(source_path)) => disassemble_file(&source_path),
+        (Command::Check | Command::Build | Command::Disassemble, None) => {
             eprintln!("{USAGE}");
             Ok(())
         }
     }
 }
 
-fn run_file(source_path: &str) -> Result<(), Error> {
-    if let Ok(source) = read_to_string(source_path) {
-        run_source(&source).unwrap_or_else(|error| error.report());
-        Ok(())
+/// Parses `10` or `10-20` into the first and last line.
+fn parse_lines(lines: &str) -> Option<(usize, usize)> {
+    let (first, last) = lines.split_once('-').unwrap_or((lines, lines));
+    let (first, last) = (first.parse().ok()?, last.parse().ok()?);
+    if first == 0 || first > last {
+        return None;
+    }
+    Some((first, last))
+}
+
+fn read_source(source_path: &str) -> Result<String, Error> {
+    read_to_string(source_path).or(Err(Error::new(
+        ErrorKind::System,
+        format!("Could not read file from '{source_path}'."),
+        None,
+    )))
+}
+
+fn run_file(source_path: &str, options: &Options) -> Result<(), Error> {
+    if Path::new(source_path).extension() == Some("indc".as_ref()) {
+        return run_bytecode(source_path, options);
+    }
+    let source = read_source(source_path)?;
+    run_source(&source, source_path, options).unwrap_or_else(|error| error.report());
+    Ok(())
+}
+
+fn run_bytecode(bytecode_path: &str, options: &Options) -> Result<(), Error> {
+    let mut vm = options.virtual_machine(bytecode_path)?;
+    let chunk = read_bytecode(bytecode_path, &vm.interner())?;
+    vm.interpret(chunk).unwrap_or_else(|error| error.report());
+    Ok(())
+}
+
+fn read_bytecode(bytecode_path: &str, interner: &Interner) -> Result<Chunk, Error> {
+    let bytes = read(bytecode_path).or(Err(Error::new(
+        ErrorKind::System,
+        format!("Could not read file from '{bytecode_path}'."),
+        None,
+    )))?;
+    bytecode::read_chunk(&bytes, interner)
+        .map_err(|message| Error::new(ErrorKind::System, message, None))
+}
+
+/// Compiles a file without running it, so it can be run later without compiling it again.
+fn build_file(source_path: &str, output_path: &str) -> Result<(), Error> {
+    let source = read_source(source_path)?;
+    let chunk = match compile_source(&source) {
+        Ok(chunk) => chunk,
+        Err(error) => {
+            error.report();
+            return Ok(());
+        }
+    };
+    write(output_path, bytecode::write_chunk(&chunk)).or(Err(Error::new(
+        ErrorKind::System,
+        format!("Could not write file to '{output_path}'."),
+        None,
+    )))?;
+    println!("Built '{output_path}' from '{source_path}'.");
+    Ok(())
+}
+
+/// Prints the bytecode a file compiles to, or that a built file holds, without running it.
+fn disassemble_file(source_path: &str) -> Result<(), Error> {
+    let chunk = if Path::new(source_path).extension() == Some("indc".as_ref()) {
+        read_bytecode(source_path, &Interner::default())?
     } else {
-        Err(Error::new(
-            ErrorKind::System,
-            format!("Could not read file from '{source_path}'."),
-            None,
-        ))
+        match compile_source(&read_source(source_path)?) {
+            Ok(chunk) => chunk,
+            Err(error) => {
+                error.report();
+                return Ok(());
+            }
+        }
+    };
+    print!("{}", disassembler::disassemble(&chunk));
+    Ok(())
+}
+
+fn compile_source(source: &str) -> Result<Chunk, Error> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan()?;
+
+    let mut parser = Parser::new(tokens, source);
+    let program = parser.parse()?;
+
+    Compiler::new(None, Interner::default()).compile(program)
+}
+
+fn check_file(source_path: &str) -> Result<(), Error> {
+    let source = read_source(source_path)?;
+    match check_source(&source) {
+        Ok(errors) if errors.is_empty() => println!("No problems found in '{source_path}'."),
+        Ok(errors) => errors.iter().for_each(Error::report),
+        Err(error) => error.report(),
     }
+    Ok(())
 }
 
-fn run_source(source: &str) -> Result<(), Error> {
-    let mut scanner = Scanner::new(&source);
+/// Runs every stage but the virtual machine. Type errors are all collected, while the
+/// other stages stop at their first error.
+fn check_source(source: &str) -> Result<Vec<Error>, Error> {
+    let mut scanner = Scanner::new(source);
     let tokens = scanner.scan()?;
 
-    let mut parser = Parser::new(tokens);
+    let mut parser = Parser::new(tokens, source);
     let program = parser.parse()?;
 
-    let mut compiler = Compiler::new(None);
-    let chunk = compiler.compile(program)?;
+    let errors = Checker::new().check(&program);
+    if errors.is_empty() {
+        Compiler::new(None, Interner::default()).compile(program)?;
+    }
+
+    Ok(errors)
+}
 
-    let mut vm = VirtualMachine::new();
+fn run_source(source: &str, source_path: &str, options: &Options) -> Result<(), Error> {
+    let mut scanner = Scanner::new(source);
+    let tokens = scanner.scan()?;
+
+    let mut parser = Parser::new(tokens, source);
+    let program = parser.parse()?;
+
+    let mut vm = options.virtual_machine(source_path)?;
+    let mut compiler = Compiler::new(None, vm.interner());
+    let chunk = compiler.compile(program)?;
     vm.interpret(chunk)?;
 
     Ok(())
 }
 
-fn run_repl() -> Result<(), Error> {
+/// Runs `source` on `vm`, so tests can look at what it leaves behind.
+#[cfg(test)]
+fn run_script(vm: &mut VirtualMachine, source: &str) -> Result<(), Error> {
+    let tokens = Scanner::new(source).scan()?;
+    let program = Parser::new(tokens, source).parse()?;
+    let chunk = Compiler::new(None, vm.interner()).compile(program)?;
+    vm.interpret(chunk)
+}
+
+fn run_repl(options: &Options) -> Result<(), Error> {
     println!("Welcome to Indu REPL.\nType '@cmd' to see available commands.\n");
 
     let mut line = String::new();
-    let mut bindings = vec![SymbolTable::default()];
+    let mut globals = None::<SymbolTable>;
+    let mut vm = options.virtual_machine("<repl>")?;
 
     loop {
         print!("|> ");
@@ -100,6 +408,19 @@ fn run_repl() -> Result<(), Error> {
                     println!("Exiting Indu REPL.");
                     break;
                 }
+                "@clock" => vm.set_clock(Box::new(SystemClock::new())),
+                command if command.starts_with("@clock ") => {
+                    if let Ok(seconds) = command["@clock ".len()..].trim().parse::<f64>() {
+                        vm.set_clock(Box::new(ManualClock::new(seconds)));
+                    } else {
+                        Error::new(
+                            ErrorKind::System,
+                            format!("Invalid command. '{command}' expects a number of seconds."),
+                            None,
+                        )
+                        .report();
+                    }
+                }
                 command => Error::new(
                     ErrorKind::System,
                     format!("Invalid command. '{command}' is not a known command."),
@@ -108,35 +429,71 @@ fn run_repl() -> Result<(), Error> {
                 .report(),
             }
 
+            line.clear();
             continue;
         }
 
-        let mut scanner = Scanner::new(line.trim());
+        let source = line.trim();
+        let mut scanner = Scanner::new(source);
         let tokens = scanner.scan().unwrap_or_else(|error| {
             error.report();
             Vec::new()
         });
 
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::new(tokens, source);
         let program = parser.parse().unwrap_or_else(|error| {
             error.report();
             Program::new()
         });
 
-        let mut compiler = Compiler::new(Some(bindings.clone()));
-        let chunk = compiler.compile(program).unwrap_or_else(|error| {
-            error.report();
-            Chunk::new()
-        });
-
-        let mut vm = VirtualMachine::new();
-        vm.interpret(chunk).unwrap_or_else(|error| {
-            error.report();
-        });
+        let mut compiler = Compiler::new(globals.clone(), vm.interner());
+        match compiler.compile(program) {
+            Ok(chunk) => {
+                vm.interpret(chunk).unwrap_or_else(|error| {
+                    error.report();
+                });
+                globals = Some(compiler.get_globals());
+            }
+            Err(error) => error.report(),
+        }
 
         line.clear();
-        bindings = compiler.get_bindings();
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{run_script, Options};
+
+    const RECURSION: &str = "fun down(n) { return down(n + 1) }\ndown(0)\n";
+
+    #[test]
+    fn limits_how_deeply_calls_nest() {
+        let options = Options {
+            max_stack: Some(50),
+            ..Options::default()
+        };
+        let mut vm = options.virtual_machine("<input>").unwrap();
+        let error = run_script(&mut vm, RECURSION).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Stack overflow. Calling 'down' nests calls deeper than 50."
+        );
+    }
+
+    #[test]
+    fn limits_how_many_values_the_stack_holds() {
+        let options = Options {
+            max_stack_values: Some(30),
+            ..Options::default()
+        };
+        let mut vm = options.virtual_machine("<input>").unwrap();
+        let error = run_script(&mut vm, RECURSION).unwrap_err();
+        assert_eq!(
+            error.message,
+            "Stack overflow. The stack holds more than 30 values."
+        );
+    }
+}
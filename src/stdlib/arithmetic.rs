@@ -0,0 +1,32 @@
+use crate::{
+    backend::vm::VirtualMachine,
+    common::{
+        bigint::BigInt,
+        object::{NativeFunction, Object},
+    },
+};
+
+pub(crate) fn functions() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("div".to_string(), 2, div)]
+}
+
+/// Divides integers, truncating the quotient towards zero, so `div(a, b) * b + a % b` is
+/// always `a`.
+fn div(_vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let a = expect_big_integer(&arguments[0])?;
+    let b = expect_big_integer(&arguments[1])?;
+    let (quotient, _) = a.divide(&b).ok_or_else(|| {
+        format!(
+            "Division by zero. Can not divide '{}' by '0'.",
+            arguments[0]
+        )
+    })?;
+    Ok(Object::integer(quotient))
+}
+
+/// Unlike `stdlib::expect_integer`, takes integers of any size.
+fn expect_big_integer(object: &Object) -> Result<BigInt, String> {
+    object
+        .as_integer()
+        .ok_or_else(|| format!("Invalid argument. 'div' expects an integer, found '{object}'."))
+}
@@ -0,0 +1,56 @@
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    backend::vm::VirtualMachine,
+    common::object::{Channel, NativeFunction, Object},
+};
+
+pub(crate) fn functions() -> Vec<NativeFunction> {
+    vec![
+        NativeFunction::new("chan".to_string(), 0, chan),
+        NativeFunction::new("send".to_string(), 2, send),
+        NativeFunction::new("recv".to_string(), 1, recv),
+    ]
+}
+
+fn chan(_: &mut VirtualMachine, _: Vec<Object>) -> Result<Object, String> {
+    Ok(Object::Channel(Rc::new(RefCell::new(Channel::default()))))
+}
+
+fn send(vm: &mut VirtualMachine, mut arguments: Vec<Object>) -> Result<Object, String> {
+    let value = arguments.pop().unwrap();
+    let channel = expect_channel("send", &arguments[0])?;
+    let mut channel = channel.borrow_mut();
+    if channel.value.is_some() {
+        vm.block_fiber();
+    } else {
+        channel.value = Some(value);
+        vm.wake_fibers();
+    }
+    Ok(Object::Nil)
+}
+
+fn recv(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let channel = expect_channel("recv", &arguments[0])?;
+    let value = channel.borrow_mut().value.take();
+    if let Some(value) = value {
+        vm.wake_fibers();
+        Ok(value)
+    } else {
+        vm.block_fiber();
+        Ok(Object::Nil)
+    }
+}
+
+fn expect_channel<'a>(
+    function_name: &str,
+    object: &'a Object,
+) -> Result<&'a Rc<RefCell<Channel>>, String> {
+    if let Object::Channel(channel) = object {
+        Ok(channel)
+    } else {
+        Err(format!(
+            "Invalid argument. '{function_name}' expects a channel, found '{object}'."
+        ))
+    }
+}
@@ -0,0 +1,15 @@
+use crate::{
+    backend::vm::VirtualMachine,
+    common::object::{NativeFunction, Object},
+};
+
+pub(crate) fn functions() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("str".to_string(), 1, str)]
+}
+
+/// Converts a value to the string `print` would show for it.
+fn str(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    vm.stringify(&arguments[0])
+        .map(|string| Object::String(string.into()))
+        .map_err(|error| error.message)
+}
@@ -0,0 +1,79 @@
+pub(crate) mod arithmetic;
+pub(crate) mod channel;
+pub(crate) mod conversion;
+pub(crate) mod random;
+pub(crate) mod re;
+pub(crate) mod time;
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    backend::heap::Gc,
+    common::object::{Module, Object},
+};
+
+pub(crate) fn modules() -> Vec<Module> {
+    vec![random::module(), re::module(), time::module()]
+}
+
+/// Everything a script can use without declaring it: the modules and the builtin functions.
+pub(crate) fn globals() -> Vec<(String, Object)> {
+    let modules = modules()
+        .into_iter()
+        .map(|module| (module.name.clone(), Object::Module(Rc::new(module))));
+    let functions = arithmetic::functions()
+        .into_iter()
+        .chain(channel::functions())
+        .chain(conversion::functions())
+        .map(|function| (function.name.clone(), Object::NativeFunction(function)));
+    modules.chain(functions).collect()
+}
+
+pub(crate) fn expect_number(function_name: &str, object: &Object) -> Result<f64, String> {
+    if let Some(number) = object.as_number() {
+        Ok(number)
+    } else {
+        Err(format!(
+            "Invalid argument. '{function_name}' expects a number, found '{object}'."
+        ))
+    }
+}
+
+pub(crate) fn expect_integer(function_name: &str, object: &Object) -> Result<i64, String> {
+    match object {
+        Object::Number(number)
+            if number.fract() == 0. && number.abs() <= 9_007_199_254_740_992. =>
+        {
+            Ok(*number as i64)
+        }
+        _ => Err(format!(
+            "Invalid argument. '{function_name}' expects an integer, found '{object}'."
+        )),
+    }
+}
+
+pub(crate) fn expect_list(
+    function_name: &str,
+    object: &Object,
+) -> Result<Gc<RefCell<Vec<Object>>>, String> {
+    if let Object::List(list) = object {
+        Ok(*list)
+    } else {
+        Err(format!(
+            "Invalid argument. '{function_name}' expects a list, found '{object}'."
+        ))
+    }
+}
+
+pub(crate) fn expect_string<'a>(
+    function_name: &str,
+    object: &'a Object,
+) -> Result<&'a str, String> {
+    if let Object::String(string) = object {
+        Ok(string.as_str())
+    } else {
+        Err(format!(
+            "Invalid argument. '{function_name}' expects a string, found '{object}'."
+        ))
+    }
+}
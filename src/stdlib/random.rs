@@ -0,0 +1,157 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{
+    backend::vm::VirtualMachine,
+    common::object::{Module, Object},
+};
+
+use super::{expect_integer, expect_list};
+
+/// A xoshiro256** generator, seeded through splitmix64. Every virtual machine owns one, so
+/// a seeded run produces the same sequence on every machine.
+pub(crate) struct Random {
+    state: [u64; 4],
+}
+
+impl Random {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut random = Self { state: [0; 4] };
+        random.seed(seed);
+        random
+    }
+
+    pub(crate) fn from_entropy() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or(0);
+        Self::new(nanos)
+    }
+
+    pub(crate) fn seed(&mut self, seed: u64) {
+        let mut seed = seed;
+        for word in &mut self.state {
+            seed = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            *word = z ^ (z >> 31);
+        }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    /// A float uniformly distributed in `[0, 1)`.
+    pub(crate) fn next_float(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer uniformly distributed in `[0, bound)`, without modulo bias.
+    pub(crate) fn next_below(&mut self, bound: u64) -> u64 {
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < limit {
+                return value % bound;
+            }
+        }
+    }
+}
+
+pub(crate) fn module() -> Module {
+    Module::new(
+        "random",
+        &[
+            ("seed", 1, seed),
+            ("float", 0, float),
+            ("int", 2, int),
+            ("choice", 1, choice),
+            ("shuffle", 1, shuffle),
+        ],
+    )
+}
+
+fn seed(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let seed = expect_integer("random.seed", &arguments[0])?;
+    vm.random().seed(seed as u64);
+    Ok(Object::Nil)
+}
+
+fn float(vm: &mut VirtualMachine, _: Vec<Object>) -> Result<Object, String> {
+    Ok(Object::Number(vm.random().next_float()))
+}
+
+fn int(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let low = expect_integer("random.int", &arguments[0])?;
+    let high = expect_integer("random.int", &arguments[1])?;
+    if low > high {
+        return Err(format!(
+            "Invalid range. Lower bound '{low}' is greater than upper bound '{high}'."
+        ));
+    }
+    let offset = vm.random().next_below(high.abs_diff(low) + 1);
+    Ok(Object::Number((low as i128 + offset as i128) as f64))
+}
+
+fn choice(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let list = expect_list("random.choice", &arguments[0])?;
+    let list = list.borrow();
+    if list.is_empty() {
+        return Err("Invalid argument. Can not choose from an empty list.".to_string());
+    }
+    let index = vm.random().next_below(list.len() as u64) as usize;
+    Ok(list[index].clone())
+}
+
+fn shuffle(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let list = expect_list("random.shuffle", &arguments[0])?;
+    let mut list = list.borrow_mut();
+    for i in (1..list.len()).rev() {
+        let j = vm.random().next_below(i as u64 + 1) as usize;
+        list.swap(i, j);
+    }
+    Ok(Object::Nil)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    const SOURCE: &str = "var dice = [1, 2, 3, 4, 5, 6]\n\
+                          random.shuffle(dice)\n\
+                          var rolls = [random.int(1, 100), random.choice(dice), random.float()]\n";
+
+    fn run(seed: Option<u64>) -> (Option<String>, Option<String>) {
+        let mut vm = VirtualMachine::new();
+        if let Some(seed) = seed {
+            vm.random().seed(seed);
+        }
+        run_script(&mut vm, SOURCE).unwrap();
+        (vm.global("dice"), vm.global("rolls"))
+    }
+
+    #[test]
+    fn repeats_itself_for_the_same_seed() {
+        assert_eq!(run(Some(42)), run(Some(42)));
+        assert_ne!(run(Some(42)), run(Some(43)));
+    }
+
+    #[test]
+    fn can_be_seeded_from_a_script() {
+        let mut vm = VirtualMachine::new();
+        let source = "random.seed(7)\nvar first = random.float()\n\
+                      random.seed(7)\nvar second = random.float()\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("first"), vm.global("second"));
+    }
+}
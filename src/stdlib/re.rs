@@ -0,0 +1,202 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+use crate::{
+    backend::vm::VirtualMachine,
+    common::{
+        object::{Match, Module, Object},
+        regex::{Captures, Regex},
+    },
+};
+
+use super::expect_string;
+
+pub(crate) fn module() -> Module {
+    Module::new(
+        "re",
+        &[
+            ("compile", 1, compile),
+            ("match", 2, match_pattern),
+            ("find_all", 2, find_all),
+            ("replace", 3, replace),
+        ],
+    )
+}
+
+/// Every function takes either a pattern or a pattern compiled by `re.compile`, which
+/// skips compiling it again on each call.
+fn expect_regex(function_name: &str, object: &Object) -> Result<Rc<Regex>, String> {
+    match object {
+        Object::Regex(regex) => Ok(regex.clone()),
+        Object::String(pattern) => Ok(Rc::new(Regex::new(pattern)?)),
+        _ => Err(format!(
+            "Invalid argument. '{function_name}' expects a pattern, found '{object}'."
+        )),
+    }
+}
+
+fn compile(_: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    Ok(Object::Regex(expect_regex("re.compile", &arguments[0])?))
+}
+
+/// The match at the start of the text, or nil. Use `re.find_all` to search further in.
+fn match_pattern(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let regex = expect_regex("re.match", &arguments[0])?;
+    let text: Vec<char> = expect_string("re.match", &arguments[1])?.chars().collect();
+    Ok(match regex.match_at(&text, 0) {
+        Some(captures) => match_object(vm, &regex, &text, &captures),
+        None => Object::Nil,
+    })
+}
+
+/// Every match that does not overlap an earlier one.
+fn find_all(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let regex = expect_regex("re.find_all", &arguments[0])?;
+    let text: Vec<char> = expect_string("re.find_all", &arguments[1])?
+        .chars()
+        .collect();
+    let matches = matches(&regex, &text)
+        .iter()
+        .map(|captures| match_object(vm, &regex, &text, captures))
+        .collect();
+    Ok(Object::List(vm.heap().allocate(RefCell::new(matches))))
+}
+
+/// Replaces every match. In the replacement, `$0` is the matched text, `$1` and on are its
+/// groups and `$$` is a dollar sign.
+fn replace(_: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let regex = expect_regex("re.replace", &arguments[0])?;
+    let text: Vec<char> = expect_string("re.replace", &arguments[1])?
+        .chars()
+        .collect();
+    let replacement: Vec<char> = expect_string("re.replace", &arguments[2])?
+        .chars()
+        .collect();
+
+    let mut replaced = String::new();
+    let mut end = 0;
+    for captures in matches(&regex, &text) {
+        let (match_start, match_end) = captures[0].expect("A match always has a span.");
+        replaced.extend(&text[end..match_start]);
+        let mut i = 0;
+        while i < replacement.len() {
+            let character = replacement[i];
+            i += 1;
+            if character != '$' {
+                replaced.push(character);
+                continue;
+            }
+            if replacement.get(i) == Some(&'$') {
+                replaced.push('$');
+                i += 1;
+                continue;
+            }
+            let digits: String = replacement[i..]
+                .iter()
+                .take_while(|character| character.is_ascii_digit())
+                .collect();
+            if digits.is_empty() {
+                return Err(
+                    "Invalid replacement. Expected a group number or '$' after '$'.".to_string(),
+                );
+            }
+            i += digits.len();
+            let group: usize = digits
+                .parse()
+                .ok()
+                .filter(|group| *group <= regex.groups)
+                .ok_or_else(|| {
+                    format!(
+                        "Invalid replacement. '${digits}' refers to a group that '{}' does not have.",
+                        regex.pattern
+                    )
+                })?;
+            if let Some((start, end)) = captures[group] {
+                replaced.extend(&text[start..end]);
+            }
+        }
+        end = match_end;
+    }
+    replaced.extend(&text[end..]);
+    Ok(Object::String(replaced.into()))
+}
+
+fn matches(regex: &Regex, text: &[char]) -> Vec<Captures> {
+    let mut matches = Vec::new();
+    let mut start = 0;
+    while let Some(captures) = regex.find_at(text, start) {
+        let (match_start, match_end) = captures[0].expect("A match always has a span.");
+        // An empty match would be found again at the same place, so the search moves on.
+        start = if match_end == match_start {
+            match_end + 1
+        } else {
+            match_end
+        };
+        matches.push(captures);
+        if start > text.len() {
+            break;
+        }
+    }
+    matches
+}
+
+fn match_object(
+    vm: &mut VirtualMachine,
+    regex: &Regex,
+    text: &[char],
+    captures: &Captures,
+) -> Object {
+    let (start, end) = captures[0].expect("A match always has a span.");
+    let matched: String = text[start..end].iter().collect();
+    let groups = captures[1..]
+        .iter()
+        .map(|span| substring(text, *span))
+        .collect();
+
+    let mut properties = HashMap::from([
+        ("text".to_string(), Object::String(matched.as_str().into())),
+        ("start".to_string(), Object::Number(start as f64)),
+        ("end".to_string(), Object::Number(end as f64)),
+        (
+            "groups".to_string(),
+            Object::List(vm.heap().allocate(RefCell::new(groups))),
+        ),
+    ]);
+    for (name, group) in &regex.names {
+        properties.insert(name.clone(), substring(text, captures[*group]));
+    }
+    Object::Match(Rc::new(Match::new(matched, properties)))
+}
+
+fn substring(text: &[char], span: Option<(usize, usize)>) -> Object {
+    match span {
+        Some((start, end)) => Object::String(text[start..end].iter().collect::<String>().into()),
+        None => Object::Nil,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    #[test]
+    fn match_only_looks_at_the_start() {
+        let mut vm = VirtualMachine::new();
+        let source = "var found = re.match(\"(?<word>\\w+)\", \"hello world\")\n\
+                      var word = found.word\n\
+                      var missing = re.match(\"world\", \"hello world\")\n";
+        run_script(&mut vm, source).unwrap();
+        assert_eq!(vm.global("word").as_deref(), Some("hello"));
+        assert_eq!(vm.global("missing").as_deref(), Some("nil"));
+    }
+
+    #[test]
+    fn rejects_group_names_that_are_match_properties() {
+        for name in ["text", "start", "end", "groups"] {
+            let source = format!("re.compile(\"(?<{name}>a)\")\n");
+            let error = run_script(&mut VirtualMachine::new(), &source).unwrap_err();
+            assert!(error.message.contains(&format!(
+                "names a group '{name}', which is already a property of matches"
+            )));
+        }
+    }
+}
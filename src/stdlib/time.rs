@@ -0,0 +1,251 @@
+use std::{
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    backend::vm::VirtualMachine,
+    common::object::{Module, Object},
+};
+
+use super::{expect_number, expect_string};
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Source of time for the `time` module. The virtual machine reads every timestamp through
+/// this trait, so a `ManualClock` can be installed to make scripts deterministic.
+pub(crate) trait Clock {
+    /// Seconds since the unix epoch.
+    fn now(&self) -> f64;
+    /// Seconds since an arbitrary, fixed point. Never goes backwards.
+    fn monotonic(&self) -> f64;
+    fn sleep(&mut self, seconds: f64);
+}
+
+pub(crate) struct SystemClock {
+    origin: Instant,
+}
+
+impl SystemClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or(0.)
+    }
+
+    fn monotonic(&self) -> f64 {
+        self.origin.elapsed().as_secs_f64()
+    }
+
+    fn sleep(&mut self, seconds: f64) {
+        sleep(Duration::from_secs_f64(seconds));
+    }
+}
+
+/// A clock that only moves when slept on.
+pub(crate) struct ManualClock {
+    now: f64,
+    monotonic: f64,
+}
+
+impl ManualClock {
+    pub(crate) fn new(now: f64) -> Self {
+        Self { now, monotonic: 0. }
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> f64 {
+        self.now
+    }
+
+    fn monotonic(&self) -> f64 {
+        self.monotonic
+    }
+
+    fn sleep(&mut self, seconds: f64) {
+        self.now += seconds;
+        self.monotonic += seconds;
+    }
+}
+
+pub(crate) fn module() -> Module {
+    Module::new(
+        "time",
+        &[
+            ("now", 0, now),
+            ("monotonic", 0, monotonic),
+            ("sleep", 1, sleep_for),
+            ("format", 2, format),
+        ],
+    )
+}
+
+fn now(vm: &mut VirtualMachine, _: Vec<Object>) -> Result<Object, String> {
+    Ok(Object::Number(vm.clock().now()))
+}
+
+fn monotonic(vm: &mut VirtualMachine, _: Vec<Object>) -> Result<Object, String> {
+    Ok(Object::Number(vm.clock().monotonic()))
+}
+
+fn sleep_for(vm: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let seconds = expect_number("time.sleep", &arguments[0])?;
+    // Anything a `Duration` can not hold is rejected, so no clock is asked to sleep for it.
+    if Duration::try_from_secs_f64(seconds).is_err() {
+        return Err(format!(
+            "Invalid argument. Can not sleep for '{seconds}' seconds."
+        ));
+    }
+    vm.clock().sleep(seconds);
+    Ok(Object::Nil)
+}
+
+fn format(_: &mut VirtualMachine, arguments: Vec<Object>) -> Result<Object, String> {
+    let timestamp = expect_number("time.format", &arguments[0])?;
+    let pattern = expect_string("time.format", &arguments[1])?;
+    if !timestamp.is_finite() {
+        return Err(format!(
+            "Invalid argument. '{timestamp}' is not a valid timestamp."
+        ));
+    }
+
+    let seconds = timestamp.floor() as i64;
+    let microseconds = ((timestamp - timestamp.floor()) * 1_000_000.) as i64;
+    let days = seconds.div_euclid(86_400);
+    let seconds_of_day = seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let (hour, minute, second) = (
+        seconds_of_day / 3600,
+        seconds_of_day % 3600 / 60,
+        seconds_of_day % 60,
+    );
+    let weekday = (days + 4).rem_euclid(7) as usize;
+    let day_of_year = days - days_from_civil(year, 1, 1) + 1;
+
+    let mut formatted = String::new();
+    let mut characters = pattern.chars();
+    while let Some(character) = characters.next() {
+        if character != '%' {
+            formatted.push(character);
+            continue;
+        }
+        match characters.next() {
+            Some('Y') => formatted.push_str(&format!("{year:04}")),
+            Some('y') => formatted.push_str(&format!("{:02}", year.rem_euclid(100))),
+            Some('m') => formatted.push_str(&format!("{month:02}")),
+            Some('d') => formatted.push_str(&format!("{day:02}")),
+            Some('j') => formatted.push_str(&format!("{day_of_year:03}")),
+            Some('H') => formatted.push_str(&format!("{hour:02}")),
+            Some('I') => formatted.push_str(&format!("{:02}", (hour + 11) % 12 + 1)),
+            Some('p') => formatted.push_str(if hour < 12 { "AM" } else { "PM" }),
+            Some('M') => formatted.push_str(&format!("{minute:02}")),
+            Some('S') => formatted.push_str(&format!("{second:02}")),
+            Some('f') => formatted.push_str(&format!("{microseconds:06}")),
+            Some('s') => formatted.push_str(&seconds.to_string()),
+            Some('a') => formatted.push_str(&WEEKDAYS[weekday][..3]),
+            Some('A') => formatted.push_str(WEEKDAYS[weekday]),
+            Some('b') => formatted.push_str(&MONTHS[month as usize - 1][..3]),
+            Some('B') => formatted.push_str(MONTHS[month as usize - 1]),
+            Some('%') => formatted.push('%'),
+            Some(specifier) => {
+                return Err(format!(
+                    "Invalid format. '%{specifier}' is not a known format specifier."
+                ))
+            }
+            None => {
+                return Err("Invalid format. Expected a specifier after '%'.".to_string());
+            }
+        }
+    }
+
+    Ok(Object::String(formatted.into()))
+}
+
+/// Converts days since the unix epoch into a proleptic gregorian `(year, month, day)` in UTC.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{backend::vm::VirtualMachine, run_script};
+
+    use super::ManualClock;
+
+    #[test]
+    fn reads_the_installed_clock() {
+        let mut vm = VirtualMachine::new();
+        // 2024-02-29 23:59:30 UTC, a Thursday.
+        vm.set_clock(Box::new(ManualClock::new(1_709_251_170.)));
+        let source = "var before = time.now()\n\
+                      time.sleep(45)\n\
+                      var after = time.now()\n\
+                      var elapsed = time.monotonic()\n\
+                      var text = time.format(after, \"%a %Y-%m-%d %H:%M:%S\")\n";
+        run_script(&mut vm, source).unwrap();
+
+        assert_eq!(vm.global("before"), Some("1709251170".to_string()));
+        assert_eq!(vm.global("after"), Some("1709251215".to_string()));
+        assert_eq!(vm.global("elapsed"), Some("45".to_string()));
+        assert_eq!(
+            vm.global("text"),
+            Some("Fri 2024-03-01 00:00:15".to_string())
+        );
+    }
+}